@@ -0,0 +1,172 @@
+//! Command line parsing
+//!
+//! Arguments are positional words (e.g. `session get alice`) followed or interleaved with `--name value` options.
+//! Options can be repeated (e.g. `--role admin --role user`).
+
+use crate::Result;
+use rocket::http::uri::Uri;
+
+pub struct Args {
+    /// Print raw JSON instead of tables
+    pub json: bool,
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut json = false;
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some("json") => json = true,
+                Some(name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for --{}", name))?;
+                    options.push((name.to_string(), value));
+                }
+                None => positional.push(arg),
+            }
+        }
+        Ok(Args {
+            json,
+            positional,
+            options,
+        })
+    }
+
+    /// The `index`th positional argument, if any
+    pub fn word(&self, index: usize) -> Option<&str> {
+        self.positional.get(index).map(String::as_str)
+    }
+
+    /// The `index`th positional argument, named `name` in errors
+    pub fn required_word(&self, index: usize, name: &str) -> Result<&str> {
+        self.word(index)
+            .ok_or_else(|| format!("missing <{}>", name).into())
+    }
+
+    /// The last value of option `name`
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.values(name).last().copied()
+    }
+
+    pub fn required_option(&self, name: &str) -> Result<&str> {
+        self.option(name)
+            .ok_or_else(|| format!("missing --{}", name).into())
+    }
+
+    /// All values of option `name`
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Fails if some options are not part of `allowed` or if there are more than `words` positional arguments
+    pub fn check(&self, words: usize, allowed: &[&str]) -> Result<()> {
+        if let Some(word) = self.positional.get(words) {
+            return Err(format!("unexpected argument {}", word).into());
+        }
+        match self
+            .options
+            .iter()
+            .find(|(name, _)| !allowed.contains(&name.as_str()))
+        {
+            Some((name, _)) => Err(format!("unknown option --{}", name).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Options, as an URL encoded query string
+    pub fn query(&self) -> String {
+        self.options
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, Uri::percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn words_and_options_can_be_interleaved() {
+        let args = parse(&[
+            "session",
+            "create",
+            "--template",
+            "node",
+            "alice",
+            "--json",
+            "--duration",
+            "30",
+        ])
+        .unwrap();
+        assert!(args.json);
+        assert_eq!(args.word(0), Some("session"));
+        assert_eq!(args.word(2), Some("alice"));
+        assert_eq!(args.word(3), None);
+        assert_eq!(args.option("template"), Some("node"));
+        assert_eq!(args.required_option("duration").unwrap(), "30");
+        assert!(args.required_option("pool").is_err());
+        assert!(args.required_word(3, "id").is_err());
+    }
+
+    #[test]
+    fn repeated_options_are_all_kept() {
+        let args = parse(&[
+            "user", "update", "alice", "--role", "admin", "--role", "user",
+        ])
+        .unwrap();
+        assert_eq!(args.values("role"), vec!["admin", "user"]);
+        assert_eq!(args.option("role"), Some("user"));
+        assert!(args.values("pool").is_empty());
+    }
+
+    #[test]
+    fn options_without_value_are_rejected() {
+        let err = parse(&["session", "list", "--pool"]).err().unwrap();
+        assert_eq!(err.to_string(), "missing value for --pool");
+    }
+
+    #[test]
+    fn unexpected_words_and_options_are_rejected() {
+        let args = parse(&["session", "get", "alice", "--pool", "default"]).unwrap();
+        assert!(args.check(3, &["pool"]).is_ok());
+        assert_eq!(
+            args.check(2, &["pool"]).err().unwrap().to_string(),
+            "unexpected argument alice"
+        );
+        assert_eq!(
+            args.check(3, &[]).err().unwrap().to_string(),
+            "unknown option --pool"
+        );
+    }
+
+    #[test]
+    fn options_are_encoded_as_query() {
+        let args = parse(&[
+            "session",
+            "list",
+            "--template",
+            "a b&c/d",
+            "--limit",
+            "5",
+            "--json",
+        ])
+        .unwrap();
+        assert_eq!(args.query(), "template=a%20b%26c%2Fd&limit=5");
+        assert_eq!(parse(&["session", "list"]).unwrap().query(), "");
+    }
+}
@@ -0,0 +1,184 @@
+//! Direct access to kubernetes via a `Manager`, when run inside the backend pod
+//!
+//! Actions are performed on behalf of `ADMIN_ACTOR`, granted all permissions and no quota. As for the API,
+//! they are recorded in the audit log and finished sessions in the session history. Both are files local to
+//! the backend pod, so this is refused elsewhere.
+
+use crate::{Backend, Result};
+use async_trait::async_trait;
+use playground::{
+    audit::AuditFilter,
+    listing::{PoolQuery, SessionQuery, UserQuery},
+    logging,
+    manager::Manager,
+    types::{
+        LoggedUser, Permission, Quota, SessionConfiguration, SessionUpdateConfiguration, Template,
+        UserConfiguration, UserUpdateConfiguration, ADMIN_ROLE,
+    },
+};
+use rocket::request::{FormItems, FromForm};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeSet, env, fmt::Debug, io};
+use tokio::task::spawn_blocking;
+
+/// Actor of actions performed via this CLI
+pub const ADMIN_ACTOR: &str = "playground-admin";
+
+pub struct ClusterBackend {
+    manager: Manager,
+    admin: LoggedUser,
+}
+
+impl ClusterBackend {
+    pub async fn new() -> Result<Self> {
+        // Only set in the backend pod, see `backend-api-deployment.yaml`
+        if env::var("AUDIT_LOG_PATH").is_err() {
+            return Err(
+                "PLAYGROUND_URL must be set when not running inside the backend pod".into(),
+            );
+        }
+        // Logs would otherwise be mixed with results
+        logging::init_with_writer(io::stderr)?;
+        Ok(ClusterBackend {
+            manager: Manager::new().await?,
+            admin: LoggedUser {
+                id: ADMIN_ACTOR.to_string(),
+                organizations: Vec::new(),
+                teams: Vec::new(),
+                pool_affinity: None,
+                roles: BTreeSet::from([ADMIN_ROLE.to_string()]),
+                permissions: Permission::ALL.iter().cloned().collect(),
+                quota: Quota::default(),
+                api_token: false,
+            },
+        })
+    }
+
+    // `Manager` blocks on its own runtime, so it can't be called from this one
+    async fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(Manager, LoggedUser) -> playground::error::Result<T> + Send + 'static,
+    {
+        let (manager, admin) = (self.manager.clone(), self.admin.clone());
+        spawn_blocking(move || f(manager, admin).map_err(|err| err.to_string()))
+            .await?
+            .map_err(Into::into)
+    }
+}
+
+// Parses a query string the same way the HTTP API does
+fn parse_query<'f, T>(query: &'f str) -> Result<T>
+where
+    T: FromForm<'f>,
+    T::Error: Debug,
+{
+    T::from_form(&mut FormItems::from(query), true)
+        .map_err(|err| format!("invalid options: {:?}", err).into())
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+#[async_trait(?Send)]
+impl Backend for ClusterBackend {
+    async fn list_sessions(&self, query: &str) -> Result<Value> {
+        let query: SessionQuery = parse_query(query)?;
+        to_value(
+            self.call(move |manager, admin| manager.list_sessions_page(&admin, &query))
+                .await?,
+        )
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Value> {
+        let id = id.to_string();
+        to_value(
+            self.call(move |manager, admin| manager.get_session(&admin, &id))
+                .await?,
+        )
+    }
+
+    async fn create_session(&self, id: &str, conf: SessionConfiguration) -> Result<()> {
+        let id = id.to_string();
+        self.call(move |manager, admin| manager.create_session(&admin, &id, conf))
+            .await
+    }
+
+    async fn update_session(&self, id: &str, conf: SessionUpdateConfiguration) -> Result<()> {
+        let id = id.to_string();
+        self.call(move |manager, admin| manager.update_session(&id, &admin, conf))
+            .await
+    }
+
+    async fn delete_session(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.call(move |manager, admin| manager.delete_session(&admin, &id))
+            .await
+    }
+
+    async fn list_users(&self, query: &str) -> Result<Value> {
+        let query: UserQuery = parse_query(query)?;
+        to_value(
+            self.call(move |manager, admin| manager.list_users_page(&admin, &query))
+                .await?,
+        )
+    }
+
+    async fn get_user(&self, id: &str) -> Result<Value> {
+        let id = id.to_string();
+        to_value(
+            self.call(move |manager, admin| manager.get_user(&admin, &id))
+                .await?,
+        )
+    }
+
+    async fn create_user(&self, id: &str, conf: UserConfiguration) -> Result<()> {
+        let id = id.to_string();
+        self.call(move |manager, admin| manager.create_user(&admin, id, conf))
+            .await
+    }
+
+    async fn update_user(&self, id: &str, conf: UserUpdateConfiguration) -> Result<()> {
+        let id = id.to_string();
+        self.call(move |manager, admin| manager.update_user(admin, id, conf))
+            .await
+    }
+
+    async fn delete_user(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.call(move |manager, admin| manager.delete_user(&admin, id))
+            .await
+    }
+
+    async fn import_template(&self, id: &str, template: Template) -> Result<()> {
+        let id = id.to_string();
+        self.call(move |manager, admin| manager.import_template(&admin, &id, template))
+            .await
+    }
+
+    async fn list_pools(&self, query: &str) -> Result<Value> {
+        let query: PoolQuery = parse_query(query)?;
+        to_value(
+            self.call(move |manager, admin| manager.list_pools_page(&admin, &query))
+                .await?,
+        )
+    }
+
+    async fn get_pool(&self, id: &str) -> Result<Value> {
+        let id = id.to_string();
+        to_value(
+            self.call(move |manager, admin| manager.get_pool(&admin, &id))
+                .await?,
+        )
+    }
+
+    async fn list_audit_entries(&self, filter: &str) -> Result<Value> {
+        let filter: AuditFilter = parse_query(filter)?;
+        to_value(
+            self.call(move |manager, admin| manager.list_audit_entries(&admin, &filter))
+                .await?,
+        )
+    }
+}
@@ -0,0 +1,149 @@
+//! Access through the HTTP API, authenticated with an API token
+
+use crate::{Backend, Result};
+use async_trait::async_trait;
+use hyper::{
+    body::{aggregate, Buf},
+    client::HttpConnector,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
+    Body, Client, Method, Request,
+};
+use hyper_tls::HttpsConnector;
+use playground::types::{
+    SessionConfiguration, SessionUpdateConfiguration, Template, UserConfiguration,
+    UserUpdateConfiguration,
+};
+use rocket::http::uri::Uri;
+use serde::Serialize;
+use serde_json::{from_reader, Value};
+
+pub struct HttpBackend {
+    /// e.g. `https://playground.substrate.dev`
+    url: String,
+    token: String,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl HttpBackend {
+    pub fn new(url: &str, token: &str) -> Self {
+        HttpBackend {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            client: Client::builder().build::<_, Body>(HttpsConnector::new()),
+        }
+    }
+
+    // Calls `path` relative to /api/v1 and returns the `result` of the response
+    async fn call(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value> {
+        let uri = format!("{}/api/v1{}", self.url, path);
+        let builder = Request::builder()
+            .method(method)
+            .uri(&uri)
+            .header(ACCEPT, "application/json")
+            .header(CONTENT_TYPE, "application/json")
+            .header(USER_AGENT, "Substrate Playground")
+            .header(AUTHORIZATION, format!("Bearer {}", self.token));
+        let body = match body {
+            Some(body) => Body::from(serde_json::to_vec(&body)?),
+            None => Body::empty(),
+        };
+        let res = self.client.request(builder.body(body)?).await?;
+        let status = res.status();
+        let mut value: Value = from_reader(aggregate(res).await?.reader())
+            .map_err(|_| format!("Request to {} failed with status {}", uri, status))?;
+        if status.is_success() {
+            Ok(value.get_mut("result").map(Value::take).unwrap_or_default())
+        } else {
+            let error = &value["error"];
+            Err(format!(
+                "{} ({}, request id {})",
+                error["message"].as_str().unwrap_or("unknown error"),
+                error["code"].as_str().unwrap_or_else(|| status.as_str()),
+                error["requestId"].as_str().unwrap_or("-"),
+            )
+            .into())
+        }
+    }
+
+    async fn send<T: Serialize>(&self, method: Method, path: &str, body: &T) -> Result<()> {
+        self.call(method, path, Some(serde_json::to_value(body)?))
+            .await?;
+        Ok(())
+    }
+}
+
+fn resource(collection: &str, id: &str) -> String {
+    format!("/{}/{}", collection, Uri::percent_encode(id))
+}
+
+#[async_trait(?Send)]
+impl Backend for HttpBackend {
+    async fn list_sessions(&self, query: &str) -> Result<Value> {
+        self.call(Method::GET, &format!("/sessions?{}", query), None)
+            .await
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Value> {
+        self.call(Method::GET, &resource("sessions", id), None)
+            .await
+    }
+
+    async fn create_session(&self, id: &str, conf: SessionConfiguration) -> Result<()> {
+        self.send(Method::PUT, &resource("sessions", id), &conf)
+            .await
+    }
+
+    async fn update_session(&self, id: &str, conf: SessionUpdateConfiguration) -> Result<()> {
+        self.send(Method::PATCH, &resource("sessions", id), &conf)
+            .await
+    }
+
+    async fn delete_session(&self, id: &str) -> Result<()> {
+        self.call(Method::DELETE, &resource("sessions", id), None)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_users(&self, query: &str) -> Result<Value> {
+        self.call(Method::GET, &format!("/users?{}", query), None)
+            .await
+    }
+
+    async fn get_user(&self, id: &str) -> Result<Value> {
+        self.call(Method::GET, &resource("users", id), None).await
+    }
+
+    async fn create_user(&self, id: &str, conf: UserConfiguration) -> Result<()> {
+        self.send(Method::PUT, &resource("users", id), &conf).await
+    }
+
+    async fn update_user(&self, id: &str, conf: UserUpdateConfiguration) -> Result<()> {
+        self.send(Method::PATCH, &resource("users", id), &conf)
+            .await
+    }
+
+    async fn delete_user(&self, id: &str) -> Result<()> {
+        self.call(Method::DELETE, &resource("users", id), None)
+            .await?;
+        Ok(())
+    }
+
+    async fn import_template(&self, id: &str, template: Template) -> Result<()> {
+        self.send(Method::PUT, &resource("templates", id), &template)
+            .await
+    }
+
+    async fn list_pools(&self, query: &str) -> Result<Value> {
+        self.call(Method::GET, &format!("/pools?{}", query), None)
+            .await
+    }
+
+    async fn get_pool(&self, id: &str) -> Result<Value> {
+        self.call(Method::GET, &resource("pools", id), None).await
+    }
+
+    async fn list_audit_entries(&self, filter: &str) -> Result<Value> {
+        self.call(Method::GET, &format!("/audit?{}", filter), None)
+            .await
+    }
+}
@@ -0,0 +1,362 @@
+//! Administration CLI
+//!
+//! Talks to the HTTP API of the playground at `PLAYGROUND_URL`, authenticated with the API token `PLAYGROUND_TOKEN`.
+//! When `PLAYGROUND_URL` isn't set and it runs inside the backend pod, goes directly to kubernetes.
+
+mod args;
+mod cluster;
+mod http;
+mod output;
+
+use crate::{
+    args::Args,
+    cluster::ClusterBackend,
+    http::HttpBackend,
+    output::{
+        print_table, Column, AUDIT_COLUMNS, NODE_COLUMNS, POOL_COLUMNS, SESSION_COLUMNS,
+        USER_COLUMNS,
+    },
+};
+use async_trait::async_trait;
+use playground::types::{
+    SessionConfiguration, SessionUpdateConfiguration, Template, UserConfiguration,
+    UserUpdateConfiguration,
+};
+use serde_json::Value;
+use std::{env, error::Error, fs, path::Path, process, time::Duration};
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const USAGE: &str = "Usage: playground-admin [--json] <command>
+
+Commands:
+  session list [--owner ID] [--template ID] [--pool ID] [--phase PHASE] [--org ORG] [--sort FIELD] [--cursor CURSOR] [--limit N]
+  session get <id>
+  session create <id> --template ID [--duration MINUTES] [--pool ID]
+  session extend <id> --duration MINUTES
+  session delete <id>
+  user list [--role ROLE] [--pool ID] [--org ORG] [--sort FIELD] [--cursor CURSOR] [--limit N]
+  user get <id>
+  user create <id> [--role ROLE]... [--pool ID]
+  user update <id> [--role ROLE]... [--pool ID]
+  user delete <id>
+  template import <file> [--id ID]
+  pool list [--cordoned BOOL] [--sort FIELD] [--cursor CURSOR] [--limit N]
+  pool get <id>
+  audit list [--actor ID] [--action ACTION] [--target ID] [--success BOOL] [--since SECONDS] [--until SECONDS] [--limit N]
+
+Environment:
+  PLAYGROUND_URL    URL of the playground, e.g. https://playground.substrate.dev
+  PLAYGROUND_TOKEN  API token used to call PLAYGROUND_URL
+";
+
+const PAGE_OPTIONS: [&str; 3] = ["sort", "cursor", "limit"];
+
+/// Operations available whether the playground is accessed via its API or directly.
+/// Lists take the query string of their /api/v1 endpoint.
+#[async_trait(?Send)]
+pub trait Backend {
+    async fn list_sessions(&self, query: &str) -> Result<Value>;
+    async fn get_session(&self, id: &str) -> Result<Value>;
+    async fn create_session(&self, id: &str, conf: SessionConfiguration) -> Result<()>;
+    async fn update_session(&self, id: &str, conf: SessionUpdateConfiguration) -> Result<()>;
+    async fn delete_session(&self, id: &str) -> Result<()>;
+    async fn list_users(&self, query: &str) -> Result<Value>;
+    async fn get_user(&self, id: &str) -> Result<Value>;
+    async fn create_user(&self, id: &str, conf: UserConfiguration) -> Result<()>;
+    async fn update_user(&self, id: &str, conf: UserUpdateConfiguration) -> Result<()>;
+    async fn delete_user(&self, id: &str) -> Result<()>;
+    async fn import_template(&self, id: &str, template: Template) -> Result<()>;
+    async fn list_pools(&self, query: &str) -> Result<Value>;
+    async fn get_pool(&self, id: &str) -> Result<Value>;
+    async fn list_audit_entries(&self, filter: &str) -> Result<Value>;
+}
+
+async fn backend() -> Result<Box<dyn Backend>> {
+    match env::var("PLAYGROUND_URL") {
+        Ok(url) => {
+            let token = env::var("PLAYGROUND_TOKEN").map_err(|_| "missing PLAYGROUND_TOKEN")?;
+            Ok(Box::new(HttpBackend::new(&url, &token)))
+        }
+        // Set in all pods
+        Err(_) if env::var("KUBERNETES_SERVICE_HOST").is_ok() => {
+            Ok(Box::new(ClusterBackend::new().await?))
+        }
+        Err(_) => Err("PLAYGROUND_URL must be set when running outside of the cluster".into()),
+    }
+}
+
+fn with<'a>(options: &[&'a str], others: &[&'a str]) -> Vec<&'a str> {
+    options.iter().chain(others).copied().collect()
+}
+
+fn minutes(value: &str) -> Result<Duration> {
+    let minutes: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration {}, must be minutes", value))?;
+    Ok(Duration::from_secs(minutes * 60))
+}
+
+fn roles(args: &Args) -> Vec<String> {
+    args.values("role").into_iter().map(String::from).collect()
+}
+
+// Adds `id` to `value`, displayed as the first column of tables
+fn with_id(id: &str, mut value: Value) -> Value {
+    if let Value::Object(object) = &mut value {
+        object.insert("id".to_string(), Value::String(id.to_string()));
+    }
+    value
+}
+
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn page(&self, columns: &[Column], page: Value) -> Result<()> {
+        if self.json {
+            return self.print_json(&page);
+        }
+        if let Some(Value::Array(items)) = page.get("items") {
+            print_table(columns, items);
+        }
+        if let Some(cursor) = page.get("nextCursor").and_then(Value::as_str) {
+            eprintln!("More results with --cursor {}", cursor);
+        }
+        Ok(())
+    }
+
+    fn list(&self, columns: &[Column], items: Value) -> Result<()> {
+        match items {
+            Value::Array(items) if !self.json => {
+                print_table(columns, &items);
+                Ok(())
+            }
+            items => self.print_json(&items),
+        }
+    }
+
+    fn item(&self, columns: &[Column], kind: &str, id: &str, item: Value) -> Result<()> {
+        if item.is_null() {
+            return Err(format!("{} {} not found", kind, id).into());
+        }
+        if self.json {
+            self.print_json(&item)
+        } else {
+            print_table(columns, &[with_id(id, item)]);
+            Ok(())
+        }
+    }
+
+    fn done(&self, kind: &str, id: &str, action: &str) {
+        if !self.json {
+            println!("{} {} {}", kind, id, action);
+        }
+    }
+
+    fn print_json(&self, value: &Value) -> Result<()> {
+        println!("{}", serde_json::to_string_pretty(value)?);
+        Ok(())
+    }
+}
+
+async fn session(backend: &dyn Backend, args: &Args, output: &Output) -> Result<()> {
+    match args.word(1) {
+        Some("list") => {
+            args.check(
+                2,
+                &with(
+                    &PAGE_OPTIONS,
+                    &["owner", "template", "pool", "phase", "org"],
+                ),
+            )?;
+            output.page(SESSION_COLUMNS, backend.list_sessions(&args.query()).await?)
+        }
+        Some("get") => {
+            args.check(3, &[])?;
+            let id = args.required_word(2, "id")?;
+            output.item(
+                SESSION_COLUMNS,
+                "session",
+                id,
+                backend.get_session(id).await?,
+            )
+        }
+        Some("create") => {
+            args.check(3, &["template", "duration", "pool"])?;
+            let id = args.required_word(2, "id")?;
+            let conf = SessionConfiguration {
+                template: args.required_option("template")?.to_string(),
+                duration: args.option("duration").map(minutes).transpose()?,
+                pool_affinity: args.option("pool").map(String::from),
+            };
+            backend.create_session(id, conf).await?;
+            output.done("session", id, "created");
+            Ok(())
+        }
+        Some("extend") => {
+            args.check(3, &["duration"])?;
+            let id = args.required_word(2, "id")?;
+            let conf = SessionUpdateConfiguration {
+                duration: Some(minutes(args.required_option("duration")?)?),
+            };
+            backend.update_session(id, conf).await?;
+            output.done("session", id, "extended");
+            Ok(())
+        }
+        Some("delete") => {
+            args.check(3, &[])?;
+            let id = args.required_word(2, "id")?;
+            backend.delete_session(id).await?;
+            output.done("session", id, "deleted");
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn user(backend: &dyn Backend, args: &Args, output: &Output) -> Result<()> {
+    match args.word(1) {
+        Some("list") => {
+            args.check(2, &with(&PAGE_OPTIONS, &["role", "pool", "org"]))?;
+            output.page(USER_COLUMNS, backend.list_users(&args.query()).await?)
+        }
+        Some("get") => {
+            args.check(3, &[])?;
+            let id = args.required_word(2, "id")?;
+            output.item(USER_COLUMNS, "user", id, backend.get_user(id).await?)
+        }
+        Some("create") => {
+            args.check(3, &["role", "pool"])?;
+            let id = args.required_word(2, "id")?;
+            let conf = UserConfiguration {
+                roles: roles(args),
+                pool_affinity: args.option("pool").map(String::from),
+                quota: None,
+            };
+            backend.create_user(id, conf).await?;
+            output.done("user", id, "created");
+            Ok(())
+        }
+        Some("update") => {
+            args.check(3, &["role", "pool"])?;
+            let id = args.required_word(2, "id")?;
+            let user = backend.get_user(id).await?;
+            if user.is_null() {
+                return Err(format!("user {} not found", id).into());
+            }
+            // Users are replaced as a whole, options not provided are left unchanged
+            let mut conf: UserUpdateConfiguration = serde_json::from_value(user)?;
+            if !args.values("role").is_empty() {
+                conf.roles = roles(args);
+            }
+            if let Some(pool) = args.option("pool") {
+                conf.pool_affinity = Some(pool.to_string());
+            }
+            backend.update_user(id, conf).await?;
+            output.done("user", id, "updated");
+            Ok(())
+        }
+        Some("delete") => {
+            args.check(3, &[])?;
+            let id = args.required_word(2, "id")?;
+            backend.delete_user(id).await?;
+            output.done("user", id, "deleted");
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn template(backend: &dyn Backend, args: &Args, output: &Output) -> Result<()> {
+    match args.word(1) {
+        Some("import") => {
+            args.check(3, &["id"])?;
+            let file = args.required_word(2, "file")?;
+            let template: Template = serde_yaml::from_str(&fs::read_to_string(file)?)
+                .map_err(|err| format!("invalid template {}: {}", file, err))?;
+            // Defaults to the file name, as when templates are loaded from a directory
+            let id = match args.option("id") {
+                Some(id) => id.to_string(),
+                None => Path::new(file)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .ok_or("missing --id")?,
+            };
+            backend.import_template(&id, template).await?;
+            output.done("template", &id, "imported");
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn pool(backend: &dyn Backend, args: &Args, output: &Output) -> Result<()> {
+    match args.word(1) {
+        Some("list") => {
+            args.check(2, &with(&PAGE_OPTIONS, &["cordoned"]))?;
+            output.page(POOL_COLUMNS, backend.list_pools(&args.query()).await?)
+        }
+        Some("get") => {
+            args.check(3, &[])?;
+            let id = args.required_word(2, "id")?;
+            let pool = backend.get_pool(id).await?;
+            let nodes = pool.get("nodes").cloned();
+            output.item(POOL_COLUMNS, "pool", id, pool)?;
+            if let Some(nodes) = nodes.filter(|_| !output.json) {
+                println!();
+                output.list(NODE_COLUMNS, nodes)?;
+            }
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn audit(backend: &dyn Backend, args: &Args, output: &Output) -> Result<()> {
+    match args.word(1) {
+        Some("list") => {
+            args.check(
+                2,
+                &[
+                    "actor", "action", "target", "success", "since", "until", "limit",
+                ],
+            )?;
+            output.list(
+                AUDIT_COLUMNS,
+                backend.list_audit_entries(&args.query()).await?,
+            )
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn run(args: Args) -> Result<()> {
+    let command = match args.word(0) {
+        Some(command @ ("session" | "user" | "template" | "pool" | "audit")) => command,
+        _ => return Err(USAGE.into()),
+    };
+    let backend = backend().await?;
+    let backend = backend.as_ref();
+    let output = Output { json: args.json };
+    match command {
+        "session" => session(backend, &args, &output).await,
+        "user" => user(backend, &args, &output).await,
+        "template" => template(backend, &args, &output).await,
+        "pool" => pool(backend, &args, &output).await,
+        _ => audit(backend, &args, &output).await,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let result = match Args::parse(env::args().skip(1)) {
+        Ok(args) => run(args).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,130 @@
+//! Rendering of API results as tables
+
+use serde_json::Value;
+
+/// A column header and the JSON pointer of its value in each row
+pub type Column = (&'static str, &'static str);
+
+pub const SESSION_COLUMNS: &[Column] = &[
+    ("ID", "/id"),
+    ("OWNER", "/userId"),
+    ("TEMPLATE", "/templateId"),
+    ("POOL", "/pool"),
+    ("NODE", "/node"),
+    ("PHASE", "/pod/phase"),
+    ("DURATION", "/duration"),
+];
+
+pub const USER_COLUMNS: &[Column] = &[
+    ("ID", "/id"),
+    ("ROLES", "/roles"),
+    ("POOL", "/poolAffinity"),
+];
+
+pub const POOL_COLUMNS: &[Column] = &[
+    ("ID", "/id"),
+    ("INSTANCE TYPE", "/instanceType"),
+    ("NODES", "/nodes"),
+    ("CORDONED", "/cordoned"),
+    ("CPU USED", "/used/cpu"),
+    ("CPU ALLOCATABLE", "/allocatable/cpu"),
+];
+
+pub const NODE_COLUMNS: &[Column] = &[
+    ("HOSTNAME", "/hostname"),
+    ("SESSIONS", "/sessions"),
+    ("READY", "/ready"),
+    ("CORDONED", "/cordoned"),
+    ("CPU USED", "/used/cpu"),
+    ("CPU ALLOCATABLE", "/allocatable/cpu"),
+];
+
+pub const AUDIT_COLUMNS: &[Column] = &[
+    ("TIMESTAMP", "/timestamp"),
+    ("ACTOR", "/actor"),
+    ("ACTION", "/action"),
+    ("TARGET", "/target"),
+    ("SUCCESS", "/success"),
+    ("ERROR", "/error"),
+];
+
+// Arrays of strings are joined, other arrays are displayed as their length
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "-".to_string(),
+        Some(Value::String(value)) => value.clone(),
+        Some(Value::Array(values)) if values.iter().all(Value::is_string) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(","),
+        Some(Value::Array(values)) => values.len().to_string(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// Prints `rows` as a table, columns aligned on their widest cell
+pub fn print_table(columns: &[Column], rows: &[Value]) {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|(_, pointer)| cell(row.pointer(pointer)))
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (header, _))| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .fold(header.len(), usize::max)
+        })
+        .collect();
+    let line = |values: Vec<&str>| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!(
+        "{}",
+        line(columns.iter().map(|(header, _)| *header).collect())
+    );
+    for row in &cells {
+        println!("{}", line(row.iter().map(String::as_str).collect()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn cells_are_rendered_from_values() {
+        assert_eq!(cell(None), "-");
+        assert_eq!(cell(Some(&Value::Null)), "-");
+        assert_eq!(cell(Some(&json!("alice"))), "alice");
+        assert_eq!(cell(Some(&json!(3))), "3");
+        assert_eq!(cell(Some(&json!(true))), "true");
+        assert_eq!(cell(Some(&json!(["admin", "user"]))), "admin,user");
+        assert_eq!(cell(Some(&json!([]))), "");
+        assert_eq!(cell(Some(&json!([{ "id": "a" }, { "id": "b" }]))), "2");
+        assert_eq!(cell(Some(&json!({ "cpu": 1 }))), r#"{"cpu":1}"#);
+    }
+
+    #[test]
+    fn cells_are_pointed_by_columns() {
+        let row = json!({ "id": "alice", "pod": { "phase": "Running" } });
+        assert_eq!(cell(row.pointer("/pod/phase")), "Running");
+        assert_eq!(cell(row.pointer("/pool")), "-");
+    }
+}
@@ -1,24 +1,34 @@
 //! HTTP endpoints exposed in /api context
 use crate::{
-    error::Result,
-    github::{current_user, orgs, GitHubUser},
-    kubernetes::Environment,
+    audit::{AuditAction, AuditEntry, AuditFilter},
+    error::{Error, Result},
+    history::{HistoryFilter, UsageGroup},
+    identity::{Identity, OAuthIdentity},
+    kubernetes::Engine,
+    listing::{PoolQuery, SessionQuery, UserQuery},
+    logging,
+    login::Login,
+    manager::Manager,
+    metrics::Metrics,
+    openapi, token,
     types::{
-        LoggedUser, SessionConfiguration, SessionUpdateConfiguration, UserConfiguration,
-        UserUpdateConfiguration,
+        ApiTokenConfiguration, DrainConfiguration, LoggedUser, SessionConfiguration,
+        SessionUpdateConfiguration, Template, UserConfiguration, UserUpdateConfiguration,
     },
     Context,
 };
 use request::FormItems;
+use rocket::fairing::{Fairing, Info, Kind};
 use rocket::response::{content, status, Redirect};
 use rocket::{
     catch, delete, get,
-    http::{Cookie, Cookies, SameSite, Status},
+    http::{ContentType, Cookie, Cookies, SameSite, Status},
     patch, put, Outcome, State,
 };
 use rocket::{
     http::uri::Origin,
-    request::{self, FromRequest, Request},
+    request::{self, Form, FormParseError, FromRequest, Request},
+    Response,
 };
 use rocket_contrib::{
     json,
@@ -26,101 +36,318 @@ use rocket_contrib::{
 };
 use rocket_oauth2::{OAuth2, TokenResponse};
 use serde::Serialize;
+use serde_json::Value;
 use tokio::runtime::Runtime;
+use tracing::warn;
 
-const COOKIE_TOKEN: &str = "token";
+// Holds the opaque ID of a `Login`
+const COOKIE_LOGIN: &str = "login";
+// Query of the page users were on when logging in, restored once authenticated
+const COOKIE_LOGIN_ORIGIN: &str = "login_origin";
+const BEARER_PREFIX: &str = "Bearer ";
 
-// Extract a User from cookies
-impl<'a, 'r> FromRequest<'a, 'r> for LoggedUser {
-    type Error = String;
+type GuardError = (Status, String);
 
-    fn from_request(request: &'a Request<'r>) -> request::Outcome<LoggedUser, String> {
-        let engine = &request
-            .guard::<State<Context>>()
-            .map_failure(|_f| (Status::BadRequest, "Can't access state".to_string()))?
-            .manager
-            .engine;
-        let mut cookies = request.cookies();
-        if let Some(token) = cookies.get_private(COOKIE_TOKEN) {
-            let token_value = token.value();
-            let runtime = Runtime::new().map_err(|_| {
+fn new_guard_runtime() -> std::result::Result<Runtime, GuardError> {
+    Runtime::new().map_err(|_| {
+        (
+            Status::ExpectationFailed,
+            "Failed to execute async fn".to_string(),
+        )
+    })
+}
+
+// Build the LoggedUser matching `identity`, provided it is whitelisted
+fn resolve_user(
+    runtime: &Runtime,
+    engine: &Engine,
+    identity: &Identity,
+) -> std::result::Result<LoggedUser, GuardError> {
+    let users = runtime.block_on(engine.clone().list_users()).map_err(|_| {
+        (
+            Status::FailedDependency,
+            "Missing users ConfigMap".to_string(),
+        )
+    })?;
+    let organizations_configuration =
+        runtime.block_on(engine.list_organizations()).map_err(|_| {
+            (
+                Status::FailedDependency,
+                "Can't access organizations".to_string(),
+            )
+        })?;
+    let roles = runtime
+        .block_on(engine.list_roles())
+        .map_err(|_| (Status::FailedDependency, "Can't access roles".to_string()))?;
+    let user = users.get(&identity.id);
+    // If at least one non-admin user is defined, then users are only allowed if whitelisted
+    let filtered = users.values().any(|user| !user.is_admin());
+    if !filtered || user.is_some() {
+        Ok(LoggedUser::new(
+            identity.id.clone(),
+            identity.organizations.clone(),
+            identity.teams.clone(),
+            user,
+            &organizations_configuration,
+            &roles,
+            &engine.configuration.session.get().quota,
+        ))
+    } else {
+        Err((Status::Forbidden, "User is not whitelisted".to_string()))
+    }
+}
+
+// Only keeps `memberships` (organizations or teams) listed in `current`
+fn retain_memberships(memberships: Vec<String>, current: &[String]) -> Vec<String> {
+    memberships
+        .into_iter()
+        .filter(|membership| current.contains(membership))
+        .collect()
+}
+
+// Extract a User from an API token provided as `Authorization: Bearer` header
+fn user_from_api_token(
+    manager: &Manager,
+    value: &str,
+) -> std::result::Result<LoggedUser, GuardError> {
+    let engine = &manager.engine;
+    let runtime = new_guard_runtime()?;
+    let api_token = runtime
+        .block_on(engine.get_api_token(&token::hash(value)))
+        .map_err(|_| (Status::FailedDependency, "Can't access tokens".to_string()))?
+        .filter(|api_token| !api_token.is_expired())
+        .ok_or_else(|| (Status::Unauthorized, "Invalid token".to_string()))?;
+    // Memberships may have been revoked since the token was created. They are capped to the ones of the owner
+    // current login if any, else to the ones recorded when it last logged in.
+    let current = match manager.logins.identity(&api_token.user_id) {
+        Some(identity) => Some((identity.organizations, identity.teams)),
+        None => runtime
+            .block_on(engine.get_user(&api_token.user_id))
+            .map_err(|_| {
                 (
-                    Status::ExpectationFailed,
-                    "Failed to execute async fn".to_string(),
+                    Status::FailedDependency,
+                    "Missing users ConfigMap".to_string(),
                 )
-            })?;
-            let gh_user = runtime.block_on(current_user(token_value)).map_err(|err| {
+            })?
+            .map(|user| (user.organizations, user.teams)),
+    };
+    let (organizations, teams) = match current {
+        Some((organizations, teams)) => (
+            retain_memberships(api_token.organizations, &organizations),
+            retain_memberships(api_token.teams, &teams),
+        ),
+        None => (api_token.organizations, api_token.teams),
+    };
+    let identity = Identity {
+        id: api_token.user_id,
+        organizations,
+        teams,
+    };
+    let mut user = resolve_user(&runtime, engine, &identity)?;
+    user.api_token = true;
+    // A token can only be used for the permissions it has been scoped to
+    user.permissions = user
+        .permissions
+        .intersection(&api_token.scopes)
+        .cloned()
+        .collect();
+    Ok(user)
+}
+
+// Keeps memberships of registered users up to date, so that they can be listed by organization and API tokens
+// restricted to them
+fn record_memberships(runtime: &Runtime, engine: &Engine, identity: &Identity) {
+    let recorded = match runtime.block_on(engine.get_user(&identity.id)) {
+        Ok(Some(user)) => (user.organizations, user.teams),
+        _ => return,
+    };
+    if recorded != (identity.organizations.clone(), identity.teams.clone()) {
+        if let Err(err) = runtime.block_on(engine.set_user_memberships(
+            &identity.id,
+            identity.organizations.clone(),
+            identity.teams.clone(),
+        )) {
+            warn!(user = %identity.id, error = %err, "Failed to record memberships");
+        }
+    }
+}
+
+// Extract a User from a `Login`, resolving it if not cached
+fn user_from_login(
+    manager: &Manager,
+    id: &str,
+    login: Login,
+) -> std::result::Result<LoggedUser, GuardError> {
+    if let Some(user) = login.user {
+        return Ok(user);
+    }
+    let engine = &manager.engine;
+    let runtime = new_guard_runtime()?;
+    let first_login = login.identity.is_none() && !login.restored;
+    let identity = match login.identity {
+        Some(identity) => identity,
+        None => runtime
+            .block_on(
+                engine
+                    .configuration
+                    .identity_provider
+                    .identity(&login.access_token),
+            )
+            .map_err(|err| {
                 // A token is present, but can't be used to access user details
-                clear(cookies);
-                log::warn!("Error while accessing user details: {}", err);
+                warn!(error = %err, "Error while accessing user details");
                 (
                     Status::BadRequest,
                     format!("Can't access user details {}", err),
                 )
-            })?;
-            let id = gh_user.clone().login;
-            let users = runtime.block_on(engine.clone().list_users()).map_err(|_| {
-                (
-                    Status::FailedDependency,
-                    "Missing users ConfigMap".to_string(),
-                )
-            })?;
-            let organizations = runtime
-                .block_on(orgs(token_value, &gh_user))
-                .unwrap_or_default()
-                .iter()
-                .map(|org| org.clone().login)
-                .collect();
-            let user = users.get(&id);
-            // If at least one non-admin user is defined, then users are only allowed if whitelisted
-            let filtered = users.values().any(|user| !user.admin);
-            if !filtered || user.is_some() {
-                Outcome::Success(LoggedUser {
-                    id: id.clone(),
-                    admin: user.map_or(false, |user| user.admin),
-                    pool_affinity: user.and_then(|user| user.pool_affinity.clone()),
-                    can_customize_duration: user.map_or(false, |user| user.can_customize_duration),
-                    can_customize_pool_affinity: user
-                        .map_or(false, |user| user.can_customize_pool_affinity),
-                    organizations,
-                })
-            } else {
-                Outcome::Failure((Status::Forbidden, "User is not whitelisted".to_string()))
+            })?,
+    };
+    let result = resolve_user(&runtime, engine, &identity);
+    if first_login {
+        manager.audit.record(AuditEntry::new(
+            &identity.id,
+            AuditAction::Login,
+            &identity.id,
+            Value::Null,
+            result.as_ref().err().map(|(_, reason)| reason.clone()),
+        ));
+    }
+    let user = result?;
+    record_memberships(&runtime, engine, &identity);
+    manager.logins.set_user(id, identity, user.clone());
+    Ok(user)
+}
+
+// Extract a User from an API token or cookies
+impl<'a, 'r> FromRequest<'a, 'r> for LoggedUser {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<LoggedUser, String> {
+        let manager = &request
+            .guard::<State<Context>>()
+            .map_failure(|_f| (Status::BadRequest, "Can't access state".to_string()))?
+            .manager;
+        if let Some(authorization) = request.headers().get_one("Authorization") {
+            return match authorization.strip_prefix(BEARER_PREFIX) {
+                Some(value) => match user_from_api_token(manager, value.trim()) {
+                    Ok(user) => Outcome::Success(user),
+                    Err(failure) => Outcome::Failure(failure),
+                },
+                None => Outcome::Failure((
+                    Status::Unauthorized,
+                    "Unsupported authorization scheme".to_string(),
+                )),
+            };
+        }
+        let mut cookies = request.cookies();
+        if let Some(cookie) = cookies.get_private(COOKIE_LOGIN) {
+            let id = cookie.value();
+            match manager.logins.get(id) {
+                Some(login) => match user_from_login(manager, id, login) {
+                    Ok(user) => Outcome::Success(user),
+                    Err(failure) => {
+                        // The login can't be used anymore
+                        if failure.0 == Status::BadRequest {
+                            clear(manager, cookies);
+                        }
+                        Outcome::Failure(failure)
+                    }
+                },
+                None => {
+                    // Unknown or expired login, anonymous call
+                    clear(manager, cookies);
+                    Outcome::Forward(())
+                }
             }
         } else {
-            // No token in cookies, anonymous call
+            // No login in cookies, anonymous call
             Outcome::Forward(())
         }
     }
 }
 
-fn result_to_jsonrpc<T: Serialize>(res: Result<T>) -> JsonValue {
+// Responses of API calls, with a status matching their outcome
+type ApiResponse = status::Custom<JsonValue>;
+
+fn error_status(err: &Error) -> Status {
+    Status::from_code(err.status()).unwrap_or(Status::InternalServerError)
+}
+
+fn error_body(err: &Error) -> JsonValue {
+    json!({
+        "error": {
+            "code": err.code(),
+            "message": err.to_string(),
+            "details": err.details(),
+            "requestId": logging::current_request_id(),
+        }
+    })
+}
+
+fn error_response(metrics: &Metrics, err: Error) -> ApiResponse {
+    metrics.inc_errors_counter(&err);
+    status::Custom(error_status(&err), error_body(&err))
+}
+
+fn result_to_jsonrpc<T: Serialize>(metrics: &Metrics, res: Result<T>) -> ApiResponse {
     match res {
-        Ok(val) => json!({ "result": val }),
-        Err(err) => json!({ "error": err.to_string() }),
+        Ok(val) => status::Custom(Status::Ok, json!({ "result": val })),
+        Err(err) => error_response(metrics, err),
     }
 }
 
+/// Query of a list endpoint, as parsed by Rocket
+type ListQuery<'q, T> = std::result::Result<Form<T>, FormParseError<'q>>;
+
+// Unknown or malformed parameters of a list endpoint are reported as `InvalidValue`, rather than forwarded to a 404
+fn list_query<T>(query: ListQuery<'_, T>) -> Result<T> {
+    let reason = match query {
+        Ok(query) => return Ok(query.into_inner()),
+        Err(FormParseError::Unknown(name, _)) => format!("unknown parameter {}", name),
+        Err(FormParseError::BadValue(name, value)) => {
+            format!("invalid value {} for {}", value, name)
+        }
+        Err(FormParseError::Missing(name)) => format!("missing parameter {}", name),
+    };
+    Err(Error::InvalidValue {
+        field: "query",
+        reason,
+    })
+}
+
 #[get("/")]
-pub fn get(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().get(user))
+pub fn get(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(&state.manager.metrics, state.manager.clone().get(user))
 }
 
 #[get("/", rank = 2)]
-pub fn get_unlogged(state: State<'_, Context>) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_unlogged())
+pub fn get_unlogged(state: State<'_, Context>) -> ApiResponse {
+    result_to_jsonrpc(&state.manager.metrics, state.manager.get_unlogged())
 }
 
 // User resources. Only accessible to Admins.
 
 #[get("/users/<id>")]
-pub fn get_user(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_user(&user, &id))
+pub fn get_user(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(&state.manager.metrics, state.manager.get_user(&user, &id))
 }
 
 #[get("/users")]
-pub fn list_users(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.list_users(&user))
+pub fn list_users(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(&state.manager.metrics, state.manager.list_users(&user))
+}
+
+/// Paginated version of `list_users`, mounted in /api/v1
+#[get("/users?<query..>")]
+pub fn list_users_page(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    query: ListQuery<'_, UserQuery>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        list_query(query).and_then(|query| state.manager.list_users_page(&user, &query)),
+    )
 }
 
 #[put("/users/<id>", data = "<conf>")]
@@ -129,8 +356,11 @@ pub fn create_user(
     user: LoggedUser,
     id: String,
     conf: Json<UserConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().create_user(&user, id, conf.0))
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.clone().create_user(&user, id, conf.0),
+    )
 }
 
 #[patch("/users/<id>", data = "<conf>")]
@@ -139,25 +369,34 @@ pub fn update_user(
     user: LoggedUser,
     id: String,
     conf: Json<UserUpdateConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().update_user(user, id, conf.0))
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.clone().update_user(user, id, conf.0),
+    )
 }
 
 #[delete("/users/<id>")]
-pub fn delete_user(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().delete_user(&user, id))
+pub fn delete_user(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.clone().delete_user(&user, id),
+    )
 }
 
 // Current Session
 
 #[get("/session")]
-pub fn get_current_session(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_session(&user, &session_id(&user.id)))
+pub fn get_current_session(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.get_session(&user, &session_id(&user.id)),
+    )
 }
 
 #[get("/session", rank = 2)]
-pub fn get_current_session_unlogged() -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+pub fn get_current_session_unlogged(state: State<'_, Context>) -> ApiResponse {
+    error_response(&state.manager.metrics, Error::Unauthenticated())
 }
 
 fn session_id(id: &str) -> String {
@@ -176,8 +415,9 @@ pub fn create_current_session(
     state: State<'_, Context>,
     user: LoggedUser,
     conf: Json<SessionConfiguration>,
-) -> JsonValue {
+) -> ApiResponse {
     result_to_jsonrpc(
+        &state.manager.metrics,
         state
             .manager
             .create_session(&user, &session_id(&user.id), conf.0),
@@ -186,9 +426,10 @@ pub fn create_current_session(
 
 #[put("/session", data = "<_conf>", rank = 2)]
 pub fn create_current_session_unlogged(
+    state: State<'_, Context>,
     _conf: Json<SessionConfiguration>,
-) -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+) -> ApiResponse {
+    error_response(&state.manager.metrics, Error::Unauthenticated())
 }
 
 #[patch("/session", data = "<conf>")]
@@ -196,8 +437,9 @@ pub fn update_current_session(
     state: State<'_, Context>,
     user: LoggedUser,
     conf: Json<SessionUpdateConfiguration>,
-) -> JsonValue {
+) -> ApiResponse {
     result_to_jsonrpc(
+        &state.manager.metrics,
         state
             .manager
             .update_session(&session_id(&user.id), &user, conf.0),
@@ -206,31 +448,121 @@ pub fn update_current_session(
 
 #[patch("/session", data = "<_conf>", rank = 2)]
 pub fn update_current_session_unlogged(
+    state: State<'_, Context>,
     _conf: Json<SessionUpdateConfiguration>,
-) -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+) -> ApiResponse {
+    error_response(&state.manager.metrics, Error::Unauthenticated())
 }
 
 #[delete("/session")]
-pub fn delete_current_session(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.delete_session(&user, &session_id(&user.id)))
+pub fn delete_current_session(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.delete_session(&user, &session_id(&user.id)),
+    )
 }
 
 #[delete("/session", rank = 2)]
-pub fn delete_current_session_unlogged() -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+pub fn delete_current_session_unlogged(state: State<'_, Context>) -> ApiResponse {
+    error_response(&state.manager.metrics, Error::Unauthenticated())
+}
+
+/// Move the session of `LoggedUser` to another node, e.g. before its node is drained
+#[put("/session/migrate")]
+pub fn migrate_current_session(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.migrate_session(&user, &session_id(&user.id)),
+    )
+}
+
+#[put("/session/migrate", rank = 2)]
+pub fn migrate_current_session_unlogged(state: State<'_, Context>) -> ApiResponse {
+    error_response(&state.manager.metrics, Error::Unauthenticated())
+}
+
+// API tokens
+
+#[get("/tokens")]
+pub fn list_current_user_api_tokens(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.list_api_tokens(&user, &user.id),
+    )
+}
+
+#[put("/tokens/<name>", data = "<conf>")]
+pub fn create_current_user_api_token(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    name: String,
+    conf: Json<ApiTokenConfiguration>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.create_api_token(&user, &name, conf.0),
+    )
+}
+
+#[delete("/tokens/<name>")]
+pub fn delete_current_user_api_token(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    name: String,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.delete_api_token(&user, &user.id, &name),
+    )
+}
+
+#[get("/users/<id>/tokens")]
+pub fn list_api_tokens(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.list_api_tokens(&user, &id),
+    )
+}
+
+#[delete("/users/<id>/tokens/<name>")]
+pub fn delete_api_token(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    id: String,
+    name: String,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.delete_api_token(&user, &id, &name),
+    )
 }
 
 // Sessions
 
 #[get("/sessions/<id>")]
-pub fn get_session(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_session(&user, &id))
+pub fn get_session(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.get_session(&user, &id),
+    )
 }
 
 #[get("/sessions")]
-pub fn list_sessions(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.list_sessions(&user))
+pub fn list_sessions(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(&state.manager.metrics, state.manager.list_sessions(&user))
+}
+
+/// Paginated version of `list_sessions`, mounted in /api/v1
+#[get("/sessions?<query..>")]
+pub fn list_sessions_page(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    query: ListQuery<'_, SessionQuery>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        list_query(query).and_then(|query| state.manager.list_sessions_page(&user, &query)),
+    )
 }
 
 #[put("/sessions/<id>", data = "<conf>")]
@@ -239,8 +571,11 @@ pub fn create_session(
     user: LoggedUser,
     id: String,
     conf: Json<SessionConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.create_session(&user, &id, conf.0))
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.create_session(&user, &id, conf.0),
+    )
 }
 
 #[patch("/sessions/<id>", data = "<conf>")]
@@ -249,28 +584,206 @@ pub fn update_session(
     user: LoggedUser,
     id: String,
     conf: Json<SessionUpdateConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.update_session(&id, &user, conf.0))
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.update_session(&id, &user, conf.0),
+    )
 }
 
 #[delete("/sessions/<id>")]
-pub fn delete_session(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.delete_session(&user, &id))
+pub fn delete_session(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.delete_session(&user, &id),
+    )
+}
+
+#[put("/sessions/<id>/migrate")]
+pub fn migrate_session(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.migrate_session(&user, &id),
+    )
 }
 
 // Pools
 
 #[get("/pools/<id>")]
-pub fn get_pool(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_pool(&user, &id))
+pub fn get_pool(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(&state.manager.metrics, state.manager.get_pool(&user, &id))
 }
 
 #[get("/pools")]
-pub fn list_pools(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.list_pools(&user))
+pub fn list_pools(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(&state.manager.metrics, state.manager.list_pools(&user))
+}
+
+/// Paginated version of `list_pools`, mounted in /api/v1
+#[get("/pools?<query..>")]
+pub fn list_pools_page(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    query: ListQuery<'_, PoolQuery>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        list_query(query).and_then(|query| state.manager.list_pools_page(&user, &query)),
+    )
+}
+
+#[put("/pools/<id>/cordon")]
+pub fn cordon_pool(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.set_pool_cordoned(&user, &id, true),
+    )
+}
+
+#[delete("/pools/<id>/cordon")]
+pub fn uncordon_pool(state: State<'_, Context>, user: LoggedUser, id: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.set_pool_cordoned(&user, &id, false),
+    )
+}
+
+/// Cordon pool `id` and delete its sessions once `conf.gracePeriod` is over, unless migrated by their owners
+#[put("/pools/<id>/drain", data = "<conf>")]
+pub fn drain_pool(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    id: String,
+    conf: Json<DrainConfiguration>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.drain_pool(&user, &id, conf.0),
+    )
+}
+
+// Nodes
+
+#[put("/nodes/<name>/cordon")]
+pub fn cordon_node(state: State<'_, Context>, user: LoggedUser, name: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.set_node_cordoned(&user, &name, true),
+    )
 }
 
-// GitHub login logic
+#[delete("/nodes/<name>/cordon")]
+pub fn uncordon_node(state: State<'_, Context>, user: LoggedUser, name: String) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.set_node_cordoned(&user, &name, false),
+    )
+}
+
+/// Cordon node `name` and delete its sessions once `conf.gracePeriod` is over, unless migrated by their owners
+#[put("/nodes/<name>/drain", data = "<conf>")]
+pub fn drain_node(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    name: String,
+    conf: Json<DrainConfiguration>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.drain_node(&user, &name, conf.0),
+    )
+}
+
+// Templates
+
+#[get("/templates/images")]
+pub fn list_template_images(state: State<'_, Context>, user: LoggedUser) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.list_template_images(&user),
+    )
+}
+
+#[put("/templates/<id>", data = "<template>")]
+pub fn import_template(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    id: String,
+    template: Json<Template>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.import_template(&user, &id, template.0),
+    )
+}
+
+// Audit
+
+#[get("/audit?<filter..>")]
+pub fn list_audit_entries(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    filter: Form<AuditFilter>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.list_audit_entries(&user, &filter),
+    )
+}
+
+/// Exports matching entries as JSON lines
+#[get("/audit/export?<filter..>")]
+pub fn export_audit_entries(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    filter: Form<AuditFilter>,
+) -> std::result::Result<content::Content<String>, ApiResponse> {
+    let manager = &state.manager;
+    let entries = manager
+        .list_audit_entries(&user, &filter)
+        .map_err(|err| error_response(&manager.metrics, err))?;
+    let lines = entries
+        .iter()
+        .filter_map(|entry| serde_json::to_string(entry).ok())
+        .map(|line| line + "\n")
+        .collect();
+    Ok(content::Content(
+        ContentType::new("application", "x-ndjson"),
+        lines,
+    ))
+}
+
+// History
+
+#[get("/history?<filter..>")]
+pub fn list_session_history(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    filter: Form<HistoryFilter>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        state.manager.list_session_history(&user, &filter),
+    )
+}
+
+/// Usage of finished sessions grouped by `group` (one of `user`, `template` or `pool`)
+#[get("/reports/usage?<group>&<filter..>")]
+pub fn get_usage_report(
+    state: State<'_, Context>,
+    user: LoggedUser,
+    group: String,
+    filter: Form<HistoryFilter>,
+) -> ApiResponse {
+    result_to_jsonrpc(
+        &state.manager.metrics,
+        group
+            .parse::<UsageGroup>()
+            .and_then(|group| state.manager.get_usage_report(&user, &filter, group)),
+    )
+}
+
+// OAuth login logic, using the configured identity provider
 
 fn query_segment(origin: &Origin) -> String {
     origin.query().map_or("".to_string(), |query| {
@@ -287,67 +800,165 @@ fn query_segment(origin: &Origin) -> String {
     })
 }
 
-fn protocol(env: &Environment) -> String {
-    if env.secured { "https" } else { "http" }.to_string()
-}
-
-// Gets called from UI. Then redirects to the GitHub `auth_uri` which itself redirects to `/auth/github`
-#[get("/login/github")]
-pub fn github_login(
+// Gets called from UI. Then redirects to the provider `auth_uri` which itself redirects to `/auth/<provider>`
+#[get("/login/<provider>")]
+pub fn oauth_login(
     state: State<'_, Context>,
     origin: &Origin,
-    oauth2: OAuth2<GitHubUser>,
+    oauth2: OAuth2<OAuthIdentity>,
     mut cookies: Cookies<'_>,
-) -> Redirect {
-    let manager = state.manager.clone();
-    let redirect_uri = format!(
-        "{}://{}/api/auth/github{}",
-        protocol(&manager.engine.env),
-        manager.engine.env.host,
-        query_segment(origin)
+    provider: String,
+) -> std::result::Result<Redirect, Status> {
+    let engine = &state.manager.engine;
+    let identity_provider = &engine.configuration.identity_provider;
+    if identity_provider.name() != provider {
+        return Err(Status::NotFound);
+    }
+    // The callback URI is fixed, as it must match the one sent when exchanging the code
+    cookies.add_private(
+        Cookie::build(COOKIE_LOGIN_ORIGIN, query_segment(origin))
+            .same_site(SameSite::Lax)
+            .finish(),
     );
+    let scopes = identity_provider.scopes();
     oauth2
-        .get_redirect_extras(
+        .get_redirect(
             &mut cookies,
-            &["user:read"],
-            &[("redirect_uri", &redirect_uri)],
+            &scopes.iter().map(String::as_str).collect::<Vec<_>>(),
         )
-        .unwrap()
+        .map_err(|err| {
+            warn!(provider = %provider, error = %err, "Error while redirecting");
+            Status::InternalServerError
+        })
 }
 
-/// Callback to handle the authenticated token received from GitHub
-/// and store it as a cookie
-#[get("/auth/github")]
-pub fn post_install_callback(
-    origin: &Origin,
-    token: TokenResponse<GitHubUser>,
+/// Callback to handle the authenticated token received from the identity provider
+/// and store the matching login ID as a cookie
+#[get("/auth/<provider>")]
+pub fn oauth_callback(
+    state: State<'_, Context>,
+    token: TokenResponse<OAuthIdentity>,
     mut cookies: Cookies<'_>,
-) -> Redirect {
-    cookies.add_private(
-        Cookie::build(COOKIE_TOKEN, token.access_token().to_string())
-            .same_site(SameSite::Lax)
-            .finish(),
-    );
+    provider: String,
+) -> std::result::Result<Redirect, Status> {
+    let manager = &state.manager;
+    if manager.engine.configuration.identity_provider.name() != provider {
+        return Err(Status::NotFound);
+    }
+    add_login(manager, &mut cookies, token.access_token().to_string());
 
-    Redirect::to(format!("/{}", query_segment(origin)))
+    let query = cookies
+        .get_private(COOKIE_LOGIN_ORIGIN)
+        .map(|cookie| cookie.value().to_string())
+        .unwrap_or_default();
+    cookies.remove_private(Cookie::named(COOKIE_LOGIN_ORIGIN));
+    Ok(Redirect::to(format!("/{}", query)))
 }
 
 #[get("/login?<bearer>")]
-pub fn login(mut cookies: Cookies<'_>, bearer: String) {
+pub fn login(state: State<'_, Context>, mut cookies: Cookies<'_>, bearer: String) {
+    add_login(&state.manager, &mut cookies, bearer)
+}
+
+#[get("/logout")]
+pub fn logout(state: State<'_, Context>, mut cookies: Cookies<'_>) {
+    let manager = &state.manager;
+    if let Some(identity) = cookies
+        .get_private(COOKIE_LOGIN)
+        .and_then(|cookie| manager.logins.get(cookie.value()))
+        .and_then(|login| login.identity)
+    {
+        manager.audit.record(AuditEntry::new(
+            &identity.id,
+            AuditAction::Logout,
+            &identity.id,
+            Value::Null,
+            None,
+        ));
+    }
+    clear(manager, cookies)
+}
+
+fn add_login(manager: &Manager, cookies: &mut Cookies<'_>, access_token: String) {
+    let id = manager.create_login(access_token);
     cookies.add_private(
-        Cookie::build(COOKIE_TOKEN, bearer)
+        Cookie::build(COOKIE_LOGIN, id)
             .same_site(SameSite::Lax)
             .finish(),
-    )
+    );
 }
 
-#[get("/logout")]
-pub fn logout(cookies: Cookies<'_>) {
-    clear(cookies)
+fn clear(manager: &Manager, mut cookies: Cookies<'_>) {
+    if let Some(cookie) = cookies.get_private(COOKIE_LOGIN) {
+        manager.remove_login(cookie.value());
+    }
+    cookies.remove_private(Cookie::named(COOKIE_LOGIN));
+}
+
+/// OpenAPI document describing routes mounted in /api/v1
+#[get("/openapi.json")]
+pub fn openapi(state: State<'_, Context>) -> ApiResponse {
+    match openapi::document(&crate::v1_routes()) {
+        Ok(document) => status::Custom(Status::Ok, JsonValue(document)),
+        Err(err) => error_response(&state.manager.metrics, err),
+    }
+}
+
+/// Flags responses of the unversioned /api routes as deprecated, pointing to their /api/v1 successor
+pub struct Deprecation;
+
+impl Fairing for Deprecation {
+    fn info(&self) -> Info {
+        Info {
+            name: "Deprecation",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let path = match request.uri().path().strip_prefix("/api") {
+            Some(path) if path.is_empty() || path.starts_with('/') => path,
+            _ => return,
+        };
+        if path == "/v1" || path.starts_with("/v1/") {
+            return;
+        }
+        response.set_raw_header("Deprecation", "true");
+        response.set_raw_header(
+            "Link",
+            format!("</api/v1{}>; rel=\"successor-version\"", path),
+        );
+    }
+}
+
+/// Succeeds as long as requests can be handled
+#[get("/health/live")]
+pub fn live() -> JsonValue {
+    json!({ "live": true })
+}
+
+/// Fails with `503` when a critical dependency is unhealthy
+#[get("/health/ready")]
+pub fn ready(state: State<'_, Context>) -> status::Custom<JsonValue> {
+    match state.manager.readiness() {
+        Ok(readiness) if readiness.ready => status::Custom(Status::Ok, json!(readiness)),
+        Ok(readiness) => status::Custom(Status::ServiceUnavailable, json!(readiness)),
+        Err(err) => status::Custom(
+            Status::ServiceUnavailable,
+            json!({ "ready": false, "error": err.to_string() }),
+        ),
+    }
+}
+
+#[get("/version")]
+pub fn version(state: State<'_, Context>) -> JsonValue {
+    json!(state.manager.version())
 }
 
-fn clear(mut cookies: Cookies<'_>) {
-    cookies.remove_private(Cookie::named(COOKIE_TOKEN));
+/// Requests whose credentials are invalid
+#[catch(401)]
+pub fn unauthorized_catcher(_req: &Request<'_>) -> JsonValue {
+    error_body(&Error::Unauthenticated())
 }
 
 #[allow(dead_code)]
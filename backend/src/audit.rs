@@ -0,0 +1,237 @@
+//! Append-only audit log of mutating actions
+//!
+//! Entries are appended as JSON lines to the file at `AUDIT_LOG_PATH`.
+
+use crate::{error::Result, jsonl::JsonLines, kubernetes::unix_time};
+use rocket::FromForm;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{env, time::SystemTime};
+use tracing::error;
+
+const DEFAULT_AUDIT_LOG_PATH: &str = "audit.jsonl";
+
+/// Actor of actions triggered by the playground itself (e.g. session expiration)
+pub const SYSTEM_ACTOR: &str = "playground";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum AuditAction {
+    Login,
+    Logout,
+    CreateUser,
+    UpdateUser,
+    DeleteUser,
+    CreateApiToken,
+    DeleteApiToken,
+    CreateSession,
+    UpdateSession,
+    DeleteSession,
+    MigrateSession,
+    CordonPool,
+    UncordonPool,
+    DrainPool,
+    CordonNode,
+    UncordonNode,
+    DrainNode,
+    ImportTemplate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Seconds since UNIX epoch
+    pub timestamp: u64,
+    pub actor: String,
+    pub action: AuditAction,
+    pub target: String,
+    pub parameters: Value,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        actor: &str,
+        action: AuditAction,
+        target: &str,
+        parameters: Value,
+        error: Option<String>,
+    ) -> Self {
+        AuditEntry {
+            timestamp: unix_time(SystemTime::now()),
+            actor: actor.to_string(),
+            action,
+            target: target.to_string(),
+            parameters,
+            success: error.is_none(),
+            error,
+        }
+    }
+}
+
+/// Criteria entries must all match. `since` and `until` are seconds since UNIX epoch.
+#[derive(FromForm, Clone, Debug, Default, JsonSchema)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub success: Option<bool>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Only keep the `limit` most recent entries
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor
+            .as_ref()
+            .map_or(true, |actor| actor == &entry.actor)
+            && self.action.as_ref().map_or(true, |action| {
+                serde_json::to_value(entry.action).ok() == Some(Value::String(action.clone()))
+            })
+            && self
+                .target
+                .as_ref()
+                .map_or(true, |target| target == &entry.target)
+            && self
+                .success
+                .map_or(true, |success| success == entry.success)
+            && self.since.map_or(true, |since| entry.timestamp >= since)
+            && self.until.map_or(true, |until| entry.timestamp <= until)
+    }
+}
+
+#[derive(Clone)]
+pub struct AuditLog {
+    entries: JsonLines,
+}
+
+impl AuditLog {
+    /// Creates an `AuditLog` backed by `AUDIT_LOG_PATH`, defaults to `audit.jsonl`
+    pub fn from_env() -> Self {
+        AuditLog {
+            entries: JsonLines::new(
+                env::var("AUDIT_LOG_PATH")
+                    .unwrap_or_else(|_| DEFAULT_AUDIT_LOG_PATH.to_string())
+                    .into(),
+            ),
+        }
+    }
+
+    /// Appends `entry`. Failures are logged but don't prevent the action.
+    pub fn record(&self, entry: AuditEntry) {
+        if let Err(err) = self.entries.append(&entry) {
+            error!(
+                user = %entry.actor,
+                action = ?entry.action,
+                target = %entry.target,
+                error = %err,
+                "Failed to record audit entry"
+            );
+        }
+    }
+
+    /// Returns entries matching `filter`, oldest first
+    pub fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        let mut entries = self.entries.read(|entry| filter.matches(entry))?;
+        if let Some(limit) = filter.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonl::tests::temp_lines;
+
+    fn entry(timestamp: u64, actor: &str, action: AuditAction) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            actor: actor.to_string(),
+            action,
+            target: "alice".to_string(),
+            parameters: Value::Null,
+            success: true,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn filter_matches_actor_and_action() {
+        let entry = entry(10, "alice", AuditAction::CreateSession);
+        assert!(AuditFilter::default().matches(&entry));
+        let by_actor = |actor: &str| AuditFilter {
+            actor: Some(actor.to_string()),
+            ..Default::default()
+        };
+        assert!(by_actor("alice").matches(&entry));
+        assert!(!by_actor("bob").matches(&entry));
+        let by_action = |action: &str| AuditFilter {
+            action: Some(action.to_string()),
+            ..Default::default()
+        };
+        assert!(by_action("CreateSession").matches(&entry));
+        assert!(!by_action("DeleteSession").matches(&entry));
+        assert!(!by_action("createSession").matches(&entry));
+    }
+
+    #[test]
+    fn filter_time_bounds_are_inclusive() {
+        let entry = entry(10, "alice", AuditAction::Login);
+        let between = |since, until| AuditFilter {
+            since,
+            until,
+            ..Default::default()
+        };
+        assert!(between(Some(10), Some(10)).matches(&entry));
+        assert!(between(Some(5), None).matches(&entry));
+        assert!(between(None, Some(15)).matches(&entry));
+        assert!(!between(Some(11), None).matches(&entry));
+        assert!(!between(None, Some(9)).matches(&entry));
+    }
+
+    #[test]
+    fn query_keeps_most_recent_entries_oldest_first() {
+        let log = AuditLog {
+            entries: temp_lines("audit"),
+        };
+        for (timestamp, actor) in [(1, "alice"), (2, "alice"), (3, "bob"), (4, "alice")].iter() {
+            log.record(entry(*timestamp, actor, AuditAction::UpdateUser));
+        }
+        let timestamps = |filter: AuditFilter| -> Vec<u64> {
+            log.query(&filter)
+                .unwrap()
+                .iter()
+                .map(|entry| entry.timestamp)
+                .collect()
+        };
+        assert_eq!(timestamps(AuditFilter::default()), vec![1, 2, 3, 4]);
+        assert_eq!(
+            timestamps(AuditFilter {
+                limit: Some(2),
+                ..Default::default()
+            }),
+            vec![3, 4]
+        );
+        assert_eq!(
+            timestamps(AuditFilter {
+                actor: Some("alice".to_string()),
+                limit: Some(2),
+                ..Default::default()
+            }),
+            vec![2, 4]
+        );
+        assert_eq!(
+            timestamps(AuditFilter {
+                actor: Some("bob".to_string()),
+                limit: Some(5),
+                ..Default::default()
+            }),
+            vec![3]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 ///! Error type for the whole project
 ///
+use crate::types::Permission;
+use serde_json::{json, Value};
 use std::result;
 use thiserror::Error;
 
@@ -8,10 +10,86 @@ pub type Result<T> = result::Result<T, Error>;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Unauthorized")]
-    Unauthorized(/*Permission*/),
+    /// No valid credentials have been provided
+    #[error("Unauthenticated")]
+    Unauthenticated(),
+    #[error("Unauthorized: missing permission {0:?}")]
+    Unauthorized(Permission),
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    /// A resource (e.g. `session`) doesn't exist
+    #[error("No matching {0}")]
+    NotFound(&'static str),
+    /// A resource (e.g. `session`) already exists
+    #[error("Existing {0}")]
+    AlreadyExists(&'static str),
+    /// A provided value can't be accepted
+    #[error("Invalid {field}: {reason}")]
+    InvalidValue { field: &'static str, reason: String },
+    /// The request can't be served for now (e.g. pool at capacity)
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
     #[error("Missing data {0}")]
     MissingData(&'static str),
     #[error("Failure: {0}")]
     Failure(#[from] Box<dyn std::error::Error>),
 }
+
+impl Error {
+    /// Name of this error variant
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Unauthenticated() => "Unauthenticated",
+            Error::Unauthorized(_) => "Unauthorized",
+            Error::QuotaExceeded(_) => "QuotaExceeded",
+            Error::NotFound(_) => "NotFound",
+            Error::AlreadyExists(_) => "AlreadyExists",
+            Error::InvalidValue { .. } => "InvalidValue",
+            Error::Unavailable(_) => "Unavailable",
+            Error::MissingData(_) => "MissingData",
+            Error::Failure(_) => "Failure",
+        }
+    }
+
+    /// Stable machine-readable code, part of the API
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Unauthenticated() => "unauthenticated",
+            Error::Unauthorized(_) => "permission_denied",
+            Error::QuotaExceeded(_) => "quota_exceeded",
+            Error::NotFound(_) => "not_found",
+            Error::AlreadyExists(_) => "already_exists",
+            Error::InvalidValue { .. } => "invalid_value",
+            Error::Unavailable(_) => "unavailable",
+            Error::MissingData(_) | Error::Failure(_) => "internal",
+        }
+    }
+
+    /// HTTP status code this error maps to
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::Unauthenticated() => 401,
+            Error::Unauthorized(_) | Error::QuotaExceeded(_) => 403,
+            Error::NotFound(_) => 404,
+            Error::AlreadyExists(_) => 409,
+            Error::InvalidValue { .. } => 422,
+            Error::Unavailable(_) => 503,
+            Error::MissingData(_) | Error::Failure(_) => 500,
+        }
+    }
+
+    /// Machine-readable details, specific to each variant
+    pub fn details(&self) -> Value {
+        match self {
+            Error::Unauthorized(permission) => json!({ "permission": permission }),
+            Error::QuotaExceeded(reason) | Error::Unavailable(reason) => {
+                json!({ "reason": reason })
+            }
+            Error::NotFound(resource) | Error::AlreadyExists(resource) => {
+                json!({ "resource": resource })
+            }
+            Error::InvalidValue { field, reason } => json!({ "field": field, "reason": reason }),
+            Error::Unauthenticated() | Error::MissingData(_) | Error::Failure(_) => Value::Null,
+        }
+    }
+}
@@ -1,18 +1,33 @@
-//! GitHub utility functions
+//! GitHub API client
+//!
+//! Follows `Link` pagination, waits for rate-limits to reset (per `X-RateLimit-*` headers) and retries transient failures.
 
+use crate::telemetry;
 use body::aggregate;
 use core::fmt;
 use hyper::{
     body::{self, Buf},
     client::HttpConnector,
-    header::{AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
-    http::request::Builder,
-    Body, Client, Request,
+    header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE, LINK, RETRY_AFTER, USER_AGENT},
+    Body, Client, Request, Response, StatusCode,
 };
 use hyper_tls::HttpsConnector;
 use serde::de::DeserializeOwned;
 use serde_json::from_reader;
-use std::error::Error as StdError;
+use std::{
+    env,
+    error::Error as StdError,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::sleep;
+use tracing::{instrument, warn};
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+const PER_PAGE: u32 = 100;
+const MAX_RETRIES: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+// Longer rate-limit waits are reported as errors
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
 
 // Custom Error type
 #[derive(Debug)]
@@ -44,6 +59,12 @@ pub struct GitHubOrg {
     pub login: String,
 }
 
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct GitHubTeam {
+    pub slug: String,
+    pub organization: GitHubOrg,
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct GitHubError {
     pub message: String,
@@ -58,58 +79,393 @@ pub struct GitHubClientError {
     pub code: String,
 }
 
-/// Create a new `Client`
-fn create_client() -> Client<HttpsConnector<HttpConnector>> {
-    Client::builder().build(HttpsConnector::new())
+// Extract the `next` URI from a `Link` header
+// e.g. `<https://api.github.com/user/orgs?page=2>; rel="next", <https://api.github.com/user/orgs?page=3>; rel="last"`
+pub(crate) fn next_link(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(LINK)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .find(|link| link.contains("rel=\"next\""))
+        .and_then(|link| {
+            let start = link.find('<')? + 1;
+            let end = link.find('>')?;
+            link.get(start..end).map(String::from)
+        })
 }
 
-/// Create a `Request` `Builder` with necessary headers
-fn create_request_builder(token: &str) -> Builder {
-    Request::builder()
-        .header(CONTENT_TYPE, "application/vnd.github.v3+json")
-        .header(USER_AGENT, "Substrate Playground")
-        .header(AUTHORIZATION, format!("token {}", token))
+fn header_as_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
 }
 
-// Send a fresh `Request` created from a `Builder`, sends it and return the object `T` parsed from JSON.
-async fn send<T>(builder: Builder) -> Result<T, Box<dyn StdError>>
-where
-    T: DeserializeOwned,
-{
-    let client = create_client();
-    let req = builder.body(Body::default())?;
-    let res = client.request(req).await?;
-    let status = res.status();
-    let whole_body = aggregate(res).await?;
-    if status.is_success() {
-        from_reader(whole_body.reader()).map_err(Into::into)
-    } else {
-        let cause: GitHubError = from_reader(whole_body.reader())?;
-        Err(Error { cause }.into())
+// Returns how long to wait before a rate-limited request can be retried, if it has been rate-limited
+fn rate_limit_wait(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    // Secondary rate-limits provide `Retry-After`
+    if let Some(seconds) = header_as_u64(headers, RETRY_AFTER.as_str()) {
+        return Some(Duration::from_secs(seconds));
+    }
+    if header_as_u64(headers, "x-ratelimit-remaining") == Some(0) {
+        let reset = header_as_u64(headers, "x-ratelimit-reset")?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        return Some(Duration::from_secs(reset.saturating_sub(now) + 1));
     }
+    None
 }
 
-///
-/// Returns current GitHubUser represented by a `token`.
-///
-/// # Arguments
-///
-/// * `token` - a github token
-///
-pub async fn current_user(token: &str) -> Result<GitHubUser, Box<dyn StdError>> {
-    let builder = create_request_builder(token).uri("https://api.github.com/user");
-    send(builder).await
+/// A reusable GitHub API client
+#[derive(Clone, Debug)]
+pub struct GitHubClient {
+    client: Client<HttpsConnector<HttpConnector>>,
+    base_uri: String,
 }
 
-///
-/// Returns a Vec<GitHubOrg> associated to a GitHubUser.
-///
-/// # Arguments
-///
-/// * `token` - a github token
-/// * `user` - a GitHubUser
-///
-pub async fn orgs(token: &str, user: &GitHubUser) -> Result<Vec<GitHubOrg>, Box<dyn StdError>> {
-    let builder = create_request_builder(token).uri(user.organizations_url.as_str());
-    send(builder).await
+impl GitHubClient {
+    pub fn new(base_uri: &str) -> Self {
+        GitHubClient {
+            client: Client::builder().build(HttpsConnector::new()),
+            base_uri: base_uri.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Creates a client for the API at `GITHUB_API_URL`, defaults to `https://api.github.com`
+    pub fn from_env() -> Self {
+        GitHubClient::new(
+            &env::var("GITHUB_API_URL").unwrap_or_else(|_| DEFAULT_API_URL.to_string()),
+        )
+    }
+
+    fn uri(&self, path: &str) -> String {
+        format!("{}{}", self.base_uri, path)
+    }
+
+    // Sends a GET request to `uri`, retrying transient failures and rate-limited requests
+    #[instrument(skip_all, fields(uri = %uri))]
+    async fn send(&self, token: &str, uri: &str) -> Result<Response<Body>, Box<dyn StdError>> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut req = Request::builder()
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/vnd.github.v3+json")
+                .header(USER_AGENT, "Substrate Playground")
+                .header(AUTHORIZATION, format!("token {}", token))
+                .body(Body::default())?;
+            telemetry::inject(req.headers_mut());
+            let wait = match self.client.request(req).await {
+                Ok(res) => {
+                    let status = res.status();
+                    match rate_limit_wait(status, res.headers()) {
+                        Some(wait) if wait <= MAX_RATE_LIMIT_WAIT => wait,
+                        Some(wait) => {
+                            return Err(format!("Rate-limited for {}s", wait.as_secs()).into())
+                        }
+                        None if status.is_server_error() && attempt <= MAX_RETRIES => backoff,
+                        None => return Ok(res),
+                    }
+                }
+                Err(err) if attempt <= MAX_RETRIES => {
+                    warn!(uri = %uri, attempt, error = %err, "GitHub call failed, retrying");
+                    backoff
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if attempt > MAX_RETRIES {
+                return Err(format!("Too many retries while calling {}", uri).into());
+            }
+            sleep(wait).await;
+            backoff *= 2;
+        }
+    }
+
+    // Returns the object `T` parsed from JSON, and the `next` page URI if any
+    async fn get_page<T>(
+        &self,
+        token: &str,
+        uri: &str,
+    ) -> Result<(T, Option<String>), Box<dyn StdError>>
+    where
+        T: DeserializeOwned,
+    {
+        let res = self.send(token, uri).await?;
+        let status = res.status();
+        let next = next_link(res.headers());
+        let whole_body = aggregate(res).await?;
+        if status.is_success() {
+            Ok((from_reader(whole_body.reader())?, next))
+        } else {
+            let cause: GitHubError = from_reader(whole_body.reader())?;
+            Err(Error { cause }.into())
+        }
+    }
+
+    async fn get<T>(&self, token: &str, uri: &str) -> Result<T, Box<dyn StdError>>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(self.get_page(token, uri).await?.0)
+    }
+
+    // Returns all elements of a paginated resource
+    async fn get_all<T>(&self, token: &str, uri: &str) -> Result<Vec<T>, Box<dyn StdError>>
+    where
+        T: DeserializeOwned,
+    {
+        let separator = if uri.contains('?') { '&' } else { '?' };
+        let mut next = Some(format!("{}{}per_page={}", uri, separator, PER_PAGE));
+        let mut all = Vec::new();
+        while let Some(uri) = next {
+            let (page, next_uri): (Vec<T>, _) = self.get_page(token, &uri).await?;
+            all.extend(page);
+            next = next_uri;
+        }
+        Ok(all)
+    }
+
+    /// Checks that the API can be reached
+    #[instrument(skip_all)]
+    pub async fn ping(&self) -> Result<(), Box<dyn StdError>> {
+        let mut req = Request::builder()
+            .uri(self.uri("/"))
+            .header(USER_AGENT, "Substrate Playground")
+            .body(Body::default())?;
+        telemetry::inject(req.headers_mut());
+        self.client.request(req).await?;
+        Ok(())
+    }
+
+    ///
+    /// Returns current GitHubUser represented by a `token`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - a github token
+    ///
+    #[instrument(skip_all)]
+    pub async fn current_user(&self, token: &str) -> Result<GitHubUser, Box<dyn StdError>> {
+        self.get(token, &self.uri("/user")).await
+    }
+
+    ///
+    /// Returns all GitHubOrg a GitHubUser belongs to.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - a github token
+    /// * `user` - a GitHubUser
+    ///
+    #[instrument(skip_all, fields(user = %user.login))]
+    pub async fn orgs(
+        &self,
+        token: &str,
+        user: &GitHubUser,
+    ) -> Result<Vec<GitHubOrg>, Box<dyn StdError>> {
+        self.get_all(token, &user.organizations_url).await
+    }
+
+    ///
+    /// Returns all GitHubTeam the user represented by a `token` belongs to.
+    /// Requires the `read:org` scope.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - a github token
+    ///
+    #[instrument(skip_all)]
+    pub async fn teams(&self, token: &str) -> Result<Vec<GitHubTeam>, Box<dyn StdError>> {
+        self.get_all(token, &self.uri("/user/teams")).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use hyper::{
+        header::HeaderValue,
+        service::{make_service_fn, service_fn},
+        Server,
+    };
+    use serde_json::json;
+    use std::{
+        convert::Infallible,
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    type Responder = fn(&str, usize, &str) -> Response<Body>;
+
+    // Serves `respond(url, request index, path and query)` on a local port.
+    // Returns the server URL and the number of requests received.
+    pub(crate) fn serve(respond: Responder) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicUsize::new(0));
+        let (base, requests) = (url.clone(), count.clone());
+        let make_service = make_service_fn(move |_| {
+            let (base, requests) = (base.clone(), requests.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let index = requests.fetch_add(1, Ordering::SeqCst);
+                    let uri = req.uri().to_string();
+                    let res = respond(&base, index, &uri);
+                    async move { Ok::<_, Infallible>(res) }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+        (url, count)
+    }
+
+    pub(crate) fn response(
+        status: StatusCode,
+        headers: &[(&str, String)],
+        body: String,
+    ) -> Response<Body> {
+        let mut builder = Response::builder().status(status);
+        for (name, value) in headers {
+            builder = builder.header(*name, value.as_str());
+        }
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    fn user() -> String {
+        json!({"login": "alice", "organizations_url": ""}).to_string()
+    }
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        values
+            .iter()
+            .map(|(name, value)| {
+                (
+                    hyper::header::HeaderName::from_static(name),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn next_link_is_parsed() {
+        let link = r#"<https://api.github.com/user/orgs?page=2>; rel="next", <https://api.github.com/user/orgs?page=3>; rel="last""#;
+        assert_eq!(
+            next_link(&headers(&[("link", link)])),
+            Some("https://api.github.com/user/orgs?page=2".to_string())
+        );
+        let last = r#"<https://api.github.com/user/orgs?page=1>; rel="prev", <https://api.github.com/user/orgs?page=1>; rel="first""#;
+        assert_eq!(next_link(&headers(&[("link", last)])), None);
+        assert_eq!(next_link(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn rate_limit_wait_follows_headers() {
+        assert_eq!(
+            rate_limit_wait(StatusCode::OK, &headers(&[("retry-after", "5")])),
+            None
+        );
+        assert_eq!(
+            rate_limit_wait(StatusCode::FORBIDDEN, &headers(&[("retry-after", "5")])),
+            Some(Duration::from_secs(5))
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let reset = (now + 10).to_string();
+        let wait = rate_limit_wait(
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers(&[
+                ("x-ratelimit-remaining", "0"),
+                ("x-ratelimit-reset", &reset),
+            ]),
+        )
+        .unwrap();
+        assert!(wait >= Duration::from_secs(10) && wait <= Duration::from_secs(11));
+        // Not rate-limited, e.g. missing permissions
+        assert_eq!(
+            rate_limit_wait(
+                StatusCode::FORBIDDEN,
+                &headers(&[("x-ratelimit-remaining", "10")])
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let (url, count) = serve(|_, index, _| match index {
+            0 | 1 => response(StatusCode::BAD_GATEWAY, &[], String::new()),
+            _ => response(StatusCode::OK, &[], user()),
+        });
+        let user = GitHubClient::new(&url).current_user("token").await.unwrap();
+        assert_eq!(user.login, "alice");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_are_bounded() {
+        let (url, count) =
+            serve(|_, _, _| response(StatusCode::INTERNAL_SERVER_ERROR, &[], String::new()));
+        assert!(GitHubClient::new(&url).current_user("token").await.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), MAX_RETRIES as usize + 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_are_retried_after_wait() {
+        let (url, count) = serve(|_, index, _| match index {
+            0 => response(
+                StatusCode::FORBIDDEN,
+                &[("retry-after", "1".to_string())],
+                String::new(),
+            ),
+            _ => response(StatusCode::OK, &[], user()),
+        });
+        assert!(GitHubClient::new(&url).current_user("token").await.is_ok());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn long_rate_limits_fail() {
+        let (url, count) = serve(|_, _, _| {
+            response(
+                StatusCode::TOO_MANY_REQUESTS,
+                &[("retry-after", "3600".to_string())],
+                String::new(),
+            )
+        });
+        assert!(GitHubClient::new(&url).current_user("token").await.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn all_pages_are_fetched() {
+        let (url, count) = serve(|base, index, uri| {
+            let team = |slug: &str| json!({"slug": slug, "organization": {"login": "org"}});
+            match index {
+                0 => {
+                    assert_eq!(uri, "/user/teams?per_page=100");
+                    let link = format!(r#"<{}/user/teams?per_page=100&page=2>; rel="next""#, base);
+                    response(
+                        StatusCode::OK,
+                        &[("link", link)],
+                        json!([team("a"), team("b")]).to_string(),
+                    )
+                }
+                _ => {
+                    assert_eq!(uri, "/user/teams?per_page=100&page=2");
+                    response(StatusCode::OK, &[], json!([team("c")]).to_string())
+                }
+            }
+        });
+        let teams = GitHubClient::new(&url).teams("token").await.unwrap();
+        let slugs: Vec<&str> = teams.iter().map(|team| team.slug.as_str()).collect();
+        assert_eq!(slugs, vec!["a", "b", "c"]);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
 }
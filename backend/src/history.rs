@@ -0,0 +1,263 @@
+//! History of finished sessions, used for usage reporting
+//!
+//! Records are appended as JSON lines to the file at `SESSION_HISTORY_PATH`.
+
+use crate::{
+    error::{Error, Result},
+    jsonl::JsonLines,
+    types::{SessionEndReason, SessionRecord, UsageReport},
+};
+use rocket::FromForm;
+use schemars::JsonSchema;
+use std::{collections::BTreeMap, env, str::FromStr};
+
+const DEFAULT_SESSION_HISTORY_PATH: &str = "sessions.jsonl";
+
+/// Criteria records must all match. `since` and `until` are seconds since UNIX epoch.
+#[derive(FromForm, Clone, Debug, Default, JsonSchema)]
+pub struct HistoryFilter {
+    pub user: Option<String>,
+    pub template: Option<String>,
+    pub pool: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl HistoryFilter {
+    // Records overlapping the date range are kept
+    fn matches(&self, record: &SessionRecord) -> bool {
+        self.user
+            .as_ref()
+            .map_or(true, |user| user == &record.creator)
+            && self
+                .template
+                .as_ref()
+                .map_or(true, |template| template == &record.template)
+            && self
+                .pool
+                .as_ref()
+                .map_or(true, |pool| Some(pool) == record.pool.as_ref())
+            && self.since.map_or(true, |since| record.end >= since)
+            && self.until.map_or(true, |until| record.start <= until)
+    }
+}
+
+/// How usage is aggregated
+#[derive(Clone, Copy, Debug)]
+pub enum UsageGroup {
+    User,
+    Template,
+    Pool,
+}
+
+impl FromStr for UsageGroup {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "user" => Ok(UsageGroup::User),
+            "template" => Ok(UsageGroup::Template),
+            "pool" => Ok(UsageGroup::Pool),
+            _ => Err(Error::InvalidValue {
+                field: "group",
+                reason: "must be one of user, template or pool".to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionHistory {
+    records: JsonLines,
+}
+
+impl SessionHistory {
+    /// Creates a `SessionHistory` backed by `SESSION_HISTORY_PATH`, defaults to `sessions.jsonl`
+    pub fn from_env() -> Self {
+        SessionHistory {
+            records: JsonLines::new(
+                env::var("SESSION_HISTORY_PATH")
+                    .unwrap_or_else(|_| DEFAULT_SESSION_HISTORY_PATH.to_string())
+                    .into(),
+            ),
+        }
+    }
+
+    pub fn record(&self, record: &SessionRecord) -> Result<()> {
+        self.records.append(record)
+    }
+
+    /// Returns records matching `filter`, oldest first
+    pub fn list(&self, filter: &HistoryFilter) -> Result<Vec<SessionRecord>> {
+        self.records.read(|record| filter.matches(record))
+    }
+
+    /// Aggregates usage of records matching `filter` per `group`. Session hours are restricted to the date range.
+    pub fn usage(
+        &self,
+        filter: &HistoryFilter,
+        group: UsageGroup,
+    ) -> Result<BTreeMap<String, UsageReport>> {
+        let mut reports: BTreeMap<String, UsageReport> = BTreeMap::new();
+        let mut deploy_durations: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+        for record in self.list(filter)? {
+            let key = match group {
+                UsageGroup::User => record.creator.clone(),
+                UsageGroup::Template => record.template.clone(),
+                UsageGroup::Pool => record.pool.clone().unwrap_or_else(|| "unknown".to_string()),
+            };
+            let start = filter
+                .since
+                .map_or(record.start, |since| record.start.max(since));
+            let end = filter
+                .until
+                .map_or(record.end, |until| record.end.min(until));
+            let report = reports.entry(key.clone()).or_default();
+            // Migrated sessions go on as another record
+            if record.end_reason != SessionEndReason::Migrated {
+                report.sessions += 1;
+            }
+            report.session_hours += end.saturating_sub(start) as f64 / 3600.0;
+            if record.end_reason == SessionEndReason::Failure {
+                report.failures += 1;
+            }
+            if let Some(deploy_duration) = record.deploy_duration {
+                deploy_durations
+                    .entry(key)
+                    .or_default()
+                    .push(deploy_duration);
+            }
+        }
+        for (key, durations) in deploy_durations {
+            if let Some(report) = reports.get_mut(&key) {
+                report.average_deploy_duration =
+                    Some(durations.iter().sum::<u64>() as f64 / durations.len() as f64);
+            }
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonl::tests::temp_lines;
+
+    const HOUR: u64 = 3600;
+
+    fn record(
+        creator: &str,
+        template: &str,
+        start: u64,
+        end: u64,
+        end_reason: SessionEndReason,
+    ) -> SessionRecord {
+        SessionRecord {
+            id: creator.to_lowercase(),
+            user_id: creator.to_string(),
+            creator: creator.to_string(),
+            template: template.to_string(),
+            pool: Some("default".to_string()),
+            node: "node-1".to_string(),
+            start,
+            end,
+            end_reason,
+            deploy_duration: Some(10),
+        }
+    }
+
+    fn history(name: &str, records: &[SessionRecord]) -> SessionHistory {
+        let history = SessionHistory {
+            records: temp_lines(name),
+        };
+        for record in records {
+            history.record(record).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn usage_is_clipped_to_date_range() {
+        let history = history(
+            "clipped",
+            &[
+                record("alice", "node", 0, 4 * HOUR, SessionEndReason::Expired),
+                record(
+                    "alice",
+                    "node",
+                    10 * HOUR,
+                    11 * HOUR,
+                    SessionEndReason::User,
+                ),
+            ],
+        );
+        let usage = |since, until| {
+            history
+                .usage(
+                    &HistoryFilter {
+                        since,
+                        until,
+                        ..Default::default()
+                    },
+                    UsageGroup::User,
+                )
+                .unwrap()
+        };
+        assert_eq!(usage(None, None)["alice"].session_hours, 5.0);
+        assert_eq!(usage(Some(3 * HOUR), None)["alice"].session_hours, 2.0);
+        assert_eq!(usage(None, Some(HOUR))["alice"].session_hours, 1.0);
+        let clipped = usage(Some(HOUR), Some(2 * HOUR));
+        assert_eq!(clipped["alice"].sessions, 1);
+        assert_eq!(clipped["alice"].session_hours, 1.0);
+        assert!(usage(Some(5 * HOUR), Some(9 * HOUR)).is_empty());
+    }
+
+    #[test]
+    fn usage_is_aggregated_per_group() {
+        let history = history(
+            "aggregated",
+            &[
+                record("alice", "node", 0, HOUR, SessionEndReason::Expired),
+                record("bob", "node", 0, 2 * HOUR, SessionEndReason::User),
+                record(
+                    "alice",
+                    "kitties",
+                    HOUR,
+                    3 * HOUR,
+                    SessionEndReason::Failure,
+                ),
+            ],
+        );
+        let per_user = history
+            .usage(&HistoryFilter::default(), UsageGroup::User)
+            .unwrap();
+        assert_eq!(per_user.len(), 2);
+        assert_eq!(per_user["alice"].sessions, 2);
+        assert_eq!(per_user["alice"].session_hours, 3.0);
+        assert_eq!(per_user["alice"].failures, 1);
+        assert_eq!(per_user["alice"].average_deploy_duration, Some(10.0));
+        assert_eq!(per_user["bob"].sessions, 1);
+        assert_eq!(per_user["bob"].session_hours, 2.0);
+
+        let per_template = history
+            .usage(&HistoryFilter::default(), UsageGroup::Template)
+            .unwrap();
+        assert_eq!(per_template["node"].sessions, 2);
+        assert_eq!(per_template["kitties"].sessions, 1);
+    }
+
+    #[test]
+    fn migrated_sessions_are_counted_once() {
+        let history = history(
+            "migrated",
+            &[
+                record("alice", "node", 0, HOUR, SessionEndReason::Migrated),
+                record("alice", "node", HOUR, 3 * HOUR, SessionEndReason::Expired),
+            ],
+        );
+        let usage = history
+            .usage(&HistoryFilter::default(), UsageGroup::User)
+            .unwrap();
+        assert_eq!(usage["alice"].sessions, 1);
+        assert_eq!(usage["alice"].session_hours, 3.0);
+    }
+}
@@ -0,0 +1,594 @@
+//! Identity providers used to authenticate users
+//!
+//! A single provider is configured per deployment via `IDENTITY_PROVIDER` (`github`, `gitlab` or `oidc`).
+//! Organizations (GitHub), groups (GitLab) and group claims (OpenID Connect) all end up as `LoggedUser#organizations`.
+//! GitLab groups are paginated, following the `Link` header or, when missing, the `X-Next-Page` one.
+
+use crate::{
+    error::{Error, Result},
+    github::{next_link, GitHubClient},
+    telemetry,
+};
+use body::aggregate;
+use hyper::{
+    body::{self, Buf},
+    header::{HeaderMap, ACCEPT, AUTHORIZATION, USER_AGENT},
+    Body, Client, Request,
+};
+use hyper_tls::HttpsConnector;
+use rocket_oauth2::{OAuthConfig, StaticProvider};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{from_reader, Value};
+use std::{env, error::Error as StdError};
+use tracing::instrument;
+
+const DEFAULT_GITLAB_URL: &str = "https://gitlab.com";
+const GITLAB_PER_PAGE: u32 = 100;
+const DEFAULT_OIDC_SCOPES: &str = "openid profile groups";
+const DEFAULT_OIDC_USERNAME_CLAIM: &str = "preferred_username";
+const DEFAULT_OIDC_GROUPS_CLAIM: &str = "groups";
+
+/// Marker type for the OAuth2 flow, whatever the configured provider
+pub struct OAuthIdentity;
+
+/// The identity of an authenticated user, as returned by an `IdentityProvider`
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub id: String,
+    pub organizations: Vec<String>,
+    /// Teams, as `org/team`. Only provided by GitHub.
+    pub teams: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum IdentityProvider {
+    #[serde(rename = "github")]
+    GitHub {
+        #[serde(skip)]
+        client: GitHubClient,
+    },
+    #[serde(rename = "gitlab")]
+    GitLab { url: String },
+    #[serde(rename = "oidc", rename_all = "camelCase")]
+    Oidc {
+        issuer_url: String,
+        #[serde(skip)]
+        auth_uri: String,
+        #[serde(skip)]
+        token_uri: String,
+        #[serde(skip)]
+        userinfo_uri: String,
+        #[serde(skip)]
+        scopes: Vec<String>,
+        #[serde(skip)]
+        username_claim: String,
+        #[serde(skip)]
+        groups_claim: String,
+    },
+}
+
+/// Settings of an OpenID Connect provider, whose endpoints are discovered from `issuer_url`
+#[derive(Clone, Debug)]
+pub struct OidcConfiguration {
+    pub issuer_url: String,
+    pub scopes: Vec<String>,
+    pub username_claim: String,
+    pub groups_claim: String,
+}
+
+impl OidcConfiguration {
+    pub fn from_env() -> Result<Self> {
+        Ok(OidcConfiguration {
+            issuer_url: required_env("OIDC_ISSUER_URL")?,
+            scopes: env_or("OIDC_SCOPES", DEFAULT_OIDC_SCOPES)
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            username_claim: env_or("OIDC_USERNAME_CLAIM", DEFAULT_OIDC_USERNAME_CLAIM),
+            groups_claim: env_or("OIDC_GROUPS_CLAIM", DEFAULT_OIDC_GROUPS_CLAIM),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct OidcDiscovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitLabUser {
+    username: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitLabGroup {
+    full_path: String,
+}
+
+// Send a GET request to `uri`, succeeding whatever the response status is
+#[instrument(skip_all, fields(uri = %uri))]
+async fn ping(uri: &str) -> std::result::Result<(), Box<dyn StdError>> {
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let mut req = Request::builder()
+        .uri(uri)
+        .header(USER_AGENT, "Substrate Playground")
+        .body(Body::default())?;
+    telemetry::inject(req.headers_mut());
+    client.request(req).await?;
+    Ok(())
+}
+
+// Send a GET request to `uri` and return the object `T` parsed from JSON, along with the response headers
+#[instrument(skip_all, fields(uri = %uri))]
+async fn get_json_with_headers<T>(
+    uri: &str,
+    token: Option<&str>,
+) -> std::result::Result<(T, HeaderMap), Box<dyn StdError>>
+where
+    T: DeserializeOwned,
+{
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let mut builder = Request::builder()
+        .uri(uri)
+        .header(ACCEPT, "application/json")
+        .header(USER_AGENT, "Substrate Playground");
+    if let Some(token) = token {
+        builder = builder.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let mut req = builder.body(Body::default())?;
+    telemetry::inject(req.headers_mut());
+    let res = client.request(req).await?;
+    let status = res.status();
+    let headers = res.headers().clone();
+    let whole_body = aggregate(res).await?;
+    if status.is_success() {
+        Ok((from_reader(whole_body.reader())?, headers))
+    } else {
+        Err(format!("Request to {} failed with status {}", uri, status).into())
+    }
+}
+
+// Send a GET request to `uri` and return the object `T` parsed from JSON
+async fn get_json<T>(uri: &str, token: Option<&str>) -> std::result::Result<T, Box<dyn StdError>>
+where
+    T: DeserializeOwned,
+{
+    Ok(get_json_with_headers(uri, token).await?.0)
+}
+
+// Returns the URI of the page following `first_uri`, per GitLab pagination headers.
+// `X-Next-Page` is empty on the last page; `Link` might be omitted for large collections.
+fn gitlab_next_page(first_uri: &str, headers: &HeaderMap) -> Option<String> {
+    if let Some(link) = next_link(headers) {
+        return Some(link);
+    }
+    let page = headers.get("x-next-page")?.to_str().ok()?.trim();
+    if page.is_empty() {
+        return None;
+    }
+    Some(format!("{}&page={}", first_uri, page))
+}
+
+// Returns all groups the user represented by `token` is a member of
+async fn gitlab_groups(
+    url: &str,
+    token: &str,
+) -> std::result::Result<Vec<GitLabGroup>, Box<dyn StdError>> {
+    let first_uri = format!(
+        "{}/api/v4/groups?min_access_level=10&per_page={}",
+        url, GITLAB_PER_PAGE
+    );
+    let mut next = Some(first_uri.clone());
+    let mut all = Vec::new();
+    while let Some(uri) = next {
+        let (page, headers): (Vec<GitLabGroup>, _) =
+            get_json_with_headers(&uri, Some(token)).await?;
+        all.extend(page);
+        next = gitlab_next_page(&first_uri, &headers);
+    }
+    Ok(all)
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+fn required_env(name: &'static str) -> Result<String> {
+    env::var(name).map_err(|_| Error::MissingData(name))
+}
+
+// Group paths might be provided as `/org/team`
+fn claim_to_organizations(claim: Option<&Value>) -> Vec<String> {
+    match claim {
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|group| group.trim_start_matches('/').to_string())
+            .collect(),
+        Some(Value::String(group)) => vec![group.trim_start_matches('/').to_string()],
+        _ => Vec::new(),
+    }
+}
+
+impl IdentityProvider {
+    /// Creates the `IdentityProvider` configured via env variables.
+    /// For OpenID Connect, endpoints are retrieved from the issuer discovery document.
+    pub async fn from_env() -> Result<Self> {
+        match env_or("IDENTITY_PROVIDER", "github").as_str() {
+            "github" => Ok(IdentityProvider::GitHub {
+                client: GitHubClient::from_env(),
+            }),
+            "gitlab" => Ok(IdentityProvider::GitLab {
+                url: env_or("GITLAB_URL", DEFAULT_GITLAB_URL)
+                    .trim_end_matches('/')
+                    .to_string(),
+            }),
+            "oidc" => IdentityProvider::oidc(OidcConfiguration::from_env()?).await,
+            _ => Err(Error::MissingData("IDENTITY_PROVIDER")),
+        }
+    }
+
+    /// Creates an OpenID Connect `IdentityProvider`, retrieving endpoints from the issuer discovery document
+    pub async fn oidc(configuration: OidcConfiguration) -> Result<Self> {
+        let issuer_url = configuration.issuer_url.trim_end_matches('/').to_string();
+        let discovery: OidcDiscovery = get_json(
+            &format!("{}/.well-known/openid-configuration", issuer_url),
+            None,
+        )
+        .await
+        .map_err(Error::Failure)?;
+        Ok(IdentityProvider::Oidc {
+            issuer_url,
+            auth_uri: discovery.authorization_endpoint,
+            token_uri: discovery.token_endpoint,
+            userinfo_uri: discovery.userinfo_endpoint,
+            scopes: configuration.scopes,
+            username_claim: configuration.username_claim,
+            groups_claim: configuration.groups_claim,
+        })
+    }
+
+    /// Name used in login routes
+    pub fn name(&self) -> &'static str {
+        match self {
+            IdentityProvider::GitHub { .. } => "github",
+            IdentityProvider::GitLab { .. } => "gitlab",
+            IdentityProvider::Oidc { .. } => "oidc",
+        }
+    }
+
+    /// Name of the env variables prefix holding the client ID and secret
+    pub fn env_prefix(&self) -> &'static str {
+        match self {
+            IdentityProvider::GitHub { .. } => "GITHUB",
+            IdentityProvider::GitLab { .. } => "GITLAB",
+            IdentityProvider::Oidc { .. } => "OIDC",
+        }
+    }
+
+    pub fn auth_uri(&self) -> String {
+        match self {
+            IdentityProvider::GitHub { .. } => {
+                "https://github.com/login/oauth/authorize".to_string()
+            }
+            IdentityProvider::GitLab { url } => format!("{}/oauth/authorize", url),
+            IdentityProvider::Oidc { auth_uri, .. } => auth_uri.clone(),
+        }
+    }
+
+    pub fn token_uri(&self) -> String {
+        match self {
+            IdentityProvider::GitHub { .. } => {
+                "https://github.com/login/oauth/access_token".to_string()
+            }
+            IdentityProvider::GitLab { url } => format!("{}/oauth/token", url),
+            IdentityProvider::Oidc { token_uri, .. } => token_uri.clone(),
+        }
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        match self {
+            IdentityProvider::GitHub { .. } => {
+                vec!["user:read".to_string(), "read:org".to_string()]
+            }
+            IdentityProvider::GitLab { .. } => vec!["read_api".to_string()],
+            IdentityProvider::Oidc { scopes, .. } => scopes.clone(),
+        }
+    }
+
+    /// URI providers redirect to once users are authenticated. It must be registered as is with the provider.
+    pub fn callback_uri(&self, base_url: &str) -> String {
+        format!("{}/api/auth/{}", base_url, self.name())
+    }
+
+    /// The OAuth2 configuration of this provider. `callback_uri` is sent both when authorizing and exchanging codes.
+    pub fn oauth_config(
+        &self,
+        client_id: String,
+        client_secret: String,
+        base_url: &str,
+    ) -> OAuthConfig {
+        OAuthConfig::new(
+            StaticProvider {
+                auth_uri: self.auth_uri().into(),
+                token_uri: self.token_uri().into(),
+            },
+            client_id,
+            client_secret,
+            Some(self.callback_uri(base_url)),
+        )
+    }
+
+    ///
+    /// Returns the `Identity` represented by a `token`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - an access token issued by this provider
+    ///
+    #[instrument(skip_all, fields(provider = self.name()))]
+    pub async fn identity(&self, token: &str) -> std::result::Result<Identity, Box<dyn StdError>> {
+        match self {
+            IdentityProvider::GitHub { client } => {
+                let user = client.current_user(token).await?;
+                let organizations = client
+                    .orgs(token, &user)
+                    .await
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|org| org.login)
+                    .collect();
+                let teams = client
+                    .teams(token)
+                    .await
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|team| format!("{}/{}", team.organization.login, team.slug))
+                    .collect();
+                Ok(Identity {
+                    id: user.login,
+                    organizations,
+                    teams,
+                })
+            }
+            IdentityProvider::GitLab { url } => {
+                let user: GitLabUser =
+                    get_json(&format!("{}/api/v4/user", url), Some(token)).await?;
+                let groups = gitlab_groups(url, token).await.unwrap_or_default();
+                Ok(Identity {
+                    id: user.username,
+                    organizations: groups.into_iter().map(|group| group.full_path).collect(),
+                    teams: Vec::new(),
+                })
+            }
+            IdentityProvider::Oidc {
+                userinfo_uri,
+                username_claim,
+                groups_claim,
+                ..
+            } => {
+                let claims: Value = get_json(userinfo_uri, Some(token)).await?;
+                let id = claims
+                    .get(username_claim)
+                    .or_else(|| claims.get("sub"))
+                    .and_then(Value::as_str)
+                    .ok_or("Missing username claim")?
+                    .to_string();
+                Ok(Identity {
+                    id,
+                    organizations: claim_to_organizations(claims.get(groups_claim)),
+                    teams: Vec::new(),
+                })
+            }
+        }
+    }
+
+    /// Checks that the provider API can be reached
+    pub async fn check(&self) -> std::result::Result<(), Box<dyn StdError>> {
+        match self {
+            IdentityProvider::GitHub { client } => client.ping().await,
+            IdentityProvider::GitLab { url } => ping(&format!("{}/api/v4/version", url)).await,
+            IdentityProvider::Oidc { userinfo_uri, .. } => ping(userinfo_uri).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::tests::{response, serve};
+    use hyper::{
+        header::HeaderValue,
+        server::Server,
+        service::{make_service_fn, service_fn},
+        Response, StatusCode,
+    };
+    use rocket_oauth2::{Adapter, HyperSyncRustlsAdapter, TokenRequest};
+    use serde_json::json;
+    use std::{
+        convert::Infallible,
+        net::TcpListener,
+        sync::{atomic::Ordering, Arc, Mutex},
+    };
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        values
+            .iter()
+            .map(|(name, value)| {
+                (
+                    hyper::header::HeaderName::from_static(name),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gitlab_next_page_follows_headers() {
+        let first = "https://gitlab.com/api/v4/groups?per_page=100";
+        let link = r#"<https://gitlab.com/api/v4/groups?per_page=100&page=2>; rel="next""#;
+        assert_eq!(
+            gitlab_next_page(first, &headers(&[("link", link), ("x-next-page", "3")])),
+            Some("https://gitlab.com/api/v4/groups?per_page=100&page=2".to_string())
+        );
+        assert_eq!(
+            gitlab_next_page(first, &headers(&[("x-next-page", "2")])),
+            Some("https://gitlab.com/api/v4/groups?per_page=100&page=2".to_string())
+        );
+        assert_eq!(
+            gitlab_next_page(first, &headers(&[("x-next-page", "")])),
+            None
+        );
+        assert_eq!(gitlab_next_page(first, &HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn all_gitlab_groups_are_fetched() {
+        let (url, count) = serve(|_, _, uri| {
+            let group = |path: &str| json!({ "full_path": path });
+            match uri {
+                "/api/v4/user" => response(
+                    StatusCode::OK,
+                    &[],
+                    json!({"username": "alice"}).to_string(),
+                ),
+                "/api/v4/groups?min_access_level=10&per_page=100" => response(
+                    StatusCode::OK,
+                    &[("x-next-page", "2".to_string())],
+                    json!([group("a"), group("a/b")]).to_string(),
+                ),
+                "/api/v4/groups?min_access_level=10&per_page=100&page=2" => response(
+                    StatusCode::OK,
+                    &[("x-next-page", String::new())],
+                    json!([group("c")]).to_string(),
+                ),
+                _ => response(StatusCode::NOT_FOUND, &[], String::new()),
+            }
+        });
+        let identity = IdentityProvider::GitLab { url }
+            .identity("token")
+            .await
+            .unwrap();
+        assert_eq!(identity.id, "alice");
+        assert_eq!(identity.organizations, vec!["a", "a/b", "c"]);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn oidc_identity_is_read_from_discovered_userinfo() {
+        let (url, _) = serve(|base, _, uri| match uri {
+            "/.well-known/openid-configuration" => response(
+                StatusCode::OK,
+                &[],
+                json!({
+                    "issuer": base,
+                    "authorization_endpoint": format!("{}/authorize", base),
+                    "token_endpoint": format!("{}/token", base),
+                    "userinfo_endpoint": format!("{}/userinfo", base),
+                })
+                .to_string(),
+            ),
+            "/userinfo" => response(
+                StatusCode::OK,
+                &[],
+                json!({
+                    "sub": "1234",
+                    "preferred_username": "alice",
+                    "groups": ["/org", "/org/team", "other"],
+                })
+                .to_string(),
+            ),
+            _ => response(StatusCode::NOT_FOUND, &[], String::new()),
+        });
+        let provider = IdentityProvider::oidc(OidcConfiguration {
+            issuer_url: format!("{}/", url),
+            scopes: vec![
+                "openid".to_string(),
+                "profile".to_string(),
+                "groups".to_string(),
+            ],
+            username_claim: DEFAULT_OIDC_USERNAME_CLAIM.to_string(),
+            groups_claim: DEFAULT_OIDC_GROUPS_CLAIM.to_string(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(provider.auth_uri(), format!("{}/authorize", url));
+        assert_eq!(provider.token_uri(), format!("{}/token", url));
+        assert_eq!(provider.scopes(), vec!["openid", "profile", "groups"]);
+
+        let identity = provider.identity("token").await.unwrap();
+        assert_eq!(identity.id, "alice");
+        assert_eq!(identity.organizations, vec!["org", "org/team", "other"]);
+        assert!(identity.teams.is_empty());
+        assert!(provider.check().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn callback_uri_is_sent_when_exchanging_codes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        let make_service = make_service_fn(move |_| {
+            let bodies = bodies.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let bodies = bodies.clone();
+                    async move {
+                        let body = body::to_bytes(request.into_body()).await?;
+                        bodies
+                            .lock()
+                            .unwrap()
+                            .push(String::from_utf8_lossy(&body).to_string());
+                        Ok::<_, hyper::Error>(Response::new(Body::from(
+                            json!({"access_token": "token", "token_type": "bearer"}).to_string(),
+                        )))
+                    }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+
+        let provider = IdentityProvider::GitLab { url };
+        let config = provider.oauth_config(
+            "id".to_string(),
+            "secret".to_string(),
+            "https://playground.example.com",
+        );
+        let redirect_uri =
+            "redirect_uri=https%3A%2F%2Fplayground.example.com%2Fapi%2Fauth%2Fgitlab";
+        let adapter = HyperSyncRustlsAdapter::default().basic_auth(false);
+        let authorization_uri = adapter
+            .authorization_uri(&config, "state", &["read_api"], &[])
+            .unwrap();
+        assert!(authorization_uri.to_string().contains(redirect_uri));
+
+        let token = tokio::task::spawn_blocking(move || {
+            adapter
+                .exchange_code(&config, TokenRequest::AuthorizationCode("code".to_string()))
+                .map(|token| token.access_token().to_string())
+                .map_err(|err| format!("{:?}", err))
+        })
+        .await
+        .unwrap();
+        assert_eq!(token, Ok("token".to_string()));
+        let bodies = received.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert!(bodies[0].contains("code=code"));
+        assert!(bodies[0].contains(redirect_uri));
+    }
+
+    #[test]
+    fn groups_claim_might_be_a_single_group() {
+        assert_eq!(
+            claim_to_organizations(Some(&json!("/org"))),
+            vec!["org".to_string()]
+        );
+        assert!(claim_to_organizations(Some(&json!(42))).is_empty());
+        assert!(claim_to_organizations(None).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! Append-only files of JSON lines
+
+use crate::error::{Error, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tracing::error;
+
+#[derive(Clone)]
+pub struct JsonLines {
+    path: PathBuf,
+    // Serializes writes
+    lock: Arc<Mutex<()>>,
+}
+
+impl JsonLines {
+    pub fn new(path: PathBuf) -> Self {
+        JsonLines {
+            path,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn append<T: Serialize>(&self, value: &T) -> Result<()> {
+        let line = serde_json::to_string(value).map_err(|err| Error::Failure(err.into()))?;
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| Error::Failure("Failed to acquire lock".into()))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| Error::Failure(err.into()))?;
+        writeln!(file, "{}", line).map_err(|err| Error::Failure(err.into()))
+    }
+
+    /// Returns all values matching `filter`, oldest first. Invalid lines are skipped.
+    pub fn read<T: DeserializeOwned>(&self, filter: impl Fn(&T) -> bool) -> Result<Vec<T>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(Error::Failure(err.into())),
+        };
+        let mut values = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|err| Error::Failure(err.into()))?;
+            match serde_json::from_str::<T>(&line) {
+                Ok(value) if filter(&value) => values.push(value),
+                Ok(_) => {}
+                Err(err) => error!(path = %self.path.display(), error = %err, "Invalid line"),
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::{env, fs, process};
+
+    /// A `JsonLines` backed by a fresh temporary file, `name` must be unique among tests
+    pub(crate) fn temp_lines(name: &str) -> JsonLines {
+        let path = env::temp_dir().join(format!("playground-{}-{}.jsonl", name, process::id()));
+        let _ = fs::remove_file(&path);
+        JsonLines::new(path)
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Line {
+        id: u32,
+    }
+
+    #[test]
+    fn missing_files_are_empty() {
+        let lines = temp_lines("missing");
+        assert!(lines.read::<Line>(|_| true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_lines_are_skipped() {
+        let lines = temp_lines("invalid");
+        lines.append(&Line { id: 1 }).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&lines.path).unwrap();
+        writeln!(file, "{{\"id\": ").unwrap();
+        writeln!(file, "\"not a line\"").unwrap();
+        lines.append(&Line { id: 2 }).unwrap();
+        lines.append(&Line { id: 3 }).unwrap();
+
+        assert_eq!(
+            lines.read::<Line>(|_| true).unwrap(),
+            vec![Line { id: 1 }, Line { id: 2 }, Line { id: 3 }]
+        );
+        assert_eq!(
+            lines.read::<Line>(|line| line.id > 1).unwrap(),
+            vec![Line { id: 2 }, Line { id: 3 }]
+        );
+    }
+}
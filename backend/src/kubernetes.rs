@@ -1,10 +1,16 @@
 //! Helper methods ton interact with k8s
 use crate::{
     error::{Error, Result},
+    identity::IdentityProvider,
+    login::StoredLogin,
+    placement::{place, Candidate},
+    scaling::{plan, provisioner_from_env, target_size, NodeProvisioner, ScalingPlan},
+    settings::{self, Reloadable},
     types::{
-        self, ContainerPhase, LoggedUser, Phase, Pool, Session, SessionConfiguration,
-        SessionDefaults, SessionUpdateConfiguration, Template, User, UserConfiguration,
-        UserUpdateConfiguration,
+        self, ApiToken, ContainerPhase, HealthCheck, LoggedUser, Organization, Phase, Pool,
+        PoolConfiguration, PoolScaling, Resources, Role, Session, SessionConfiguration,
+        SessionDefaults, SessionUpdateConfiguration, Template, TemplateImage, Usage, User,
+        UserConfiguration, UserUpdateConfiguration,
     },
 };
 use json_patch::{AddOperation, PatchOperation, RemoveOperation};
@@ -14,7 +20,8 @@ use k8s_openapi::{
         core::v1::{
             Affinity, ConfigMap, Container, ContainerStatus, EnvVar, Node, NodeAffinity,
             NodeSelector, NodeSelectorRequirement, NodeSelectorTerm, Pod, PodSpec,
-            ResourceRequirements, Service, ServicePort, ServiceSpec,
+            PreferredSchedulingTerm, ResourceRequirements, Secret, Service, ServicePort,
+            ServiceSpec, Toleration,
         },
         networking::v1::{
             HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
@@ -22,21 +29,30 @@ use k8s_openapi::{
         },
     },
     apimachinery::pkg::api::resource::Quantity,
+    ByteString,
 };
 use kube::{
     api::{Api, DeleteParams, ListParams, Patch, PatchParams, PostParams},
     config::KubeConfigOptions,
     Client, Config,
 };
-use log::error;
+use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
 use std::{
-    collections::BTreeMap, convert::TryFrom, env, fmt::Debug, num::ParseIntError, str::FromStr,
-    time::Duration,
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    env,
+    fmt::Debug,
+    future::Future,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::time::{sleep, timeout};
+use tracing::{error, info, instrument, warn};
 
-const NODE_POOL_LABEL: &str = "cloud.google.com/gke-nodepool";
+const DEFAULT_NODE_POOL_LABEL: &str = "cloud.google.com/gke-nodepool";
+const DEFAULT_POOL: &str = "default";
 const INSTANCE_TYPE_LABEL: &str = "node.kubernetes.io/instance-type";
 const HOSTNAME_LABEL: &str = "kubernetes.io/hostname";
 const APP_LABEL: &str = "app.kubernetes.io/part-of";
@@ -46,10 +62,29 @@ const COMPONENT_VALUE: &str = "session";
 const OWNER_LABEL: &str = "app.kubernetes.io/owner";
 const INGRESS_NAME: &str = "ingress";
 const TEMPLATE_ANNOTATION: &str = "playground.substrate.io/template";
+const TEMPLATE_ID_ANNOTATION: &str = "playground.substrate.io/template_id";
 const SESSION_DURATION_ANNOTATION: &str = "playground.substrate.io/session_duration";
+const CREATOR_ANNOTATION: &str = "playground.substrate.io/creator";
+const ORGANIZATIONS_ANNOTATION: &str = "playground.substrate.io/organizations";
+const POOL_ANNOTATION: &str = "playground.substrate.io/pool";
+const DRAIN_DEADLINE_ANNOTATION: &str = "playground.substrate.io/drain_deadline";
 const USERS_CONFIG_MAP: &str = "playground-users";
 const TEMPLATES_CONFIG_MAP: &str = "playground-templates";
+const ROLES_CONFIG_MAP: &str = "playground-roles";
+const ORGANIZATIONS_CONFIG_MAP: &str = "playground-organizations";
+const USAGE_CONFIG_MAP: &str = "playground-usage";
+const TOKENS_CONFIG_MAP: &str = "playground-tokens";
+const POOLS_CONFIG_MAP: &str = "playground-pools";
+const LOGINS_SECRET: &str = "playground-logins";
+const DAY_SECS: u64 = 24 * 60 * 60;
+const WEEK_SECS: u64 = 7 * DAY_SECS;
 const THEIA_WEB_PORT: i32 = 3000;
+const DEFAULT_REGISTRY: &str = "docker.io";
+const POD_DELETION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const POD_DELETION_MAX_CHECKS: u32 = 60;
+// Attempts at updating `USAGE_CONFIG_MAP` while it is concurrently modified
+const USAGE_UPDATE_ATTEMPTS: usize = 5;
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
 
 fn running_or_pending_sessions(sessions: Vec<&Session>) -> Vec<&Session> {
     sessions
@@ -74,6 +109,11 @@ async fn list_by_selector<K: Clone + DeserializeOwned + Debug>(
         .map_err(|err| Error::Failure(err.into()))
 }
 
+/// ID of the session of `user`. Lowercase, so that it can be used as part of a DNS name.
+pub fn session_id(user: &str) -> String {
+    user.to_lowercase()
+}
+
 pub fn pod_name(user: &str) -> String {
     format!("{}-{}", COMPONENT_VALUE, user)
 }
@@ -120,34 +160,106 @@ fn session_duration_annotation(duration: Duration) -> String {
     duration_min.to_string()
 }
 
-fn str_to_session_duration_minutes(str: &str) -> Result<Duration> {
-    Ok(Duration::from_secs(
-        str.parse::<u64>()
-            .map_err(|err| Error::Failure(err.into()))?
-            * 60,
-    ))
-}
-
-fn create_pod_annotations(
-    template: &Template,
-    duration: &Duration,
-) -> Result<BTreeMap<String, String>> {
+fn create_pod_annotations(spec: &SessionSpec, pool_id: &str) -> Result<BTreeMap<String, String>> {
     let mut annotations = BTreeMap::new();
-    let s = serde_yaml::to_string(template).map_err(|err| Error::Failure(err.into()))?;
+    let s = serde_yaml::to_string(spec.template).map_err(|err| Error::Failure(err.into()))?;
     annotations.insert(TEMPLATE_ANNOTATION.to_string(), s);
+    annotations.insert(
+        TEMPLATE_ID_ANNOTATION.to_string(),
+        spec.template_id.to_string(),
+    );
     annotations.insert(
         SESSION_DURATION_ANNOTATION.to_string(),
-        session_duration_annotation(*duration),
+        session_duration_annotation(spec.duration),
     );
+    annotations.insert(CREATOR_ANNOTATION.to_string(), spec.creator.to_string());
+    annotations.insert(
+        ORGANIZATIONS_ANNOTATION.to_string(),
+        spec.organizations.join(","),
+    );
+    annotations.insert(POOL_ANNOTATION.to_string(), pool_id.to_string());
     Ok(annotations)
 }
 
+fn preferred_scheduling_terms(hostnames: &[String]) -> Option<Vec<PreferredSchedulingTerm>> {
+    if hostnames.is_empty() {
+        return None;
+    }
+    Some(vec![PreferredSchedulingTerm {
+        weight: 100,
+        preference: hostname_term(hostnames.to_vec()),
+    }])
+}
+
+// Matches nodes with one of `hostnames`
+fn hostname_term(hostnames: Vec<String>) -> NodeSelectorTerm {
+    NodeSelectorTerm {
+        match_expressions: Some(vec![NodeSelectorRequirement {
+            key: HOSTNAME_LABEL.to_string(),
+            operator: "In".to_string(),
+            values: Some(hostnames),
+        }]),
+        ..Default::default()
+    }
+}
+
+// Resources requested by each session
+fn session_requests() -> BTreeMap<String, Quantity> {
+    BTreeMap::from([
+        ("memory".to_string(), Quantity("10Gi".to_string())),
+        (
+            "ephemeral-storage".to_string(),
+            Quantity("25Gi".to_string()),
+        ),
+    ])
+}
+
+// What a session is deployed with, kept as annotations of its pod
+struct SessionSpec<'a> {
+    creator: &'a str,
+    template_id: &'a str,
+    template: &'a Template,
+    duration: Duration,
+    /// Organizations of its owner
+    organizations: &'a [String],
+}
+
+// A pool and node picked for a session, before any resource is created or deleted
+struct Target {
+    pool_id: String,
+    pool: PoolConfiguration,
+    /// Hostname of the picked node, `None` when no node can host the session yet
+    node: Option<String>,
+    /// Nodes that already pulled the template image
+    preferred_nodes: Vec<String>,
+}
+
+// Where a session pod can be scheduled
+struct PodPlacement<'a> {
+    pool_id: &'a str,
+    pool: &'a PoolConfiguration,
+    pool_label: &'a str,
+    /// Hostname of the pinned node, if any
+    node: Option<String>,
+    /// Favored when no node is pinned
+    preferred_nodes: &'a [String],
+}
+
+fn create_toleration(toleration: &types::Toleration) -> Toleration {
+    Toleration {
+        key: toleration.key.clone(),
+        operator: toleration.operator.clone(),
+        value: toleration.value.clone(),
+        effect: toleration.effect.clone(),
+        ..Default::default()
+    }
+}
+
 fn create_pod(
     env: &Environment,
     session_id: &str,
-    template: &Template,
-    duration: &Duration,
-    pool_id: &str,
+    spec: &SessionSpec,
+    placement: PodPlacement,
 ) -> Result<Pod> {
     let mut labels = BTreeMap::new();
     labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
@@ -158,38 +270,47 @@ fn create_pod(
         metadata: ObjectMeta {
             name: Some(pod_name(session_id)),
             labels: Some(labels),
-            annotations: Some(create_pod_annotations(template, duration)?),
+            annotations: Some(create_pod_annotations(spec, placement.pool_id)?),
             ..Default::default()
         },
         spec: Some(PodSpec {
+            // A pinned node belongs to the pool as per `node_pool`, even when not labelled (e.g. in the default pool)
+            node_selector: match placement.node {
+                Some(_) => None,
+                None => Some(pool_node_selector(
+                    placement.pool_label,
+                    placement.pool_id,
+                    placement.pool,
+                )),
+            },
+            tolerations: Some(
+                placement
+                    .pool
+                    .tolerations
+                    .iter()
+                    .map(create_toleration)
+                    .collect(),
+            ),
             affinity: Some(Affinity {
                 node_affinity: Some(NodeAffinity {
-                    required_during_scheduling_ignored_during_execution: Some(NodeSelector {
-                        node_selector_terms: vec![NodeSelectorTerm {
-                            match_expressions: Some(vec![NodeSelectorRequirement {
-                                key: NODE_POOL_LABEL.to_string(),
-                                operator: "In".to_string(),
-                                values: Some(vec![pool_id.to_string()]),
-                            }]),
-                            ..Default::default()
-                        }],
-                    }),
-                    ..Default::default()
+                    // The scheduler still checks resources and taints of the pinned node
+                    required_during_scheduling_ignored_during_execution: placement.node.map(
+                        |hostname| NodeSelector {
+                            node_selector_terms: vec![hostname_term(vec![hostname])],
+                        },
+                    ),
+                    // Favor nodes that already pulled the template image
+                    preferred_during_scheduling_ignored_during_execution:
+                        preferred_scheduling_terms(placement.preferred_nodes),
                 }),
                 ..Default::default()
             }),
             containers: vec![Container {
                 name: format!("{}-container", COMPONENT_VALUE),
-                image: Some(template.image.to_string()),
-                env: Some(pod_env_variables(template, &env.host, session_id)),
+                image: Some(spec.template.image.to_string()),
+                env: Some(pod_env_variables(spec.template, &env.host, session_id)),
                 resources: Some(ResourceRequirements {
-                    requests: Some(BTreeMap::from([
-                        ("memory".to_string(), Quantity("10Gi".to_string())),
-                        (
-                            "ephemeral-storage".to_string(),
-                            Quantity("25Gi".to_string()),
-                        ),
-                    ])),
+                    requests: Some(session_requests()),
                     limits: Some(BTreeMap::from([(
                         "ephemeral-storage".to_string(),
                         Quantity("40Gi".to_string()),
@@ -291,6 +412,231 @@ fn subdomain(host: &str, session_id: &str) -> String {
     format!("{}.{}", session_id, host)
 }
 
+fn node_hostname(node: &Node) -> String {
+    node.metadata
+        .labels
+        .clone()
+        .unwrap_or_default()
+        .get(HOSTNAME_LABEL)
+        .cloned()
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Expands an image reference the way the container runtime reports it (e.g. `paritytech/image:tag` => `docker.io/paritytech/image:tag`)
+fn normalize_image(image: &str) -> String {
+    let image = match image.split_once('/') {
+        Some((registry, _))
+            if registry.contains('.') || registry.contains(':') || registry == "localhost" =>
+        {
+            image.to_string()
+        }
+        Some(_) => format!("{}/{}", DEFAULT_REGISTRY, image),
+        None => format!("{}/library/{}", DEFAULT_REGISTRY, image),
+    };
+    // Images referenced without tag or digest default to `latest`
+    let name = image.rsplit('/').next().unwrap_or_default();
+    if name.contains(':') || name.contains('@') {
+        image
+    } else {
+        format!("{}:latest", image)
+    }
+}
+
+/// Returns true if `image` has already been pulled on `node`
+fn node_has_image(node: &Node, image: &str) -> bool {
+    let image = normalize_image(image);
+    node.status
+        .as_ref()
+        .and_then(|status| status.images.as_ref())
+        .map_or(false, |images| {
+            images
+                .iter()
+                .any(|i| i.names.iter().any(|name| normalize_image(name) == image))
+        })
+}
+
+// Parses a quantity (e.g. `500m`, `10Gi` or `1e3`) as a number of units
+fn parse_quantity(quantity: &Quantity) -> Option<f64> {
+    const SUFFIXES: [(&str, f64); 15] = [
+        ("Ki", 1024.0),
+        ("Mi", 1_048_576.0),
+        ("Gi", 1_073_741_824.0),
+        ("Ti", 1_099_511_627_776.0),
+        ("Pi", 1_125_899_906_842_624.0),
+        ("Ei", 1_152_921_504_606_846_976.0),
+        ("n", 1e-9),
+        ("u", 1e-6),
+        ("m", 1e-3),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+    let value = quantity.0.trim();
+    let (number, multiplier) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            value
+                .strip_suffix(suffix)
+                .map(|number| (number, *multiplier))
+        })
+        .unwrap_or((value, 1.0));
+    number.parse::<f64>().ok().map(|number| number * multiplier)
+}
+
+fn quantities_to_resources(quantities: &BTreeMap<String, Quantity>) -> Resources {
+    let get = |name: &str, scale: f64| {
+        quantities
+            .get(name)
+            .and_then(parse_quantity)
+            .map_or(0, |value| (value * scale).round() as u64)
+    };
+    Resources {
+        cpu: get("cpu", 1000.0),
+        memory: get("memory", 1.0),
+        storage: get("ephemeral-storage", 1.0),
+    }
+}
+
+// Resources requested by all containers of `pod`
+fn pod_requests(pod: &Pod) -> Resources {
+    let mut resources = Resources::default();
+    for container in pod.spec.iter().flat_map(|spec| spec.containers.iter()) {
+        if let Some(requests) = container
+            .resources
+            .as_ref()
+            .and_then(|resources| resources.requests.as_ref())
+        {
+            resources += quantities_to_resources(requests);
+        }
+    }
+    resources
+}
+
+fn pod_is_running_or_pending(pod: &Pod) -> bool {
+    matches!(
+        pod.status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref()),
+        Some("Running") | Some("Pending")
+    )
+}
+
+fn node_is_ready(node: &Node) -> bool {
+    node.status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .map_or(false, |conditions| {
+            conditions
+                .iter()
+                .any(|condition| condition.type_ == "Ready" && condition.status == "True")
+        })
+}
+
+// Details of `node`, accounting running and pending session `pods` scheduled on it
+fn node_to_node(node: &Node, pods: &[Pod]) -> types::Node {
+    let name = node_name(node);
+    let mut used = Resources::default();
+    let mut sessions = 0;
+    for pod in pods.iter().filter(|pod| {
+        pod_is_running_or_pending(pod)
+            && pod.spec.as_ref().and_then(|spec| spec.node_name.as_ref()) == Some(&name)
+    }) {
+        used += pod_requests(pod);
+        sessions += 1;
+    }
+    types::Node {
+        hostname: node_hostname(node),
+        allocatable: node
+            .status
+            .as_ref()
+            .and_then(|status| status.allocatable.as_ref())
+            .map(quantities_to_resources)
+            .unwrap_or_default(),
+        used,
+        sessions,
+        cordoned: node_is_unschedulable(node),
+        ready: node_is_ready(node),
+    }
+}
+
+fn node_name(node: &Node) -> String {
+    node.metadata.name.clone().unwrap_or_default()
+}
+
+fn node_is_unschedulable(node: &Node) -> bool {
+    node.spec
+        .as_ref()
+        .and_then(|spec| spec.unschedulable)
+        .unwrap_or(false)
+}
+
+// Sessions deployed on pool `id`. Sessions created before pools were tracked are matched by node.
+fn pool_sessions<'a>(sessions: Vec<&'a Session>, id: &str, nodes: &[Node]) -> Vec<&'a Session> {
+    let names: BTreeSet<String> = nodes.iter().map(node_name).collect();
+    sessions
+        .into_iter()
+        .filter(|session| match &session.pool {
+            Some(pool) => pool == id,
+            None => names.contains(&session.node),
+        })
+        .collect()
+}
+
+// Labels of nodes of pool `id` that are not registered yet, e.g. added by scaling
+fn pool_node_selector(
+    pool_label: &str,
+    id: &str,
+    pool: &PoolConfiguration,
+) -> BTreeMap<String, String> {
+    pool.node_selector
+        .clone()
+        .unwrap_or_else(|| BTreeMap::from([(pool_label.to_string(), id.to_string())]))
+}
+
+fn node_matches(node: &Node, selector: &BTreeMap<String, String>) -> bool {
+    let labels = node.metadata.labels.clone().unwrap_or_default();
+    selector
+        .iter()
+        .all(|(key, value)| labels.get(key) == Some(value))
+}
+
+// The pool `node` belongs to: the first configured pool whose selector it matches, otherwise the pool named after
+// its `pool_label` value, otherwise the default pool. Pool membership is always resolved this way.
+fn node_pool(node: &Node, pool_label: &str, pools: &BTreeMap<String, PoolConfiguration>) -> String {
+    pools
+        .iter()
+        .find(|(_, pool)| {
+            pool.node_selector
+                .as_ref()
+                .map_or(false, |selector| node_matches(node, selector))
+        })
+        .map(|(id, _)| id.clone())
+        .or_else(|| {
+            node.metadata
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(pool_label).cloned())
+        })
+        .unwrap_or_else(|| DEFAULT_POOL.to_string())
+}
+
+// Groups `nodes` by pool, see `node_pool`
+fn nodes_by_pool(
+    nodes: Vec<Node>,
+    pool_label: &str,
+    pools: &BTreeMap<String, PoolConfiguration>,
+) -> BTreeMap<String, Vec<Node>> {
+    nodes.into_iter().fold(BTreeMap::new(), |mut acc, node| {
+        acc.entry(node_pool(&node, pool_label, pools))
+            .or_insert_with(Vec::new)
+            .push(node);
+        acc
+    })
+}
+
 async fn config() -> Result<Config> {
     Config::from_kubeconfig(&KubeConfigOptions::default())
         .await
@@ -318,6 +664,64 @@ async fn get_config_map(
         .and_then(|o| o.data.ok_or(Error::MissingData("config map")))
 }
 
+// Same as `get_config_map`, but returns an empty map if the ConfigMap doesn't exist
+async fn get_optional_config_map(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<BTreeMap<String, String>> {
+    let config_map_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    match config_map_api.get(name).await {
+        Ok(config_map) => Ok(config_map.data.unwrap_or_default()),
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(BTreeMap::new()),
+        Err(err) => Err(Error::Failure(err.into())),
+    }
+}
+
+// Creates an empty ConfigMap `name` unless it already exists
+async fn ensure_config_map(client: Client, namespace: &str, name: &str) -> Result<()> {
+    let config_map_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    match config_map_api.get(name).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(response)) if response.code == 404 => {
+            let config_map = ConfigMap {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            config_map_api
+                .create(&PostParams::default(), &config_map)
+                .await
+                .map_err(|err| Error::Failure(err.into()))?;
+            Ok(())
+        }
+        Err(err) => Err(Error::Failure(err.into())),
+    }
+}
+
+//
+// Sets a value of a ConfigMap, specified by a `key`.
+// Unlike `add_config_map_value`, works on ConfigMap without data
+//
+// Equivalent to `kubectl patch configmap $name --type=merge -p='{"data": {"$key": "$value"}}'`
+async fn set_config_map_value(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    key: &str,
+    value: &str,
+) -> Result<()> {
+    let config_map_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let patch = Patch::Merge(json!({ "data": { key: value } }));
+    config_map_api
+        .patch(name, &PatchParams::default(), &patch)
+        .await
+        .map_err(|err| Error::Failure(err.into()))?;
+    Ok(())
+}
+
 //
 // Adds a value to a ConfigMap, specified by a `key`.
 // Err if provided `key` doesn't exist
@@ -374,31 +778,208 @@ async fn delete_config_map_value(
     Ok(())
 }
 
+// Secret utilities
+
+// Returns the values of Secret `name`, or an empty map if it doesn't exist
+async fn get_optional_secret(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<BTreeMap<String, String>> {
+    let secret_api: Api<Secret> = Api::namespaced(client, namespace);
+    match secret_api.get(name).await {
+        Ok(secret) => Ok(secret
+            .data
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(key, ByteString(value))| {
+                String::from_utf8(value).ok().map(|value| (key, value))
+            })
+            .collect()),
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(BTreeMap::new()),
+        Err(err) => Err(Error::Failure(err.into())),
+    }
+}
+
+// Creates an empty Secret `name` unless it already exists
+async fn ensure_secret(client: Client, namespace: &str, name: &str) -> Result<()> {
+    let secret_api: Api<Secret> = Api::namespaced(client, namespace);
+    match secret_api.get(name).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(response)) if response.code == 404 => {
+            let secret = Secret {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            secret_api
+                .create(&PostParams::default(), &secret)
+                .await
+                .map_err(|err| Error::Failure(err.into()))?;
+            Ok(())
+        }
+        Err(err) => Err(Error::Failure(err.into())),
+    }
+}
+
+//
+// Sets a value of a Secret, specified by a `key`. A `None` value removes `key`, if present.
+// Unlike other updates, removals succeed when the Secret doesn't exist
+//
+// Equivalent to `kubectl patch secret $name --type=merge -p='{"stringData": {"$key": "$value"}}'`
+async fn set_secret_value(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    key: &str,
+    value: Option<&str>,
+) -> Result<()> {
+    let secret_api: Api<Secret> = Api::namespaced(client, namespace);
+    // `stringData` is write-only, removals have to go through `data`
+    let patch = match value {
+        Some(value) => Patch::Merge(json!({ "stringData": { key: value } })),
+        None => Patch::Merge(json!({ "data": { key: null } })),
+    };
+    match secret_api
+        .patch(name, &PatchParams::default(), &patch)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(response)) if response.code == 404 && value.is_none() => Ok(()),
+        Err(err) => Err(Error::Failure(err.into())),
+    }
+}
+
 async fn get_templates(client: Client, namespace: &str) -> Result<BTreeMap<String, String>> {
     get_config_map(client, namespace, TEMPLATES_CONFIG_MAP).await
 }
 
+// Value of `USERS_CONFIG_MAP` entries: a configuration, plus what is recorded when users log in
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserEntry {
+    #[serde(flatten)]
+    configuration: UserConfiguration,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    organizations: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    teams: Vec<String>,
+}
+
+fn parse_user_entry(s: &str) -> Result<UserEntry> {
+    serde_yaml::from_str(s).map_err(|err| Error::Failure(err.into()))
+}
+
+async fn set_user_entry(
+    client: Client,
+    namespace: &str,
+    id: &str,
+    entry: &UserEntry,
+) -> Result<()> {
+    add_config_map_value(
+        client,
+        namespace,
+        USERS_CONFIG_MAP,
+        id,
+        serde_yaml::to_string(entry)
+            .map_err(|err| Error::Failure(err.into()))?
+            .as_str(),
+    )
+    .await
+}
+
 async fn list_users(client: Client, namespace: &str) -> Result<BTreeMap<String, String>> {
     get_config_map(client, namespace, USERS_CONFIG_MAP).await
 }
 
+async fn list_roles(client: Client, namespace: &str) -> Result<BTreeMap<String, String>> {
+    get_optional_config_map(client, namespace, ROLES_CONFIG_MAP).await
+}
+
+async fn list_organizations(client: Client, namespace: &str) -> Result<BTreeMap<String, String>> {
+    get_optional_config_map(client, namespace, ORGANIZATIONS_CONFIG_MAP).await
+}
+
+// Parses all values of a ConfigMap as YAML, skipping invalid ones
+fn parse_config_map_values<T: DeserializeOwned>(
+    name: &str,
+    values: BTreeMap<String, String>,
+) -> BTreeMap<String, T> {
+    values
+        .into_iter()
+        .filter_map(|(k, v)| match serde_yaml::from_str(&v) {
+            Ok(value) => Some((k, value)),
+            Err(err) => {
+                error!(kind = %name, id = %k, error = %err, "Invalid ConfigMap value");
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// Sums the portion of all `intervals` that happened after `since`
+fn hours_since(intervals: &[(u64, u64)], since: u64) -> f64 {
+    let secs: u64 = intervals
+        .iter()
+        .map(|(start, end)| end.saturating_sub(*start.max(&since)))
+        .sum();
+    secs as f64 / 3600.0
+}
+
+// Running sessions are booked for their whole `duration`, from `start` or from `now` while pending
+fn booked(start: Option<SystemTime>, duration: Duration, now: u64) -> (u64, u64) {
+    let start = start.map_or(now, unix_time);
+    (start, start + duration.as_secs())
+}
+
+/// A finished session, accounted in its creator usage
 #[derive(Serialize, Deserialize, Debug, Clone)]
+struct UsageRecord {
+    start: u64,
+    end: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Environment {
     pub secured: bool,
     pub host: String,
     pub namespace: String,
 }
 
-#[derive(Serialize, Debug, Clone)]
+impl Environment {
+    /// URL the playground is served from, e.g. `https://playground.substrate.dev`
+    pub fn base_url(&self) -> String {
+        format!(
+            "{}://{}",
+            if self.secured { "https" } else { "http" },
+            self.host
+        )
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Configuration {
-    pub github_client_id: String,
-    pub session: SessionDefaults,
+    pub identity_provider: IdentityProvider,
+    pub client_id: String,
+    /// Only set when GitHub is the identity provider
+    pub github_client_id: Option<String>,
+    /// Reloaded when the configuration file changes
+    #[schemars(with = "SessionDefaults")]
+    pub session: Reloadable<SessionDefaults>,
 }
 
 #[derive(Clone)]
 pub struct Secrets {
-    pub github_client_secret: String,
+    pub client_secret: String,
 }
 
 #[derive(Clone)]
@@ -406,9 +987,14 @@ pub struct Engine {
     pub env: Environment,
     pub configuration: Configuration,
     pub secrets: Secrets,
+    /// Label whose value is the pool of a node
+    pub pool_label: String,
+    /// Pools are not scaled when `None`
+    pub provisioner: Option<Arc<dyn NodeProvisioner>>,
 }
 
 impl Engine {
+    #[instrument(skip_all)]
     pub async fn new() -> Result<Self> {
         let config = config().await?;
         let namespace = config.clone().default_namespace.to_string();
@@ -440,19 +1026,13 @@ impl Engine {
             "localhost".to_string()
         };
 
-        // Retrieve 'static' configuration from Env variables
-        let github_client_id =
-            env::var("GITHUB_CLIENT_ID").map_err(|_| Error::MissingData("GITHUB_CLIENT_ID"))?;
-        let github_client_secret =
-            env::var("GITHUB_CLIENT_SECRET").map_err(|_| Error::MissingData("GITHUB_CLIENT_ID"))?;
-        let session_default_duration = env::var("SESSION_DEFAULT_DURATION")
-            .map_err(|_| Error::MissingData("SESSION_DEFAULT_DURATION"))?;
-        let session_max_duration = env::var("SESSION_MAX_DURATION")
-            .map_err(|_| Error::MissingData("SESSION_MAX_DURATION"))?;
-        let session_default_pool_affinity = env::var("SESSION_DEFAULT_POOL_AFFINITY")
-            .map_err(|_| Error::MissingData("SESSION_DEFAULT_POOL_AFFINITY"))?;
-        let session_default_max_per_node = env::var("SESSION_DEFAULT_MAX_PER_NODE")
-            .map_err(|_| Error::MissingData("SESSION_DEFAULT_MAX_PER_NODE"))?;
+        // Retrieve 'static' configuration from the configuration file and env variables
+        let identity_provider = IdentityProvider::from_env().await?;
+        let settings = settings::load(identity_provider.env_prefix())?;
+        let github_client_id = match identity_provider {
+            IdentityProvider::GitHub { .. } => Some(settings.client_id.clone()),
+            _ => None,
+        };
 
         Ok(Engine {
             env: Environment {
@@ -461,19 +1041,17 @@ impl Engine {
                 namespace: namespace.clone(),
             },
             configuration: Configuration {
+                identity_provider,
+                client_id: settings.client_id,
                 github_client_id,
-                session: SessionDefaults {
-                    duration: str_to_session_duration_minutes(&session_default_duration)?,
-                    max_duration: str_to_session_duration_minutes(&session_max_duration)?,
-                    pool_affinity: session_default_pool_affinity,
-                    max_sessions_per_pod: session_default_max_per_node
-                        .parse()
-                        .map_err(|err: ParseIntError| Error::Failure(err.into()))?,
-                },
+                session: Reloadable::new(settings.session),
             },
             secrets: Secrets {
-                github_client_secret,
+                client_secret: settings.client_secret,
             },
+            pool_label: env::var("NODE_POOL_LABEL")
+                .unwrap_or_else(|_| DEFAULT_NODE_POOL_LABEL.to_string()),
+            provisioner: provisioner_from_env()?,
         })
     }
 
@@ -491,21 +1069,45 @@ impl Engine {
             .annotations
             .clone()
             .ok_or(Error::MissingData("pod#metadata#annotations"))?;
-        let template = serde_yaml::from_str(
+        let template: Template = serde_yaml::from_str(
             annotations
                 .get(TEMPLATE_ANNOTATION)
                 .ok_or(Error::MissingData("template"))?,
         )
         .map_err(|err| Error::Failure(err.into()))?;
+        // Sessions created before template IDs were tracked fall back to their template name
+        let template_id = annotations
+            .get(TEMPLATE_ID_ANNOTATION)
+            .unwrap_or(&template.name)
+            .clone();
         let duration = str_to_session_duration_minutes(
             annotations
                 .get(SESSION_DURATION_ANNOTATION)
                 .ok_or(Error::MissingData("template#session_duration"))?,
         )?;
 
+        // Sessions created before creators were tracked are attributed to their owner
+        let creator = annotations
+            .get(CREATOR_ANNOTATION)
+            .unwrap_or(username)
+            .clone();
+        let organizations = annotations
+            .get(ORGANIZATIONS_ANNOTATION)
+            .map(|organizations| {
+                organizations
+                    .split(',')
+                    .filter(|organization| !organization.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
         Ok(Session {
             user_id: username.clone(),
+            creator,
+            template_id,
             template,
+            organizations,
             url: subdomain(&env.host, username),
             pod: Self::pod_to_details(self, &pod.clone())?,
             duration,
@@ -515,38 +1117,46 @@ impl Engine {
                 .ok_or(Error::MissingData("pod#spec"))?
                 .node_name
                 .unwrap_or_else(|| "<Unknown>".to_string()),
+            pool: annotations.get(POOL_ANNOTATION).cloned(),
+            drain_deadline: annotations
+                .get(DRAIN_DEADLINE_ANNOTATION)
+                .and_then(|deadline| deadline.parse().ok()),
         })
     }
 
-    fn nodes_to_pool(self, id: String, nodes: Vec<Node>) -> Result<Pool> {
+    fn nodes_to_pool(
+        self,
+        id: String,
+        nodes: Vec<Node>,
+        pods: &[Pod],
+        scaling: Option<PoolScaling>,
+        cordoned: bool,
+    ) -> Result<Pool> {
         let node = nodes
             .first()
             .ok_or(Error::MissingData("empty vec of nodes"))?;
-        let labels = node
+        let instance_type = node
             .metadata
             .labels
-            .clone()
-            .ok_or(Error::MissingData("metadata#labels"))?;
-        let local = "local".to_string();
-        let unknown = "unknown".to_string();
-        let instance_type = labels.get(INSTANCE_TYPE_LABEL).unwrap_or(&local);
+            .as_ref()
+            .and_then(|labels| labels.get(INSTANCE_TYPE_LABEL).cloned());
+
+        let nodes: Vec<types::Node> = nodes.iter().map(|node| node_to_node(node, pods)).collect();
+        let mut allocatable = Resources::default();
+        let mut used = Resources::default();
+        for node in &nodes {
+            allocatable += node.allocatable;
+            used += node.used;
+        }
 
         Ok(Pool {
             name: id,
-            instance_type: Some(instance_type.clone()),
-            nodes: nodes
-                .iter()
-                .map(|node| crate::types::Node {
-                    hostname: node
-                        .metadata
-                        .labels
-                        .clone()
-                        .unwrap_or_default()
-                        .get(HOSTNAME_LABEL)
-                        .unwrap_or(&unknown)
-                        .clone(),
-                })
-                .collect(),
+            instance_type,
+            nodes,
+            scaling,
+            cordoned,
+            allocatable,
+            used,
         })
     }
 
@@ -586,6 +1196,16 @@ impl Engine {
         let status = pod.status.as_ref().ok_or(Error::MissingData("status"))?;
         let container_statuses = status.clone().container_statuses;
         let container_status = container_statuses.as_ref().and_then(|v| v.first());
+        let container_start_time = container_status
+            .and_then(|c| c.state.as_ref())
+            .and_then(|s| s.running.as_ref())
+            .and_then(|r| r.started_at.as_ref());
+        let deploy_duration = pod
+            .metadata
+            .creation_timestamp
+            .as_ref()
+            .zip(container_start_time)
+            .map(|(created, started)| (started.0 - created.0).num_seconds().max(0) as u64);
         Ok(types::Pod {
             phase: Phase::from_str(
                 &status
@@ -598,37 +1218,65 @@ impl Engine {
             message: status.clone().message.unwrap_or_else(|| "".to_string()),
             start_time: status.clone().start_time.map(|dt| dt.0.into()),
             container: container_status.map(|c| self.container_status_to_container_status(c)),
+            deploy_duration,
         })
     }
 
     fn yaml_to_user(self, s: &str) -> Result<User> {
-        let user_configuration: UserConfiguration =
-            serde_yaml::from_str(s).map_err(|err| Error::Failure(err.into()))?;
+        let entry = parse_user_entry(s)?;
         Ok(User {
-            admin: user_configuration.admin,
-            pool_affinity: user_configuration.pool_affinity,
-            can_customize_duration: user_configuration.can_customize_duration,
-            can_customize_pool_affinity: user_configuration.can_customize_pool_affinity,
+            roles: entry.configuration.roles,
+            pool_affinity: entry.configuration.pool_affinity,
+            quota: entry.configuration.quota,
+            organizations: entry.organizations,
+            teams: entry.teams,
         })
     }
 
+    #[instrument(skip_all)]
     pub async fn list_templates(self) -> Result<BTreeMap<String, Template>> {
         let client = new_client().await?;
 
         Ok(get_templates(client, &self.env.namespace)
             .await?
             .into_iter()
-            .filter_map(|(k, v)| {
-                if let Ok(template) = serde_yaml::from_str(&v) {
-                    Some((k, template))
-                } else {
-                    error!("Error while parsing template {}", k);
+            .filter_map(|(k, v)| match serde_yaml::from_str(&v) {
+                Ok(template) => Some((k, template)),
+                Err(err) => {
+                    error!(template = %k, error = %err, "Invalid template");
                     None
                 }
             })
             .collect::<BTreeMap<String, Template>>())
     }
 
+    /// Creates or replaces the template `id`
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn import_template(&self, id: &str, template: &Template) -> Result<()> {
+        // Template IDs are ConfigMap keys
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            return Err(Error::InvalidValue {
+                field: "id",
+                reason: "must only contain alphanumeric characters, '-', '_' or '.'".to_string(),
+            });
+        }
+        let client = new_client().await?;
+
+        add_config_map_value(
+            client,
+            &self.env.namespace,
+            TEMPLATES_CONFIG_MAP,
+            id,
+            &serde_yaml::to_string(template).map_err(|err| Error::Failure(err.into()))?,
+        )
+        .await
+    }
+
+    #[instrument(skip_all, fields(id = %id))]
     pub async fn get_user(&self, id: &str) -> Result<Option<User>> {
         let client = new_client().await?;
 
@@ -641,6 +1289,7 @@ impl Engine {
         }
     }
 
+    #[instrument(skip_all)]
     pub async fn list_users(&self) -> Result<BTreeMap<String, User>> {
         let client = new_client().await?;
 
@@ -651,45 +1300,172 @@ impl Engine {
             .collect::<Result<BTreeMap<String, User>>>()?)
     }
 
-    pub async fn create_user(&self, id: String, conf: UserConfiguration) -> Result<()> {
+    /// Lists all roles, including default ones unless overridden
+    #[instrument(skip_all)]
+    pub async fn list_roles(&self) -> Result<BTreeMap<String, Role>> {
         let client = new_client().await?;
 
-        add_config_map_value(
+        let mut roles = Role::defaults();
+        roles.extend(parse_config_map_values(
+            "role",
+            list_roles(client, &self.env.namespace).await?,
+        ));
+        Ok(roles)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn list_organizations(&self) -> Result<BTreeMap<String, Organization>> {
+        let client = new_client().await?;
+
+        Ok(parse_config_map_values(
+            "organization",
+            list_organizations(client, &self.env.namespace).await?,
+        ))
+    }
+
+    /// Lists all API tokens, by hash
+    #[instrument(skip_all)]
+    pub async fn list_api_tokens(&self) -> Result<BTreeMap<String, ApiToken>> {
+        let client = new_client().await?;
+
+        Ok(parse_config_map_values(
+            "token",
+            get_optional_config_map(client, &self.env.namespace, TOKENS_CONFIG_MAP).await?,
+        ))
+    }
+
+    #[instrument(skip_all)]
+    pub async fn get_api_token(&self, hash: &str) -> Result<Option<ApiToken>> {
+        Ok(self.list_api_tokens().await?.remove(hash))
+    }
+
+    #[instrument(skip_all)]
+    pub async fn create_api_token(&self, hash: &str, token: &ApiToken) -> Result<()> {
+        let client = new_client().await?;
+
+        ensure_config_map(client.clone(), &self.env.namespace, TOKENS_CONFIG_MAP).await?;
+        set_config_map_value(
             client,
             &self.env.namespace,
-            USERS_CONFIG_MAP,
-            id.as_str(),
-            serde_yaml::to_string(&conf)
-                .map_err(|err| Error::Failure(err.into()))?
-                .as_str(),
+            TOKENS_CONFIG_MAP,
+            hash,
+            &serde_yaml::to_string(token).map_err(|err| Error::Failure(err.into()))?,
         )
-        .await?;
+        .await
+    }
 
-        Ok(())
+    #[instrument(skip_all)]
+    pub async fn delete_api_token(&self, hash: &str) -> Result<()> {
+        let client = new_client().await?;
+        delete_config_map_value(client, &self.env.namespace, TOKENS_CONFIG_MAP, hash).await
+    }
+
+    /// Lists all persisted logins, by ID
+    #[instrument(skip_all)]
+    pub async fn list_logins(&self) -> Result<BTreeMap<String, StoredLogin>> {
+        let client = new_client().await?;
+
+        Ok(parse_config_map_values(
+            "login",
+            get_optional_secret(client, &self.env.namespace, LOGINS_SECRET).await?,
+        ))
+    }
+
+    #[instrument(skip_all)]
+    pub async fn store_login(&self, id: &str, login: &StoredLogin) -> Result<()> {
+        let client = new_client().await?;
+
+        ensure_secret(client.clone(), &self.env.namespace, LOGINS_SECRET).await?;
+        set_secret_value(
+            client,
+            &self.env.namespace,
+            LOGINS_SECRET,
+            id,
+            Some(&serde_yaml::to_string(login).map_err(|err| Error::Failure(err.into()))?),
+        )
+        .await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn delete_login(&self, id: &str) -> Result<()> {
+        let client = new_client().await?;
+
+        set_secret_value(client, &self.env.namespace, LOGINS_SECRET, id, None).await
+    }
+
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn create_user(&self, id: String, conf: UserConfiguration) -> Result<()> {
+        let client = new_client().await?;
+
+        set_user_entry(
+            client,
+            &self.env.namespace,
+            &id,
+            &UserEntry {
+                configuration: conf,
+                organizations: Vec::new(),
+                teams: Vec::new(),
+            },
+        )
+        .await
     }
 
+    #[instrument(skip_all, fields(id = %id))]
     pub async fn update_user(&self, id: String, conf: UserUpdateConfiguration) -> Result<()> {
         let client = new_client().await?;
 
-        add_config_map_value(
+        // Recorded memberships are not part of the configuration, keep them
+        let (organizations, teams) = match list_users(client.clone(), &self.env.namespace)
+            .await?
+            .get(&id)
+        {
+            Some(entry) => {
+                let entry = parse_user_entry(entry)?;
+                (entry.organizations, entry.teams)
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+        set_user_entry(
             client,
             &self.env.namespace,
-            USERS_CONFIG_MAP,
-            id.as_str(),
-            serde_yaml::to_string(&conf)
-                .map_err(|err| Error::Failure(err.into()))?
-                .as_str(),
+            &id,
+            &UserEntry {
+                configuration: UserConfiguration {
+                    roles: conf.roles,
+                    pool_affinity: conf.pool_affinity,
+                    quota: conf.quota,
+                },
+                organizations,
+                teams,
+            },
         )
-        .await?;
+        .await
+    }
 
-        Ok(())
+    /// Records the `organizations` and `teams` user `id` belongs to, as seen when it logged in
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn set_user_memberships(
+        &self,
+        id: &str,
+        organizations: Vec<String>,
+        teams: Vec<String>,
+    ) -> Result<()> {
+        let client = new_client().await?;
+
+        let users = list_users(client.clone(), &self.env.namespace).await?;
+        let mut entry = parse_user_entry(users.get(id).ok_or(Error::NotFound("user"))?)?;
+        entry.organizations = organizations;
+        entry.teams = teams;
+        set_user_entry(client, &self.env.namespace, id, &entry).await
     }
 
+    #[instrument(skip_all, fields(id = %id))]
     pub async fn delete_user(&self, id: String) -> Result<()> {
         let client = new_client().await?;
         delete_config_map_value(client, &self.env.namespace, USERS_CONFIG_MAP, id.as_str()).await
     }
 
+    #[instrument(skip_all, fields(id = %id))]
     pub async fn get_session(&self, id: &str) -> Result<Option<Session>> {
         let client = new_client().await?;
         let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
@@ -701,15 +1477,21 @@ impl Engine {
         }
     }
 
-    /// Lists all currently running sessions
-    pub async fn list_sessions(&self) -> Result<BTreeMap<String, Session>> {
+    #[instrument(skip_all)]
+    async fn list_session_pods(&self) -> Result<Vec<Pod>> {
         let client = new_client().await?;
         let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
-        let pods = list_by_selector(
+        list_by_selector(
             &pod_api,
             format!("{}={}", COMPONENT_LABEL, COMPONENT_VALUE).to_string(),
         )
-        .await?;
+        .await
+    }
+
+    /// Lists all currently running sessions
+    #[instrument(skip_all)]
+    pub async fn list_sessions(&self) -> Result<BTreeMap<String, Session>> {
+        let pods = self.list_session_pods().await?;
 
         Ok(pods
             .iter()
@@ -718,6 +1500,7 @@ impl Engine {
             .collect::<BTreeMap<String, Session>>())
     }
 
+    #[instrument(skip_all)]
     pub async fn patch_ingress(&self, templates: &BTreeMap<String, &Template>) -> Result<()> {
         let client = new_client().await?;
         let ingress_api: Api<Ingress> = Api::namespaced(client, &self.env.namespace);
@@ -755,45 +1538,25 @@ impl Engine {
         Ok(())
     }
 
+    #[instrument(skip_all, fields(session_id = %session_id))]
     pub async fn create_session(
         &self,
         user: &LoggedUser,
         session_id: &str,
         conf: SessionConfiguration,
     ) -> Result<()> {
-        // Make sure some node on the right pools still have rooms
-        // Find pool affinity, lookup corresponding pool and capacity based on nodes, figure out if there is room left
-        // TODO: replace with custom scheduler
-        // * https://kubernetes.io/docs/tasks/extend-kubernetes/configure-multiple-schedulers/
-        // * https://kubernetes.io/blog/2017/03/advanced-scheduling-in-kubernetes/
         let pool_id = conf.clone().pool_affinity.unwrap_or_else(|| {
             user.clone()
                 .pool_affinity
-                .unwrap_or(self.clone().configuration.session.pool_affinity)
+                .unwrap_or(self.configuration.session.get().pool_affinity)
         });
-        let pool = self
-            .get_pool(&pool_id)
-            .await?
-            .ok_or(Error::MissingData("no matching pool"))?;
-        let max_sessions_allowed =
-            pool.nodes.len() * self.configuration.session.max_sessions_per_pod;
-        let sessions = self.list_sessions().await?;
-
-        if running_or_pending_sessions(sessions.values().collect()).len() >= max_sessions_allowed {
-            // TODO Should trigger pool dynamic scalability. Right now this will only consider the pool lower bound.
-            // "Reached maximum number of concurrent sessions allowed: {}"
-            return Err(Error::Unauthorized());
-        }
-        let client = new_client().await?;
         // Access the right image id
         let templates = self.clone().list_templates().await?;
         let template = templates
             .get(&conf.template.to_string())
-            .ok_or(Error::MissingData("no matching template"))?;
-
-        let namespace = &self.env.namespace;
-
-        let pod_api: Api<Pod> = Api::namespaced(client.clone(), namespace);
+            .ok_or(Error::NotFound("template"))?;
+        // Make sure the pool still has room before anything is created
+        let target = self.target(session_id, template, &pool_id).await?;
 
         //TODO deploy a new ingress matching the route
         // With the proper mapping
@@ -804,28 +1567,174 @@ impl Engine {
         sessions.insert(session_id.to_string(), template);
         self.patch_ingress(&sessions).await?;
 
-        let duration = conf.duration.unwrap_or(self.configuration.session.duration);
+        let duration = conf
+            .duration
+            .unwrap_or(self.configuration.session.get().duration);
+
+        // Sessions created on behalf of other users get the organizations they last logged in with
+        let organizations = if session_id == user.id {
+            user.organizations.clone()
+        } else {
+            self.get_user(session_id)
+                .await?
+                .map(|owner| owner.organizations)
+                .unwrap_or_default()
+        };
+        self.deploy_session(
+            session_id,
+            &SessionSpec {
+                creator: &user.id,
+                template_id: &conf.template,
+                template,
+                duration,
+                organizations: &organizations,
+            },
+            &target,
+        )
+        .await
+    }
+
+    // Picks a node of pool `pool_id` for session `session_id`. Fails if the pool is cordoned or at capacity.
+    #[instrument(skip_all, fields(session_id = %session_id, pool_id = %pool_id))]
+    async fn target(&self, session_id: &str, template: &Template, pool_id: &str) -> Result<Target> {
+        let pool = self.get_pool_configuration(pool_id).await?;
+        if pool.cordoned {
+            return Err(Error::Unavailable(format!("pool {} is cordoned", pool_id)));
+        }
+        let nodes: Vec<Node> = self
+            .list_pool_nodes(pool_id)
+            .await?
+            .into_iter()
+            .filter(|node| !node_is_unschedulable(node))
+            .collect();
+        // Scalable pools can host sessions up to their upper bound, even without any node yet
+        let scaling = pool.scaling.clone().filter(|_| self.provisioner.is_some());
+        let max_nodes = scaling
+            .as_ref()
+            .map_or(nodes.len(), |scaling| scaling.max_nodes.max(nodes.len()));
+        if max_nodes == 0 {
+            return Err(Error::NotFound("pool"));
+        }
+        let max_sessions_per_pod = self.configuration.session.get().max_sessions_per_pod;
+        let sessions = self.list_sessions().await?;
+        // A migrated session doesn't count against its own pool
+        let concurrent_sessions = pool_sessions(
+            running_or_pending_sessions(sessions.values().collect()),
+            pool_id,
+            &nodes,
+        )
+        .into_iter()
+        .filter(|session| self::session_id(&session.user_id) != session_id)
+        .count();
+        if concurrent_sessions >= max_nodes * max_sessions_per_pod {
+            return Err(Error::Unavailable(format!(
+                "pool {} reached its maximum of {} concurrent sessions",
+                pool_id,
+                max_nodes * max_sessions_per_pod
+            )));
+        }
+        let preferred_nodes: Vec<String> = nodes
+            .iter()
+            .filter(|node| node_has_image(node, &template.image))
+            .map(node_hostname)
+            .collect();
+        if preferred_nodes.is_empty() {
+            warn!(
+                session_id = %session_id,
+                pool = %pool_id,
+                image = %template.image,
+                "Image not available on pool, it will be pulled"
+            );
+        }
+
+        // Pick a node among those of the pool
+        let pods = self.list_session_pods().await?;
+        let candidates: Vec<Candidate> = nodes
+            .iter()
+            .map(|node| Candidate {
+                name: node_name(node),
+                node: node_to_node(node, &pods),
+                has_image: node_has_image(node, &template.image),
+            })
+            .collect();
+        let node = place(
+            self.configuration.session.get().placement,
+            session_id,
+            &candidates,
+            &quantities_to_resources(&session_requests()),
+            self.configuration.session.get().max_sessions_per_pod,
+        )
+        .and_then(|name| {
+            candidates
+                .iter()
+                .find(|candidate| candidate.name == name)
+                .map(|candidate| candidate.node.hostname.clone())
+        });
+
+        Ok(Target {
+            pool_id: pool_id.to_string(),
+            pool,
+            node,
+            preferred_nodes,
+        })
+    }
 
+    // Deploys the pod and service of a session on `target`
+    #[instrument(skip_all, fields(session_id = %session_id, pool_id = %target.pool_id))]
+    async fn deploy_session(
+        &self,
+        session_id: &str,
+        spec: &SessionSpec<'_>,
+        target: &Target,
+    ) -> Result<()> {
         // Deploy a new pod for this image
+        let client = new_client().await?;
+        let pod_api: Api<Pod> = Api::namespaced(client.clone(), &self.env.namespace);
         pod_api
             .create(
                 &PostParams::default(),
-                &create_pod(&self.env, session_id, template, &duration, &pool_id)?,
+                &create_pod(
+                    &self.env,
+                    session_id,
+                    spec,
+                    PodPlacement {
+                        pool_id: &target.pool_id,
+                        pool: &target.pool,
+                        pool_label: &self.pool_label,
+                        node: target.node.clone(),
+                        preferred_nodes: &target.preferred_nodes,
+                    },
+                )?,
             )
             .await
             .map_err(|err| Error::Failure(err.into()))?;
 
         // Deploy the associated service
-        let service_api: Api<Service> = Api::namespaced(client.clone(), namespace);
-        let service = create_service(session_id, template);
+        let service_api: Api<Service> = Api::namespaced(client, &self.env.namespace);
+        let service = create_service(session_id, spec.template);
         service_api
             .create(&PostParams::default(), &service)
             .await
             .map_err(|err| Error::Failure(err.into()))?;
 
+        // Grow the pool right away if this session doesn't fit on existing nodes, it will stay pending until then
+        if let Some(scaling) = target.pool.scaling.as_ref() {
+            if target.node.is_none() {
+                if let Err(err) = self.scale_pool(&target.pool_id, scaling).await {
+                    error!(
+                        session_id = %session_id,
+                        pool = %target.pool_id,
+                        error = %err,
+                        "Failed to scale pool"
+                    );
+                }
+            }
+        }
+
         Ok(())
     }
 
+    #[instrument(skip_all, fields(session_id = %session_id))]
     pub async fn update_session(
         &self,
         session_id: &str,
@@ -835,12 +1744,17 @@ impl Engine {
             .clone()
             .get_session(session_id)
             .await?
-            .ok_or(Error::MissingData("no matching session"))?;
+            .ok_or(Error::NotFound("session"))?;
 
-        let duration = conf.duration.unwrap_or(self.configuration.session.duration);
-        let max_duration = self.configuration.session.max_duration;
+        let duration = conf
+            .duration
+            .unwrap_or(self.configuration.session.get().duration);
+        let max_duration = self.configuration.session.get().max_duration;
         if duration >= max_duration {
-            return Err(Error::Unauthorized());
+            return Err(Error::InvalidValue {
+                field: "duration",
+                reason: format!("must be less than {} minutes", max_duration.as_secs() / 60),
+            });
         }
         if duration != session.duration {
             let client = new_client().await?;
@@ -865,7 +1779,11 @@ impl Engine {
         Ok(())
     }
 
-    pub async fn delete_session(&self, id: &str) -> Result<()> {
+    /// Deletes session `id`. Returns the deleted session, if it could be retrieved.
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn delete_session(&self, id: &str) -> Result<Option<Session>> {
+        let session = self.get_session(id).await?;
+
         // Undeploy the service by its id
         let client = new_client().await?;
         let service_api: Api<Service> = Api::namespaced(client.clone(), &self.env.namespace);
@@ -907,50 +1825,695 @@ impl Engine {
             .await
             .map_err(|err| Error::Failure(err.into()))?;
 
-        Ok(())
+        if let Some(session) = &session {
+            if let Err(err) = self.record_usage(session).await {
+                error!(
+                    session_id = %id,
+                    user = %session.creator,
+                    error = %err,
+                    "Failed to record usage"
+                );
+            }
+        }
+
+        Ok(session)
     }
 
-    pub async fn get_pool(&self, id: &str) -> Result<Option<Pool>> {
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn list_usage_records(&self, user_id: &str) -> Result<Vec<UsageRecord>> {
+        let client = new_client().await?;
+        let usage = get_optional_config_map(client, &self.env.namespace, USAGE_CONFIG_MAP).await?;
+
+        match usage.get(user_id) {
+            Some(records) => {
+                serde_yaml::from_str(records).map_err(|err| Error::Failure(err.into()))
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Adds `session` to its creator usage. Records older than a week are discarded.
+    // The ConfigMap is replaced with the `resourceVersion` it was read with, so that concurrent updates (e.g. of
+    // sessions ending at the same time) are retried rather than lost.
+    #[instrument(skip_all)]
+    async fn record_usage(&self, session: &Session) -> Result<()> {
+        let start = match session.pod.start_time {
+            Some(start) => unix_time(start),
+            None => return Ok(()),
+        };
+
+        let client = new_client().await?;
+        ensure_config_map(client.clone(), &self.env.namespace, USAGE_CONFIG_MAP).await?;
+        let config_map_api: Api<ConfigMap> = Api::namespaced(client, &self.env.namespace);
+        for _ in 0..USAGE_UPDATE_ATTEMPTS {
+            let mut config_map = config_map_api
+                .get(USAGE_CONFIG_MAP)
+                .await
+                .map_err(|err| Error::Failure(err.into()))?;
+            let data = config_map.data.get_or_insert_with(BTreeMap::new);
+            let mut records: Vec<UsageRecord> = match data.get(&session.creator) {
+                Some(records) => {
+                    serde_yaml::from_str(records).map_err(|err| Error::Failure(err.into()))?
+                }
+                None => Vec::new(),
+            };
+            let now = unix_time(SystemTime::now());
+            records.retain(|record| record.end + WEEK_SECS > now);
+            records.push(UsageRecord { start, end: now });
+            data.insert(
+                session.creator.clone(),
+                serde_yaml::to_string(&records).map_err(|err| Error::Failure(err.into()))?,
+            );
+
+            match config_map_api
+                .replace(USAGE_CONFIG_MAP, &PostParams::default(), &config_map)
+                .await
+            {
+                Ok(_) => return Ok(()),
+                // Modified since read
+                Err(kube::Error::Api(response)) if response.code == 409 => continue,
+                Err(err) => return Err(Error::Failure(err.into())),
+            }
+        }
+        Err(Error::Unavailable(format!(
+            "usage of {} is being concurrently updated",
+            session.creator
+        )))
+    }
+
+    /// Returns the usage of sessions created by `user_id`. Finished sessions count for the time they ran, running
+    /// ones for their whole duration.
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    pub async fn get_usage(&self, user_id: &str) -> Result<Usage> {
+        let now = unix_time(SystemTime::now());
+        let sessions = self.list_sessions().await?;
+        let running: Vec<&Session> = running_or_pending_sessions(sessions.values().collect())
+            .into_iter()
+            .filter(|session| session.creator == user_id)
+            .collect();
+        let mut intervals: Vec<(u64, u64)> = self
+            .list_usage_records(user_id)
+            .await?
+            .iter()
+            .map(|record| (record.start, record.end))
+            .collect();
+        intervals.extend(
+            running
+                .iter()
+                .map(|session| booked(session.pod.start_time, session.duration, now)),
+        );
+
+        Ok(Usage {
+            concurrent_sessions: running.len(),
+            session_hours_last_day: hours_since(&intervals, now.saturating_sub(DAY_SECS)),
+            session_hours_last_week: hours_since(&intervals, now.saturating_sub(WEEK_SECS)),
+        })
+    }
+
+    #[instrument(skip_all)]
+    async fn list_nodes(&self) -> Result<Vec<Node>> {
         let client = new_client().await?;
         let node_api: Api<Node> = Api::all(client);
-        let nodes =
-            list_by_selector(&node_api, format!("{}={}", NODE_POOL_LABEL, id).to_string()).await?;
+        node_api
+            .list(&ListParams::default())
+            .await
+            .map(|l| l.items)
+            .map_err(|err| Error::Failure(err.into()))
+    }
 
-        match self.clone().nodes_to_pool(id.to_string(), nodes) {
+    #[instrument(skip_all, fields(id = %id))]
+    async fn list_pool_nodes(&self, id: &str) -> Result<Vec<Node>> {
+        let pools = self.list_pool_configurations().await?;
+        Ok(self
+            .list_nodes()
+            .await?
+            .into_iter()
+            .filter(|node| node_pool(node, &self.pool_label, &pools) == id)
+            .collect())
+    }
+
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn get_pool(&self, id: &str) -> Result<Option<Pool>> {
+        let nodes = self.list_pool_nodes(id).await?;
+        let pods = self.list_session_pods().await?;
+        let conf = self.get_pool_configuration(id).await?;
+        let scaling = conf.scaling.filter(|_| self.provisioner.is_some());
+
+        match self
+            .clone()
+            .nodes_to_pool(id.to_string(), nodes, &pods, scaling, conf.cordoned)
+        {
             Ok(pool) => Ok(Some(pool)),
             Err(_) => Ok(None),
         }
     }
 
+    #[instrument(skip_all)]
     pub async fn list_pools(&self) -> Result<BTreeMap<String, Pool>> {
+        let nodes = self.list_nodes().await?;
+        let pods = self.list_session_pods().await?;
+        let configurations = self.list_pool_configurations().await?;
+
+        Ok(nodes_by_pool(nodes, &self.pool_label, &configurations)
+            .into_iter()
+            .flat_map(|(s, v)| {
+                let conf = configurations.get(&s);
+                let scaling = conf
+                    .and_then(|conf| conf.scaling.clone())
+                    .filter(|_| self.provisioner.is_some());
+                let cordoned = conf.map_or(false, |conf| conf.cordoned);
+                match self
+                    .clone()
+                    .nodes_to_pool(s.clone(), v, &pods, scaling, cordoned)
+                {
+                    Ok(pool) => Some((s, pool)),
+                    Err(_) => None,
+                }
+            })
+            .collect())
+    }
+
+    #[instrument(skip_all)]
+    pub async fn list_pool_configurations(&self) -> Result<BTreeMap<String, PoolConfiguration>> {
+        let client = new_client().await?;
+
+        Ok(parse_config_map_values(
+            "pool",
+            get_optional_config_map(client, &self.env.namespace, POOLS_CONFIG_MAP).await?,
+        ))
+    }
+
+    // Configuration of pool `id`, defaults to an empty one
+    #[instrument(skip_all, fields(id = %id))]
+    async fn get_pool_configuration(&self, id: &str) -> Result<PoolConfiguration> {
+        Ok(self
+            .list_pool_configurations()
+            .await?
+            .remove(id)
+            .unwrap_or_default())
+    }
+
+    /// Cordoned nodes don't accept new sessions
+    #[instrument(skip_all, fields(name = %name))]
+    pub async fn set_node_unschedulable(&self, name: &str, unschedulable: bool) -> Result<()> {
         let client = new_client().await?;
         let node_api: Api<Node> = Api::all(client);
+        let patch = Patch::Merge(json!({ "spec": { "unschedulable": unschedulable } }));
+        node_api
+            .patch(name, &PatchParams::default(), &patch)
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        Ok(())
+    }
 
-        let nodes = node_api
-            .list(&ListParams::default())
+    /// Grows or shrinks pool `id` so that its running and pending sessions fit, within `scaling` bounds.
+    /// Only nodes not hosting any session are removed.
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn scale_pool(&self, id: &str, scaling: &PoolScaling) -> Result<()> {
+        let provisioner = match &self.provisioner {
+            Some(provisioner) => provisioner,
+            None => return Ok(()),
+        };
+        let nodes = self.list_pool_nodes(id).await?;
+        // Cordoned nodes are being removed, or must not host new sessions
+        let schedulable: Vec<Node> = nodes
+            .iter()
+            .filter(|node| !node_is_unschedulable(node))
+            .cloned()
+            .collect();
+        let sessions = self.list_sessions().await?;
+        let concurrent_sessions = pool_sessions(
+            running_or_pending_sessions(sessions.values().collect()),
+            id,
+            &nodes,
+        )
+        .len();
+        let target = target_size(
+            concurrent_sessions,
+            self.configuration.session.get().max_sessions_per_pod,
+            scaling,
+        );
+        let requested = provisioner.size(id).await?;
+        let busy: BTreeSet<&str> = sessions
+            .values()
+            .map(|session| session.node.as_str())
+            .collect();
+        let idle: Vec<String> = schedulable
+            .iter()
+            .map(node_name)
+            .filter(|name| !busy.contains(name.as_str()))
+            .collect();
+
+        let idle = match plan(target, requested, nodes.len(), schedulable.len(), &idle) {
+            ScalingPlan::Keep => return Ok(()),
+            ScalingPlan::Grow(size) => {
+                info!(
+                    pool = %id,
+                    from = requested.max(nodes.len()),
+                    to = size,
+                    "Scaling pool up"
+                );
+                return provisioner.resize(id, size).await;
+            }
+            ScalingPlan::Shrink(idle) => idle,
+        };
+        if !provisioner.deletes_nodes() {
+            info!(pool = %id, nodes = ?idle, "Scaling pool down");
+            return provisioner.remove(id, &idle).await;
+        }
+
+        // Cordon nodes first, then make sure no session has been scheduled in the meantime
+        for name in &idle {
+            self.set_node_unschedulable(name, true).await?;
+        }
+        let sessions = self.list_sessions().await?;
+        let mut removed = Vec::new();
+        for name in idle {
+            if sessions.values().any(|session| session.node == name) {
+                self.set_node_unschedulable(&name, false).await?;
+            } else {
+                removed.push(name);
+            }
+        }
+        if removed.is_empty() {
+            return Ok(());
+        }
+        info!(
+            pool = %id,
+            from = schedulable.len(),
+            to = schedulable.len() - removed.len(),
+            nodes = ?removed,
+            "Scaling pool down"
+        );
+        provisioner.remove(id, &removed).await
+    }
+
+    /// Cordons or uncordons pool `id` and all its nodes
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn set_pool_cordoned(&self, id: &str, cordoned: bool) -> Result<()> {
+        let nodes = self.list_pool_nodes(id).await?;
+        let configuration = self.list_pool_configurations().await?.remove(id);
+        // Unknown pools would otherwise be created
+        if configuration.is_none() && nodes.is_empty() {
+            return Err(Error::NotFound("pool"));
+        }
+        let mut pool = configuration.unwrap_or_default();
+        pool.cordoned = cordoned;
+        let value = serde_yaml::to_string(&pool).map_err(|err| Error::Failure(err.into()))?;
+        let client = new_client().await?;
+        ensure_config_map(client.clone(), &self.env.namespace, POOLS_CONFIG_MAP).await?;
+        set_config_map_value(client, &self.env.namespace, POOLS_CONFIG_MAP, id, &value).await?;
+
+        for node in nodes {
+            self.set_node_unschedulable(&node_name(&node), cordoned)
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(session_id = %session_id))]
+    async fn set_drain_deadline(&self, session_id: &str, deadline: u64) -> Result<()> {
+        let client = new_client().await?;
+        let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
+        let patch = Patch::Merge(json!({
+            "metadata": { "annotations": { DRAIN_DEADLINE_ANNOTATION: deadline.to_string() } }
+        }));
+        pod_api
+            .patch(&pod_name(session_id), &PatchParams::default(), &patch)
             .await
-            .map(|l| l.items)
             .map_err(|err| Error::Failure(err.into()))?;
+        Ok(())
+    }
+
+    /// Cordons node `name` and sets a drain deadline on all its sessions, `grace_period` from now.
+    /// Returns the drained sessions.
+    #[instrument(skip_all, fields(name = %name))]
+    pub async fn drain_node(&self, name: &str, grace_period: Duration) -> Result<Vec<Session>> {
+        self.set_node_unschedulable(name, true).await?;
+
+        let deadline = unix_time(SystemTime::now() + grace_period);
+        let mut drained = Vec::new();
+        for session in self.list_sessions().await?.into_values() {
+            // Keep earlier deadlines
+            if session.node != name || session.drain_deadline.is_some() {
+                continue;
+            }
+            self.set_drain_deadline(&session_id(&session.user_id), deadline)
+                .await?;
+            drained.push(session);
+        }
+        Ok(drained)
+    }
+
+    /// Cordons pool `id` and drains all its nodes, see `drain_node`
+    #[instrument(skip_all, fields(id = %id))]
+    pub async fn drain_pool(&self, id: &str, grace_period: Duration) -> Result<Vec<Session>> {
+        self.set_pool_cordoned(id, true).await?;
+
+        let mut drained = Vec::new();
+        for node in self.list_pool_nodes(id).await? {
+            drained.extend(self.drain_node(&node_name(&node), grace_period).await?);
+        }
+        Ok(drained)
+    }
+
+    // Waits until the pod of session `id` has been deleted
+    #[instrument(skip_all, fields(id = %id))]
+    async fn wait_for_pod_deletion(&self, id: &str) -> Result<()> {
+        let client = new_client().await?;
+        let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
+        for _ in 0..POD_DELETION_MAX_CHECKS {
+            match pod_api.get(&pod_name(id)).await {
+                Err(kube::Error::Api(response)) if response.code == 404 => return Ok(()),
+                Err(err) => return Err(Error::Failure(err.into())),
+                Ok(_) => sleep(POD_DELETION_CHECK_INTERVAL).await,
+            }
+        }
+        Err(Error::Failure(
+            format!("Pod of session {} has not been deleted", id).into(),
+        ))
+    }
 
-        let default = "default".to_string();
-        let nodes_by_pool: BTreeMap<String, Vec<Node>> =
-            nodes.iter().fold(BTreeMap::new(), |mut acc, node| {
-                if let Some(labels) = node.metadata.labels.clone() {
-                    let key = labels.get(NODE_POOL_LABEL).unwrap_or(&default);
-                    let nodes = acc.entry(key.clone()).or_insert_with(Vec::new);
-                    nodes.push(node.clone());
-                } else {
-                    error!("No labels");
+    /// Recreates `session` on another node of its pool. The remaining duration is kept, but not the session content.
+    /// Elapsed time is added to its creator usage, as the new pod starts afresh.
+    #[instrument(skip_all, fields(id = %session.user_id))]
+    pub async fn migrate_session(&self, session: &Session) -> Result<()> {
+        let id = &session_id(&session.user_id);
+        let pool_id = session
+            .pool
+            .clone()
+            .unwrap_or_else(|| self.configuration.session.get().pool_affinity);
+        // Sessions are moved to another node of their pool, otherwise of the default pool
+        let default_pool_id = self.configuration.session.get().pool_affinity;
+        let mut pool_ids = vec![pool_id.clone()];
+        if default_pool_id != pool_id {
+            pool_ids.push(default_pool_id);
+        }
+        let mut target = None;
+        for pool_id in pool_ids {
+            match self.target(id, &session.template, &pool_id).await {
+                Ok(candidate) if candidate.node.is_some() => {
+                    target = Some(candidate);
+                    break;
                 }
-                acc
-            });
+                Ok(_) => info!(session_id = %id, pool = %pool_id, "No node can host session"),
+                Err(err @ Error::Unavailable(_)) | Err(err @ Error::NotFound("pool")) => {
+                    info!(session_id = %id, pool = %pool_id, reason = %err, "Pool unavailable")
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        // Nothing is deleted unless a node can host the session and its usage has been recorded
+        let target = target.ok_or_else(|| {
+            Error::Unavailable(format!("no node is available to migrate session {}", id))
+        })?;
+        self.record_usage(session).await?;
+        let elapsed = session
+            .pod
+            .start_time
+            .and_then(|start| start.elapsed().ok())
+            .unwrap_or_default();
+        let duration = session.duration.checked_sub(elapsed).unwrap_or_default();
+
+        self.redeploy_session(session, &target, duration)
+            .await
+            .map_err(|err| {
+                Error::Failure(
+                    format!(
+                        "migration of session {} failed after its deletion started: {}",
+                        id, err
+                    )
+                    .into(),
+                )
+            })
+    }
+
+    // Replaces the pod and service of `session` with new ones on `target`
+    async fn redeploy_session(
+        &self,
+        session: &Session,
+        target: &Target,
+        duration: Duration,
+    ) -> Result<()> {
+        let id = &session_id(&session.user_id);
+        // The ingress route is kept, as the service name doesn't change
+        let client = new_client().await?;
+        let service_api: Api<Service> = Api::namespaced(client.clone(), &self.env.namespace);
+        service_api
+            .delete(&service_name(id), &DeleteParams::default())
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
+        pod_api
+            .delete(&pod_name(id), &DeleteParams::default())
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        self.wait_for_pod_deletion(id).await?;
+
+        info!(
+            session_id = %id,
+            user = %session.user_id,
+            template = %session.template_id,
+            node = %session.node,
+            from = ?session.pool,
+            to = %target.pool_id,
+            "Migrating session"
+        );
+        self.deploy_session(
+            id,
+            &SessionSpec {
+                creator: &session.creator,
+                template_id: &session.template_id,
+                template: &session.template,
+                duration,
+                organizations: &session.organizations,
+            },
+            target,
+        )
+        .await
+    }
+
+    /// Scales all pools with scaling bounds, see `scale_pool`
+    #[instrument(skip_all)]
+    pub async fn scale_pools(&self) -> Result<()> {
+        if self.provisioner.is_none() {
+            return Ok(());
+        }
+        for (id, conf) in self.list_pool_configurations().await? {
+            if conf.cordoned {
+                continue;
+            }
+            if let Some(scaling) = conf.scaling {
+                if let Err(err) = self.scale_pool(&id, &scaling).await {
+                    error!(pool = %id, error = %err, "Failed to scale pool");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn check_api(&self) -> Result<()> {
+        new_client()
+            .await?
+            .apiserver_version()
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        Ok(())
+    }
+
+    async fn check_config_map(&self, name: &str) -> Result<()> {
+        get_config_map(new_client().await?, &self.env.namespace, name).await?;
+        Ok(())
+    }
+
+    async fn check_ingress(&self) -> Result<()> {
+        let ingress_api: Api<Ingress> = Api::namespaced(new_client().await?, &self.env.namespace);
+        ingress_api
+            .get(INGRESS_NAME)
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        Ok(())
+    }
+
+    async fn check_identity_provider(&self) -> Result<()> {
+        self.configuration
+            .identity_provider
+            .check()
+            .await
+            .map_err(Error::Failure)
+    }
+
+    /// Checks all dependencies needed to serve requests: the kubernetes API, required ConfigMaps, the ingress and
+    /// the identity provider. Each check fails after `HEALTH_CHECK_TIMEOUT`.
+    /// The identity provider is not critical: most requests can still be served while it is unreachable.
+    #[instrument(skip_all)]
+    pub async fn check_health(&self) -> BTreeMap<String, HealthCheck> {
+        async fn check(future: impl Future<Output = Result<()>>) -> HealthCheck {
+            match timeout(HEALTH_CHECK_TIMEOUT, future).await {
+                Ok(result) => result.into(),
+                Err(_) => HealthCheck::from(Err::<(), _>("Timed out")),
+            }
+        }
+
+        // Checks run concurrently
+        let (api, users, templates, ingress, identity_provider) = tokio::join!(
+            check(self.check_api()),
+            check(self.check_config_map(USERS_CONFIG_MAP)),
+            check(self.check_config_map(TEMPLATES_CONFIG_MAP)),
+            check(self.check_ingress()),
+            check(self.check_identity_provider()),
+        );
+        BTreeMap::from([
+            ("kubernetes".to_string(), api),
+            (format!("configMap/{}", USERS_CONFIG_MAP), users),
+            (format!("configMap/{}", TEMPLATES_CONFIG_MAP), templates),
+            ("ingress".to_string(), ingress),
+            (
+                format!(
+                    "identityProvider/{}",
+                    self.configuration.identity_provider.name()
+                ),
+                HealthCheck {
+                    critical: false,
+                    ..identity_provider
+                },
+            ),
+        ])
+    }
+
+    /// Lists, for each template, the nodes of each pool where its image has already been pulled
+    #[instrument(skip_all)]
+    pub async fn list_template_images(&self) -> Result<BTreeMap<String, TemplateImage>> {
+        let templates = self.clone().list_templates().await?;
+        let pools = nodes_by_pool(
+            self.list_nodes().await?,
+            &self.pool_label,
+            &self.list_pool_configurations().await?,
+        );
 
-        Ok(nodes_by_pool
+        Ok(templates
             .into_iter()
-            .flat_map(|(s, v)| match self.clone().nodes_to_pool(s.clone(), v) {
-                Ok(pool) => Some((s, pool)),
-                Err(_) => None,
+            .map(|(id, template)| {
+                let pools: BTreeMap<String, Vec<String>> = pools
+                    .iter()
+                    .map(|(pool_id, nodes)| {
+                        (
+                            pool_id.clone(),
+                            nodes
+                                .iter()
+                                .filter(|node| node_has_image(node, &template.image))
+                                .map(node_hostname)
+                                .collect(),
+                        )
+                    })
+                    .collect();
+                let missing = pools.values().all(Vec::is_empty);
+                (
+                    id,
+                    TemplateImage {
+                        image: template.image,
+                        pools,
+                        missing,
+                    },
+                )
             })
             .collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POOL_LABEL: &str = "cloud.google.com/gke-nodepool";
+
+    fn node(labels: &[(&str, &str)]) -> Node {
+        Node {
+            metadata: ObjectMeta {
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn pools(selectors: &[(&str, &[(&str, &str)])]) -> BTreeMap<String, PoolConfiguration> {
+        selectors
+            .iter()
+            .map(|(id, selector)| {
+                (
+                    id.to_string(),
+                    PoolConfiguration {
+                        node_selector: Some(
+                            selector
+                                .iter()
+                                .map(|(key, value)| (key.to_string(), value.to_string()))
+                                .collect(),
+                        ),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn running_sessions_are_booked_for_their_whole_duration() {
+        let now = 10 * 3600;
+        let started = Some(UNIX_EPOCH + Duration::from_secs(now - 3600));
+        let intervals = [
+            (0, 3600),
+            booked(started, Duration::from_secs(3 * 3600), now),
+            booked(None, Duration::from_secs(3600), now),
+        ];
+        assert_eq!(intervals[1], (now - 3600, now + 2 * 3600));
+        assert_eq!(intervals[2], (now, now + 3600));
+        assert_eq!(hours_since(&intervals, 0), 5.0);
+        assert_eq!(hours_since(&intervals, now - 1800), 3.5);
+    }
+
+    #[test]
+    fn nodes_belong_to_their_labelled_pool() {
+        let pools = pools(&[]);
+        assert_eq!(
+            node_pool(&node(&[(POOL_LABEL, "gpu")]), POOL_LABEL, &pools),
+            "gpu"
+        );
+        assert_eq!(node_pool(&node(&[]), POOL_LABEL, &pools), DEFAULT_POOL);
+    }
+
+    #[test]
+    fn configured_selectors_win_in_order() {
+        let pools = pools(&[("a", &[("disk", "ssd")]), ("b", &[("zone", "eu")])]);
+        let both = node(&[("disk", "ssd"), ("zone", "eu"), (POOL_LABEL, "c")]);
+        assert_eq!(node_pool(&both, POOL_LABEL, &pools), "a");
+        let zone = node(&[("zone", "eu"), (POOL_LABEL, "c")]);
+        assert_eq!(node_pool(&zone, POOL_LABEL, &pools), "b");
+        let other = node(&[("zone", "us"), (POOL_LABEL, "c")]);
+        assert_eq!(node_pool(&other, POOL_LABEL, &pools), "c");
+    }
+
+    #[test]
+    fn nodes_are_grouped_by_pool() {
+        let pools = pools(&[("a", &[("disk", "ssd")])]);
+        let grouped = nodes_by_pool(
+            vec![
+                node(&[("disk", "ssd")]),
+                node(&[]),
+                node(&[("disk", "ssd")]),
+            ],
+            POOL_LABEL,
+            &pools,
+        );
+        let sizes: Vec<(&str, usize)> = grouped
+            .iter()
+            .map(|(id, nodes)| (id.as_str(), nodes.len()))
+            .collect();
+        assert_eq!(sizes, vec![("a", 2), (DEFAULT_POOL, 1)]);
+    }
+}
@@ -0,0 +1,108 @@
+//! Shared by the `playground` server and the `playground-admin` CLI
+#![feature(async_closure, proc_macro_hygiene, decl_macro)]
+
+pub mod api;
+pub mod audit;
+pub mod error;
+pub mod github;
+pub mod history;
+pub mod identity;
+pub mod jsonl;
+pub mod kubernetes;
+pub mod listing;
+pub mod logging;
+pub mod login;
+pub mod manager;
+pub mod metrics;
+pub mod openapi;
+pub mod placement;
+pub mod prometheus;
+pub mod scaling;
+pub mod settings;
+pub mod telemetry;
+pub mod token;
+pub mod types;
+
+use crate::manager::Manager;
+use rocket::{routes, Route};
+
+pub struct Context {
+    pub manager: Manager,
+}
+
+/// Routes mounted in /api. Deprecated in favor of `v1_routes`.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        api::get,
+        api::get_unlogged,
+        // Users
+        api::get_user,
+        api::list_users,
+        api::create_user,
+        api::update_user,
+        api::delete_user,
+        // API tokens
+        api::list_current_user_api_tokens,
+        api::create_current_user_api_token,
+        api::delete_current_user_api_token,
+        api::list_api_tokens,
+        api::delete_api_token,
+        // Current Session
+        api::get_current_session,
+        api::get_current_session_unlogged,
+        api::create_current_session,
+        api::create_current_session_unlogged,
+        api::update_current_session,
+        api::update_current_session_unlogged,
+        api::delete_current_session,
+        api::delete_current_session_unlogged,
+        api::migrate_current_session,
+        api::migrate_current_session_unlogged,
+        // Sessions
+        api::get_session,
+        api::list_sessions,
+        api::create_session,
+        api::update_session,
+        api::delete_session,
+        api::migrate_session,
+        // Pools
+        api::get_pool,
+        api::list_pools,
+        api::cordon_pool,
+        api::uncordon_pool,
+        api::drain_pool,
+        // Nodes
+        api::cordon_node,
+        api::uncordon_node,
+        api::drain_node,
+        // Templates
+        api::list_template_images,
+        api::import_template,
+        // Audit
+        api::list_audit_entries,
+        api::export_audit_entries,
+        api::list_session_history,
+        api::get_usage_report,
+        // Login
+        api::oauth_login,
+        api::oauth_callback,
+        api::login,
+        api::logout,
+        // Spec
+        api::openapi,
+    ]
+}
+
+/// Routes mounted in /api/v1. Same as `api_routes`, with paginated lists.
+pub fn v1_routes() -> Vec<Route> {
+    let paginated = ["list_users", "list_sessions", "list_pools"];
+    api_routes()
+        .into_iter()
+        .filter(|route| !route.name.map_or(false, |name| paginated.contains(&name)))
+        .chain(routes![
+            api::list_users_page,
+            api::list_sessions_page,
+            api::list_pools_page,
+        ])
+        .collect()
+}
@@ -0,0 +1,411 @@
+//! Pagination, filtering and sorting of the /api/v1 list endpoints
+//!
+//! Items are sorted by a field then by ID. A cursor identifies the last item of a page, so that following pages
+//! stay consistent when items are created or deleted in between calls.
+
+use crate::{
+    error::{Error, Result},
+    types::{Pool, Session, User},
+};
+use rocket::FromForm;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::cmp::Ordering;
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+const CURSOR_SEPARATOR: char = '\u{0}';
+
+/// A resource and its ID
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct Item<T> {
+    pub id: String,
+    #[serde(flatten)]
+    pub value: T,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<Item<T>>,
+    /// Number of items matching the filters, across all pages
+    pub total: usize,
+    /// To be provided as `cursor` to get the next page. Not set on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Resources returned by list endpoints
+pub trait Listable {
+    /// Fields items can be sorted on, in addition to `id`
+    const SORT_FIELDS: &'static [&'static str];
+
+    /// Value of `field` (one of `SORT_FIELDS`) compared when sorting
+    fn sort_key(&self, field: &str) -> String;
+}
+
+impl Listable for Session {
+    const SORT_FIELDS: &'static [&'static str] = &["owner", "template", "pool", "phase"];
+
+    fn sort_key(&self, field: &str) -> String {
+        match field {
+            "owner" => self.user_id.clone(),
+            "template" => self.template_id.clone(),
+            "pool" => self.pool.clone().unwrap_or_default(),
+            _ => format!("{:?}", self.pod.phase),
+        }
+    }
+}
+
+impl Listable for User {
+    const SORT_FIELDS: &'static [&'static str] = &["pool"];
+
+    fn sort_key(&self, _field: &str) -> String {
+        self.pool_affinity.clone().unwrap_or_default()
+    }
+}
+
+impl Listable for Pool {
+    const SORT_FIELDS: &'static [&'static str] = &["instanceType", "sessions"];
+
+    fn sort_key(&self, field: &str) -> String {
+        match field {
+            "instanceType" => self.instance_type.clone().unwrap_or_default(),
+            // Padded so that numbers are sorted as strings
+            _ => format!(
+                "{:020}",
+                self.nodes.iter().map(|node| node.sessions).sum::<usize>()
+            ),
+        }
+    }
+}
+
+/// Criteria sessions must all match. `sort` is a field of `Listable::SORT_FIELDS` or `id`, prefixed with `-` for descending order.
+#[derive(FromForm, Clone, Debug, Default, JsonSchema)]
+pub struct SessionQuery {
+    pub template: Option<String>,
+    pub phase: Option<String>,
+    pub pool: Option<String>,
+    pub owner: Option<String>,
+    /// Organization the owner belonged to when the session was created
+    pub org: Option<String>,
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl SessionQuery {
+    fn matches(&self, session: &Session) -> bool {
+        self.template
+            .as_ref()
+            .map_or(true, |template| template == &session.template_id)
+            && self
+                .phase
+                .as_ref()
+                .map_or(true, |phase| phase == &format!("{:?}", session.pod.phase))
+            && self
+                .pool
+                .as_ref()
+                .map_or(true, |pool| Some(pool) == session.pool.as_ref())
+            && self
+                .owner
+                .as_ref()
+                .map_or(true, |owner| owner == &session.user_id)
+            && self
+                .org
+                .as_ref()
+                .map_or(true, |org| session.organizations.contains(org))
+    }
+
+    pub fn page(&self, sessions: impl Iterator<Item = (String, Session)>) -> Result<Page<Session>> {
+        paginate(
+            sessions.filter(|(_, session)| self.matches(session)),
+            self.sort.as_deref(),
+            self.cursor.as_deref(),
+            self.limit,
+        )
+    }
+}
+
+/// Criteria users must all match. See `SessionQuery` for `sort`.
+#[derive(FromForm, Clone, Debug, Default, JsonSchema)]
+pub struct UserQuery {
+    pub role: Option<String>,
+    pub pool: Option<String>,
+    /// Organization the user belonged to when last logged in
+    pub org: Option<String>,
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl UserQuery {
+    fn matches(&self, user: &User) -> bool {
+        self.role
+            .as_ref()
+            .map_or(true, |role| user.roles.contains(role))
+            && self
+                .pool
+                .as_ref()
+                .map_or(true, |pool| Some(pool) == user.pool_affinity.as_ref())
+            && self
+                .org
+                .as_ref()
+                .map_or(true, |org| user.organizations.contains(org))
+    }
+
+    pub fn page(&self, users: impl Iterator<Item = (String, User)>) -> Result<Page<User>> {
+        paginate(
+            users.filter(|(_, user)| self.matches(user)),
+            self.sort.as_deref(),
+            self.cursor.as_deref(),
+            self.limit,
+        )
+    }
+}
+
+/// Criteria pools must all match. See `SessionQuery` for `sort`.
+#[derive(FromForm, Clone, Debug, Default, JsonSchema)]
+pub struct PoolQuery {
+    pub cordoned: Option<bool>,
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl PoolQuery {
+    fn matches(&self, pool: &Pool) -> bool {
+        self.cordoned
+            .map_or(true, |cordoned| cordoned == pool.cordoned)
+    }
+
+    pub fn page(&self, pools: impl Iterator<Item = (String, Pool)>) -> Result<Page<Pool>> {
+        paginate(
+            pools.filter(|(_, pool)| self.matches(pool)),
+            self.sort.as_deref(),
+            self.cursor.as_deref(),
+            self.limit,
+        )
+    }
+}
+
+fn invalid_cursor() -> Error {
+    Error::InvalidValue {
+        field: "cursor",
+        reason: "must be a cursor returned by a previous call".to_string(),
+    }
+}
+
+fn encode_cursor(key: &str, id: &str) -> String {
+    hex::encode(format!("{}{}{}", key, CURSOR_SEPARATOR, id))
+}
+
+fn decode_cursor(cursor: &str) -> Result<(String, String)> {
+    let cursor = hex::decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(invalid_cursor)?;
+    let (key, id) = cursor
+        .split_once(CURSOR_SEPARATOR)
+        .ok_or_else(invalid_cursor)?;
+    Ok((key.to_string(), id.to_string()))
+}
+
+/// Sorts `items` according to `sort`, then returns the (at most) `limit` ones following `cursor`
+fn paginate<T: Listable>(
+    items: impl Iterator<Item = (String, T)>,
+    sort: Option<&str>,
+    cursor: Option<&str>,
+    limit: Option<usize>,
+) -> Result<Page<T>> {
+    let sort = sort.unwrap_or("id");
+    let (field, descending) = match sort.strip_prefix('-') {
+        Some(field) => (field, true),
+        None => (sort, false),
+    };
+    if field != "id" && !T::SORT_FIELDS.contains(&field) {
+        return Err(Error::InvalidValue {
+            field: "sort",
+            reason: format!(
+                "must be one of id, {}, optionally prefixed with -",
+                T::SORT_FIELDS.join(", ")
+            ),
+        });
+    }
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(Error::InvalidValue {
+            field: "limit",
+            reason: format!("must be between 1 and {}", MAX_LIMIT),
+        });
+    }
+
+    let key = |id: &str, item: &T| {
+        if field == "id" {
+            id.to_string()
+        } else {
+            item.sort_key(field)
+        }
+    };
+    let order = |a: (&str, &str), b: (&str, &str)| {
+        let ordering = a.cmp(&b);
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    };
+    let mut keyed: Vec<(String, Item<T>)> = items
+        .map(|(id, value)| (key(&id, &value), Item { id, value }))
+        .collect();
+    keyed.sort_by(|(a_key, a), (b_key, b)| {
+        order(
+            (a_key.as_str(), a.id.as_str()),
+            (b_key.as_str(), b.id.as_str()),
+        )
+    });
+
+    let total = keyed.len();
+    let start = match cursor {
+        Some(cursor) => {
+            let (cursor_key, cursor_id) = decode_cursor(cursor)?;
+            keyed
+                .iter()
+                .position(|(key, item)| {
+                    order(
+                        (key.as_str(), item.id.as_str()),
+                        (cursor_key.as_str(), cursor_id.as_str()),
+                    ) == Ordering::Greater
+                })
+                .unwrap_or(total)
+        }
+        None => 0,
+    };
+    let end = (start + limit).min(total);
+    let next_cursor = if end < total {
+        keyed
+            .get(end - 1)
+            .map(|(key, item)| encode_cursor(key, &item.id))
+    } else {
+        None
+    };
+    Ok(Page {
+        items: keyed
+            .into_iter()
+            .skip(start)
+            .take(end - start)
+            .map(|(_, item)| item)
+            .collect(),
+        total,
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(pool: &str, organizations: &[&str]) -> User {
+        User {
+            roles: Vec::new(),
+            pool_affinity: Some(pool.to_string()),
+            quota: None,
+            organizations: organizations.iter().map(|org| org.to_string()).collect(),
+            teams: Vec::new(),
+        }
+    }
+
+    fn users() -> Vec<(String, User)> {
+        vec![
+            ("alice".to_string(), user("b", &["parity"])),
+            ("bob".to_string(), user("a", &[])),
+            ("carol".to_string(), user("b", &["parity", "web3"])),
+            ("dave".to_string(), user("c", &["web3"])),
+        ]
+    }
+
+    fn ids<T>(page: &Page<T>) -> Vec<&str> {
+        page.items.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    // Follows cursors until the last page, returning all IDs
+    fn all_ids(sort: Option<&str>, limit: usize) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = paginate(users().into_iter(), sort, cursor.as_deref(), Some(limit)).unwrap();
+            assert_eq!(page.total, 4);
+            result.extend(page.items.iter().map(|item| item.id.clone()));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return result,
+            }
+        }
+    }
+
+    #[test]
+    fn pages_follow_each_other() {
+        assert_eq!(all_ids(None, 3), vec!["alice", "bob", "carol", "dave"]);
+        assert_eq!(
+            all_ids(Some("pool"), 1),
+            vec!["bob", "alice", "carol", "dave"]
+        );
+    }
+
+    #[test]
+    fn descending_order() {
+        assert_eq!(
+            all_ids(Some("-id"), 3),
+            vec!["dave", "carol", "bob", "alice"]
+        );
+        // Ties are broken by ID, in the same order
+        assert_eq!(
+            all_ids(Some("-pool"), 2),
+            vec!["dave", "carol", "alice", "bob"]
+        );
+    }
+
+    #[test]
+    fn cursor_survives_deletions() {
+        let page = paginate(users().into_iter(), None, None, Some(2)).unwrap();
+        assert_eq!(ids(&page), vec!["alice", "bob"]);
+        let remaining = users().into_iter().filter(|(id, _)| id != "bob");
+        let page = paginate(remaining, None, page.next_cursor.as_deref(), Some(2)).unwrap();
+        assert_eq!(ids(&page), vec!["carol", "dave"]);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        for (sort, cursor, limit, field) in [
+            (Some("roles"), None, None, "sort"),
+            (Some("-"), None, None, "sort"),
+            (None, Some("zz"), None, "cursor"),
+            (None, Some("616c696365"), None, "cursor"),
+            (None, None, Some(0), "limit"),
+            (None, None, Some(MAX_LIMIT + 1), "limit"),
+        ] {
+            match paginate(users().into_iter(), sort, cursor, limit) {
+                Err(Error::InvalidValue { field: invalid, .. }) => assert_eq!(invalid, field),
+                other => panic!("unexpected {:?}", other.map(|page| page.total)),
+            }
+        }
+    }
+
+    #[test]
+    fn filter_by_organization() {
+        let query = UserQuery {
+            org: Some("parity".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(&query.page(users().into_iter()).unwrap()),
+            vec!["alice", "carol"]
+        );
+        let query = UserQuery {
+            org: Some("web3".to_string()),
+            pool: Some("c".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.page(users().into_iter()).unwrap()), vec!["dave"]);
+    }
+}
@@ -0,0 +1,160 @@
+//! Structured logging
+//!
+//! Logs are printed as text, or as JSON lines when `LOG_FORMAT` is `json`. Verbosity is set via `RUST_LOG`.
+//! Each HTTP request is handled within a `request` span carrying its ID, so that all logs emitted while handling it
+//! (including in `Manager` and `Engine`) share the same `request_id`.
+
+use crate::telemetry;
+use opentelemetry::propagation::Extractor;
+use rand::{thread_rng, RngCore};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::HeaderMap,
+    Data, Request, Response,
+};
+use std::{cell::RefCell, env, error::Error, io, time::Instant};
+use tracing::{debug, field, info, info_span, span::EnteredSpan};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Registry,
+};
+
+/// Header carrying the ID of a request. Provided values are reused, so that IDs can be propagated by proxies.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+const HEALTH_PATH: &str = "/health";
+
+struct CurrentRequest {
+    id: String,
+    start: Instant,
+    // Exited when dropped
+    _span: EnteredSpan,
+}
+
+thread_local! {
+    // Rocket handles a request on a single thread, from `on_request` to `on_response`
+    static CURRENT_REQUEST: RefCell<Option<CurrentRequest>> = RefCell::new(None);
+}
+
+/// If logs are printed as JSON lines
+pub fn json_enabled() -> bool {
+    env::var("LOG_FORMAT").map_or(false, |format| format == "json")
+}
+
+/// Installs the global logger printing to stdout, also forwarding `log` records
+pub fn init() -> Result<(), Box<dyn Error>> {
+    init_with_writer(io::stdout)
+}
+
+/// Installs the global logger printing to `writer`, also forwarding `log` records
+pub fn init_with_writer<W>(writer: W) -> Result<(), Box<dyn Error>>
+where
+    W: for<'a> MakeWriter<'a> + Clone + Send + Sync + 'static,
+{
+    let (text_layer, json_layer) = if json_enabled() {
+        (
+            None,
+            Some(
+                fmt::layer()
+                    .with_writer(writer)
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true),
+            ),
+        )
+    } else {
+        (Some(fmt::layer().with_writer(writer)), None)
+    };
+    Registry::default()
+        .with(EnvFilter::from_default_env())
+        .with(text_layer)
+        .with(json_layer)
+        .with(telemetry::layer()?)
+        .try_init()?;
+    Ok(())
+}
+
+fn new_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// ID of the request handled by the current thread, if any
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST.with(|current| current.borrow().as_ref().map(|request| request.id.clone()))
+}
+
+// Reads the trace context propagated by the caller from request headers
+struct HeaderExtractor<'a>(&'a HeaderMap<'a>);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get_one(key)
+    }
+
+    // Only used by propagators reading arbitrary headers, unlike the installed `TraceContextPropagator`
+    fn keys(&self) -> Vec<&str> {
+        Vec::new()
+    }
+}
+
+/// Handles each request within a `request` span, child of the trace propagated via `traceparent` if any, and logs
+/// its outcome
+pub struct RequestLogger;
+
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .filter(|id| is_valid_request_id(id))
+            .map_or_else(new_request_id, ToString::to_string);
+        let span = info_span!(
+            "request",
+            request_id = %id,
+            method = %request.method(),
+            path = %request.uri().path(),
+            trace_id = field::Empty,
+        );
+        telemetry::set_parent(&span, &HeaderExtractor(request.headers()));
+        telemetry::record_trace_id(&span);
+        CURRENT_REQUEST.with(|current| {
+            *current.borrow_mut() = Some(CurrentRequest {
+                id,
+                start: Instant::now(),
+                _span: span.entered(),
+            })
+        });
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if let Some(current) = CURRENT_REQUEST.with(|current| current.borrow_mut().take()) {
+            response.set_raw_header(REQUEST_ID_HEADER, current.id.clone());
+            let route = request.route().map_or("", |route| route.uri.path());
+            let status = response.status().code;
+            let duration_ms = current.start.elapsed().as_millis() as u64;
+            // Probes would flood logs
+            if route.starts_with(HEALTH_PATH) {
+                debug!(route, status, duration_ms, "Handled request");
+            } else {
+                info!(route, status, duration_ms, "Handled request");
+            }
+        }
+    }
+}
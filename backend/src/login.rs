@@ -0,0 +1,273 @@
+//! Server-side store of login sessions
+//!
+//! Browsers only get an opaque ID (as private cookie). The identity provider access token
+//! and the resolved `LoggedUser` are kept in memory, so that the identity provider is not called on every request.
+//! Access tokens are also persisted (see `StoredLogin`) so that logins survive restarts; identities are then resolved again.
+
+use crate::{identity::Identity, types::LoggedUser};
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::error;
+
+#[derive(Clone, Debug)]
+pub struct Login {
+    pub access_token: String,
+    /// `None` until first resolved
+    pub identity: Option<Identity>,
+    /// Cached user, `None` when it must be resolved again
+    pub user: Option<LoggedUser>,
+    /// Restored after a restart, i.e. already audited
+    pub restored: bool,
+    created_at: Instant,
+    refreshed_at: Instant,
+}
+
+/// The persisted part of a `Login`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredLogin {
+    pub access_token: String,
+    /// Creation time, as seconds since UNIX epoch
+    pub created_at: u64,
+}
+
+impl Login {
+    pub fn stored(&self) -> StoredLogin {
+        let created_at = SystemTime::now()
+            .checked_sub(self.created_at.elapsed())
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        StoredLogin {
+            access_token: self.access_token.clone(),
+            created_at,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LoginStore {
+    logins: Arc<Mutex<HashMap<String, Login>>>,
+    ttl: Duration,
+}
+
+impl LoginStore {
+    pub fn new(ttl: Duration) -> Self {
+        LoginStore {
+            logins: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    // Apply `f` to the store, if the lock can be acquired
+    fn with_logins<T>(&self, f: impl FnOnce(&mut HashMap<String, Login>) -> T) -> Option<T> {
+        match self.logins.lock() {
+            Ok(mut logins) => Some(f(&mut logins)),
+            Err(_) => {
+                error!("Failed to acquire logins lock");
+                None
+            }
+        }
+    }
+
+    /// Creates a new login for `access_token` and returns its ID
+    pub fn create(&self, access_token: String) -> String {
+        let mut bytes = [0u8; 32];
+        thread_rng().fill_bytes(&mut bytes);
+        let id = hex::encode(bytes);
+        let now = Instant::now();
+        self.with_logins(|logins| {
+            logins.insert(
+                id.clone(),
+                Login {
+                    access_token,
+                    identity: None,
+                    user: None,
+                    restored: false,
+                    created_at: now,
+                    refreshed_at: now,
+                },
+            )
+        });
+        id
+    }
+
+    /// Restores a persisted login. Returns `false` if it couldn't be restored, e.g. because it has expired.
+    pub fn restore(&self, id: String, stored: StoredLogin) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let age = Duration::from_secs(now.saturating_sub(stored.created_at));
+        let created_at = match Instant::now().checked_sub(age) {
+            Some(created_at) if age < self.ttl => created_at,
+            _ => return false,
+        };
+        self.with_logins(|logins| {
+            logins.insert(
+                id,
+                Login {
+                    access_token: stored.access_token,
+                    identity: None,
+                    user: None,
+                    restored: true,
+                    created_at,
+                    refreshed_at: created_at,
+                },
+            )
+        })
+        .is_some()
+    }
+
+    /// Returns the `Login` matching `id`, unless expired
+    pub fn get(&self, id: &str) -> Option<Login> {
+        let ttl = self.ttl;
+        self.with_logins(|logins| {
+            logins
+                .get(id)
+                .filter(|login| login.created_at.elapsed() < ttl)
+                .cloned()
+        })
+        .flatten()
+    }
+
+    /// Caches the resolved `identity` and `user`
+    pub fn set_user(&self, id: &str, identity: Identity, user: LoggedUser) {
+        self.with_logins(|logins| {
+            if let Some(login) = logins.get_mut(id) {
+                login.identity = Some(identity);
+                login.user = Some(user);
+            }
+        });
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.with_logins(|logins| logins.remove(id));
+    }
+
+    /// Forces `LoggedUser` matching `user_id` to be resolved again on next request
+    pub fn invalidate_user(&self, user_id: &str) {
+        self.with_logins(|logins| {
+            for login in logins.values_mut() {
+                if login.identity.as_ref().map(|identity| identity.id.as_str()) == Some(user_id) {
+                    login.user = None;
+                }
+            }
+        });
+    }
+
+    /// Identity of the most recently refreshed login of `user_id`, if any
+    pub fn identity(&self, user_id: &str) -> Option<Identity> {
+        self.with_logins(|logins| {
+            logins
+                .values()
+                .filter_map(|login| {
+                    login
+                        .identity
+                        .as_ref()
+                        .filter(|identity| identity.id == user_id)
+                        .map(|identity| (login.refreshed_at, identity))
+                })
+                .max_by_key(|(refreshed_at, _)| *refreshed_at)
+                .map(|(_, identity)| identity.clone())
+        })
+        .flatten()
+    }
+
+    /// Returns the ID and access token of resolved logins not refreshed for at least `max_age`
+    pub fn stale(&self, max_age: Duration) -> Vec<(String, String)> {
+        self.with_logins(|logins| {
+            logins
+                .iter()
+                .filter(|(_, login)| {
+                    login.identity.is_some() && login.refreshed_at.elapsed() >= max_age
+                })
+                .map(|(id, login)| (id.clone(), login.access_token.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Updates the identity (e.g. organizations) of a login. Its user will be resolved again on next request.
+    pub fn refresh(&self, id: &str, identity: Identity) {
+        self.with_logins(|logins| {
+            if let Some(login) = logins.get_mut(id) {
+                login.identity = Some(identity);
+                login.user = None;
+                login.refreshed_at = Instant::now();
+            }
+        });
+    }
+
+    /// Removes expired logins, returns the IDs of removed ones
+    pub fn remove_expired(&self) -> Vec<String> {
+        let ttl = self.ttl;
+        self.with_logins(|logins| {
+            let expired: Vec<String> = logins
+                .iter()
+                .filter(|(_, login)| login.created_at.elapsed() >= ttl)
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in &expired {
+                logins.remove(id);
+            }
+            expired
+        })
+        .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60 * 60);
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn stored_logins_are_restored() {
+        let store = LoginStore::new(TTL);
+        let id = store.create("token".to_string());
+        let stored = store.get(&id).unwrap().stored();
+        assert_eq!(stored.access_token, "token");
+        assert!(now() - stored.created_at <= 1);
+
+        let restarted = LoginStore::new(TTL);
+        assert!(restarted.restore(id.clone(), stored.clone()));
+        let login = restarted.get(&id).unwrap();
+        assert!(login.restored);
+        assert!(login.identity.is_none());
+        // Restored logins keep their creation time
+        assert!(login.stored().created_at.saturating_sub(stored.created_at) <= 1);
+    }
+
+    #[test]
+    fn expired_logins_are_not_restored() {
+        let store = LoginStore::new(TTL);
+        let stored = StoredLogin {
+            access_token: "token".to_string(),
+            created_at: now() - TTL.as_secs(),
+        };
+        assert!(!store.restore("id".to_string(), stored));
+        assert!(store.get("id").is_none());
+    }
+
+    #[test]
+    fn expired_logins_are_removed() {
+        let store = LoginStore::new(Duration::from_secs(0));
+        let id = store.create("token".to_string());
+        assert_eq!(store.remove_expired(), vec![id]);
+        assert!(store.remove_expired().is_empty());
+    }
+}
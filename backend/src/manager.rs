@@ -1,21 +1,33 @@
 use crate::{
+    audit::{AuditAction, AuditEntry, AuditFilter, AuditLog, SYSTEM_ACTOR},
     error::{Error, Result},
-    kubernetes::{Configuration, Engine, Environment},
+    history::{HistoryFilter, SessionHistory, UsageGroup},
+    kubernetes::{session_id, unix_time, Configuration, Engine, Environment},
+    listing::{Page, PoolQuery, SessionQuery, UserQuery},
+    logging,
+    login::LoginStore,
     metrics::Metrics,
+    settings::Watcher,
+    telemetry, token,
     types::{
-        LoggedUser, Phase, Pool, Session, SessionConfiguration, SessionUpdateConfiguration,
-        Template, User, UserConfiguration, UserUpdateConfiguration,
+        ApiToken, ApiTokenConfiguration, CreatedApiToken, DrainConfiguration, Features, LoggedUser,
+        Permission, Phase, Pool, Quota, QuotaStatus, Readiness, Session, SessionConfiguration,
+        SessionEndReason, SessionRecord, SessionUpdateConfiguration, Template, TemplateImage,
+        Usage, UsageReport, User, UserConfiguration, UserUpdateConfiguration, Version,
     },
 };
-use log::{error, info, warn};
+use schemars::JsonSchema;
 use serde::Serialize;
+use serde_json::Value;
 use std::{
     collections::{BTreeMap, HashSet},
+    env,
     sync::{Arc, Mutex},
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::runtime::Runtime;
+use tracing::{error, field, info, info_span, instrument, warn};
 
 fn running_sessions(sessions: Vec<&Session>) -> Vec<&Session> {
     sessions
@@ -28,19 +40,29 @@ fn running_sessions(sessions: Vec<&Session>) -> Vec<&Session> {
 pub struct Manager {
     pub engine: Engine,
     pub metrics: Metrics,
+    pub logins: LoginStore,
+    pub audit: AuditLog,
+    pub history: SessionHistory,
+    settings: Watcher,
     sessions: Arc<Mutex<HashSet<String>>>,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, JsonSchema)]
 pub struct Playground {
     pub env: Environment,
     pub configuration: Configuration,
     pub templates: BTreeMap<String, Template>,
     pub user: Option<LoggedUser>,
+    pub quota: Option<QuotaStatus>,
 }
 
 impl Manager {
     const SLEEP_TIME: Duration = Duration::from_secs(60);
+    const DEFAULT_TOKEN_DURATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+    const MAX_TOKEN_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+    const LOGIN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    const LOGIN_REFRESH_TIME: Duration = Duration::from_secs(10 * 60);
+    const DRAIN_GRACE_PERIOD: Duration = Duration::from_secs(30 * 60);
 
     pub async fn new() -> Result<Self> {
         let metrics = Metrics::new().map_err(|err| Error::Failure(err.into()))?;
@@ -55,19 +77,42 @@ impl Manager {
                 engine.clone().patch_ingress(&running).await?;
 
                 if running.is_empty() {
-                    info!("No sesssions restored");
+                    info!("No sessions restored");
                 } else {
-                    info!("Restored sesssions for {:?}", running.keys());
+                    info!(sessions = ?running.keys(), "Restored sessions");
                 }
             }
             Err(err) => error!(
-                "Failed to call list_all: {}. Existing sessions won't be accessible",
-                err
+                error = %err,
+                "Failed to call list_all. Existing sessions won't be accessible"
+            ),
+        }
+        // Restore logins persisted before a restart, expired ones are deleted
+        let logins = LoginStore::new(Manager::LOGIN_TTL);
+        match engine.list_logins().await {
+            Ok(stored) => {
+                let mut restored = 0;
+                for (id, login) in stored {
+                    if logins.restore(id.clone(), login) {
+                        restored += 1;
+                    } else if let Err(err) = engine.delete_login(&id).await {
+                        warn!(error = %err, "Failed to delete expired login");
+                    }
+                }
+                info!(logins = restored, "Restored logins");
+            }
+            Err(err) => error!(
+                error = %err,
+                "Failed to list logins. Users will have to log in again"
             ),
         }
         Ok(Manager {
             engine,
             metrics,
+            logins,
+            audit: AuditLog::from_env(),
+            history: SessionHistory::from_env(),
+            settings: Watcher::default(),
             sessions: Arc::new(Mutex::new(HashSet::new())), // Temp map used to track session deployment time
         })
     }
@@ -76,6 +121,17 @@ impl Manager {
         thread::spawn(move || loop {
             thread::sleep(Manager::SLEEP_TIME);
 
+            let span = info_span!("controller", trace_id = field::Empty);
+            telemetry::record_trace_id(&span);
+            let _enter = span.enter();
+
+            // Pick up changes of the configuration file
+            match self.settings.reload(&self.engine.configuration.session) {
+                Some(Ok(())) => info!("Reloaded configuration"),
+                Some(Err(err)) => warn!(error = %err, "Ignored invalid configuration"),
+                None => {}
+            }
+
             // Track some deployments metrics
             if let Ok(runtime) = new_runtime() {
                 let sessions_thread = self.clone().sessions.clone();
@@ -92,21 +148,24 @@ impl Manager {
                                         if let Some(duration) =
                                             &session.pod.start_time.and_then(|p| p.elapsed().ok())
                                         {
-                                            self.clone()
-                                                .metrics
-                                                .observe_deploy_duration(duration.as_secs_f64());
+                                            self.metrics.observe_deploy_duration(
+                                                &session.template_id,
+                                                session.pool.as_deref(),
+                                                duration.as_secs_f64(),
+                                            );
                                         }
                                     }
                                     _ => {}
                                 }
                             }
                             Err(err) => {
-                                warn!("Failed to call get: {}", err);
+                                warn!(session_id = %id, error = %err, "Failed to get session");
                                 sessions2.remove(id);
                             }
-                            Ok(None) => warn!("No matching pod: {}", id),
+                            Ok(None) => warn!(session_id = %id, "No matching pod"),
                         }
                     }
+                    self.metrics.set_queued_users(sessions2.len());
                 } else {
                     error!("Failed to acquire sessions lock");
                 }
@@ -114,29 +173,119 @@ impl Manager {
                 // Go through all Running pods and figure out if they have to be undeployed
                 match runtime.block_on(self.engine.list_sessions()) {
                     Ok(sessions) => {
+                        self.metrics.set_sessions(sessions.values());
+
+                        let now = unix_time(SystemTime::now());
                         for session in sessions.values() {
-                            if let Some(duration) =
-                                &session.pod.start_time.and_then(|p| p.elapsed().ok())
-                            {
-                                if duration > &session.duration {
-                                    info!("Undeploying {}", session.user_id);
-
-                                    match runtime.block_on(
-                                        self.engine.delete_session(&session_id(&session.user_id)),
-                                    ) {
-                                        Ok(()) => (),
-                                        Err(err) => {
-                                            warn!(
-                                                "Error while undeploying {}: {}",
-                                                session.user_id, err
-                                            )
-                                        }
-                                    }
+                            let expired = session
+                                .pod
+                                .start_time
+                                .and_then(|p| p.elapsed().ok())
+                                .map_or(false, |duration| duration > session.duration);
+                            // Sessions left on drained nodes past their deadline
+                            let drained = session
+                                .drain_deadline
+                                .map_or(false, |deadline| deadline <= now);
+                            if expired || drained {
+                                info!(
+                                    user = %session.user_id,
+                                    template = %session.template_id,
+                                    pool = ?session.pool,
+                                    expired,
+                                    drained,
+                                    "Undeploying session"
+                                );
+
+                                let reason = if expired {
+                                    SessionEndReason::Expired
+                                } else {
+                                    SessionEndReason::Drained
+                                };
+                                let id = session_id(&session.user_id);
+                                let result = runtime.block_on(self.engine.delete_session(&id));
+                                match &result {
+                                    Ok(Some(session)) => self.record_session_end(session, reason),
+                                    Ok(None) => {}
+                                    Err(err) => warn!(
+                                        session_id = %id,
+                                        error = %err,
+                                        "Error while undeploying"
+                                    ),
                                 }
+                                self.audit.record(AuditEntry::new(
+                                    SYSTEM_ACTOR,
+                                    AuditAction::DeleteSession,
+                                    &id,
+                                    Value::Null,
+                                    result.as_ref().err().map(ToString::to_string),
+                                ));
                             }
                         }
                     }
-                    Err(err) => error!("Failed to call list_all: {}", err),
+                    Err(err) => error!(error = %err, "Failed to call list_all"),
+                }
+
+                // Refresh pools capacity
+                match runtime.block_on(self.engine.list_pools()) {
+                    Ok(pools) => self.metrics.set_pools(
+                        &pools,
+                        self.engine.configuration.session.get().max_sessions_per_pod,
+                    ),
+                    Err(err) => error!(error = %err, "Failed to list pools"),
+                }
+
+                // Grow or shrink pools according to their sessions
+                if let Err(err) = runtime.block_on(self.engine.scale_pools()) {
+                    error!(error = %err, "Failed to scale pools");
+                }
+
+                // Remove expired API tokens
+                match runtime.block_on(self.engine.list_api_tokens()) {
+                    Ok(tokens) => {
+                        for (hash, token) in tokens.iter().filter(|(_, token)| token.is_expired()) {
+                            let result = runtime.block_on(self.engine.delete_api_token(hash));
+                            match &result {
+                                Ok(()) => info!(
+                                    user = %token.user_id,
+                                    token = %token.name,
+                                    "Removed expired token"
+                                ),
+                                Err(err) => warn!(
+                                    user = %token.user_id,
+                                    token = %token.name,
+                                    error = %err,
+                                    "Error while removing expired token"
+                                ),
+                            }
+                            self.audit.record(AuditEntry::new(
+                                SYSTEM_ACTOR,
+                                AuditAction::DeleteApiToken,
+                                &format!("{}/{}", token.user_id, token.name),
+                                Value::Null,
+                                result.as_ref().err().map(ToString::to_string),
+                            ));
+                        }
+                    }
+                    Err(err) => error!(error = %err, "Failed to list API tokens"),
+                }
+
+                // Refresh identities (e.g. organizations) of logged users
+                let expired = self.logins.remove_expired();
+                if !expired.is_empty() {
+                    info!(logins = expired.len(), "Removed expired logins");
+                }
+                for id in expired {
+                    if let Err(err) = runtime.block_on(self.engine.delete_login(&id)) {
+                        warn!(error = %err, "Failed to delete expired login");
+                    }
+                }
+                let provider = &self.engine.configuration.identity_provider;
+                for (id, access_token) in self.logins.stale(Manager::LOGIN_REFRESH_TIME) {
+                    match runtime.block_on(provider.identity(&access_token)) {
+                        Ok(identity) => self.logins.refresh(&id, identity),
+                        // Keep the cached user, it will be refreshed during next iteration
+                        Err(err) => warn!(error = %err, "Failed to refresh identity"),
+                    }
                 }
             }
         })
@@ -147,27 +296,242 @@ fn new_runtime() -> Result<Runtime> {
     Runtime::new().map_err(|err| Error::Failure(err.into()))
 }
 
-fn session_id(id: &str) -> String {
-    // Create a unique ID for this session. Use lowercase to make sure the result can be used as part of a DNS
-    id.to_string().to_lowercase()
+// Returns the owners of drained sessions. They are notified via the `drain_deadline` of their session,
+// displayed by the frontend until it is migrated.
+fn drained_owners(sessions: &[Session], grace_period: Duration) -> Vec<String> {
+    sessions
+        .iter()
+        .map(|session| {
+            info!(
+                session_id = %session_id(&session.user_id),
+                grace_period_minutes = grace_period.as_secs() / 60,
+                "Session must be migrated"
+            );
+            session.user_id.clone()
+        })
+        .collect()
+}
+
+// Serialized parameters of an audited action
+fn to_parameters<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+fn ensure_permission(user: &LoggedUser, permission: Permission) -> Result<()> {
+    if user.has_permission(permission) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized(permission))
+    }
+}
+
+// API tokens can't be used to manage tokens, a leaked one would otherwise allow to revoke or outlive all others
+fn ensure_login(user: &LoggedUser) -> Result<()> {
+    if user.api_token {
+        Err(Error::Unauthenticated())
+    } else {
+        Ok(())
+    }
+}
+
+fn ensure_quota(quota: &Quota, usage: &Usage, template: &str, duration: Duration) -> Result<()> {
+    if !quota.allows_template(template) {
+        return Err(Error::QuotaExceeded(format!(
+            "template {} is not allowed",
+            template
+        )));
+    }
+    if let Some(max) = quota.max_concurrent_sessions {
+        if usage.concurrent_sessions >= max {
+            return Err(Error::QuotaExceeded(format!(
+                "at most {} concurrent sessions allowed",
+                max
+            )));
+        }
+    }
+    // Sessions are assumed to last for their whole duration
+    ensure_session_hours(quota, usage, duration)
+}
+
+// Fails if `duration` more session time would exceed daily or weekly hours of `quota`
+fn ensure_session_hours(quota: &Quota, usage: &Usage, duration: Duration) -> Result<()> {
+    let hours = duration.as_secs_f64() / 3600.0;
+    if let Some(max) = quota.max_session_hours_per_day {
+        if usage.session_hours_last_day + hours > max {
+            return Err(Error::QuotaExceeded(format!(
+                "{:.1} of {} session hours per day already used",
+                usage.session_hours_last_day, max
+            )));
+        }
+    }
+    if let Some(max) = quota.max_session_hours_per_week {
+        if usage.session_hours_last_week + hours > max {
+            return Err(Error::QuotaExceeded(format!(
+                "{:.1} of {} session hours per week already used",
+                usage.session_hours_last_week, max
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Sessions owned by `user` require `permission`, others require `any_permission`
+fn ensure_session_permission(
+    user: &LoggedUser,
+    id: &str,
+    permission: Permission,
+    any_permission: Permission,
+) -> Result<()> {
+    if session_id(&user.id) == session_id(id) {
+        ensure_permission(user, permission)
+    } else {
+        ensure_permission(user, any_permission)
+    }
 }
 
 impl Manager {
+    // Runs `f` and records its outcome in the audit log
+    fn audited<T>(
+        &self,
+        actor: &str,
+        action: AuditAction,
+        target: &str,
+        parameters: Value,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let result = f();
+        self.audit.record(AuditEntry::new(
+            actor,
+            action,
+            target,
+            parameters,
+            result.as_ref().err().map(ToString::to_string),
+        ));
+        result
+    }
+
+    // Appends `session` to the history. Failed sessions are always recorded as such.
+    fn record_session_end(&self, session: &Session, reason: SessionEndReason) {
+        let end_reason = if session.pod.phase == Phase::Failed {
+            SessionEndReason::Failure
+        } else {
+            reason
+        };
+        let now = SystemTime::now();
+        let record = SessionRecord {
+            id: session_id(&session.user_id),
+            user_id: session.user_id.clone(),
+            creator: session.creator.clone(),
+            template: session.template_id.clone(),
+            pool: session.pool.clone(),
+            node: session.node.clone(),
+            start: unix_time(session.pod.start_time.unwrap_or(now)),
+            end: unix_time(now),
+            end_reason,
+            deploy_duration: session.pod.deploy_duration,
+        };
+        self.metrics
+            .observe_session_lifetime(end_reason, record.end.saturating_sub(record.start) as f64);
+        if let Err(err) = self.history.record(&record) {
+            error!(
+                session_id = %record.id,
+                user = %record.user_id,
+                template = %record.template,
+                pool = ?record.pool,
+                error = %err,
+                "Failed to record session"
+            );
+        }
+    }
+
+    /// Creates a login for `access_token` and returns its ID.
+    /// A login that can't be persisted is still usable until the next restart.
+    pub fn create_login(&self, access_token: String) -> String {
+        let id = self.logins.create(access_token);
+        if let Some(login) = self.logins.get(&id) {
+            let result = new_runtime().and_then(|runtime| {
+                runtime.block_on(self.engine.store_login(&id, &login.stored()))
+            });
+            if let Err(err) = result {
+                warn!(error = %err, "Failed to persist login");
+            }
+        }
+        id
+    }
+
+    pub fn remove_login(&self, id: &str) {
+        self.logins.remove(id);
+        let result =
+            new_runtime().and_then(|runtime| runtime.block_on(self.engine.delete_login(id)));
+        if let Err(err) = result {
+            warn!(error = %err, "Failed to delete persisted login");
+        }
+    }
+
+    /// Checks all dependencies, see `Engine::check_health`
+    pub fn readiness(&self) -> Result<Readiness> {
+        let checks = new_runtime()?.block_on(self.engine.check_health());
+        for (name, check) in &checks {
+            if let Some(error) = &check.error {
+                warn!(check = %name, error = %error, "Health check failed");
+            }
+        }
+        Ok(Readiness {
+            ready: checks
+                .values()
+                .all(|check| check.healthy || !check.critical),
+            checks,
+        })
+    }
+
+    pub fn version(&self) -> Version {
+        Version {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            sha: env::var("GITHUB_SHA").ok(),
+            features: Features {
+                identity_provider: self
+                    .engine
+                    .configuration
+                    .identity_provider
+                    .name()
+                    .to_string(),
+                node_provisioner: self
+                    .engine
+                    .provisioner
+                    .as_ref()
+                    .map(|provisioner| provisioner.name().to_string()),
+                placement: self.engine.configuration.session.get().placement,
+                tracing: telemetry::enabled(),
+                json_logs: logging::json_enabled(),
+            },
+        }
+    }
+
+    #[instrument(skip_all, fields(user = %user.id))]
     pub fn get(self, user: LoggedUser) -> Result<Playground> {
-        let templates = new_runtime()?.block_on(self.clone().engine.list_templates())?;
+        let runtime = new_runtime()?;
+        let templates = runtime.block_on(self.clone().engine.list_templates())?;
+        let usage = runtime.block_on(self.engine.get_usage(&user.id))?;
         Ok(Playground {
             templates,
+            quota: Some(QuotaStatus {
+                quota: user.quota.clone(),
+                remaining: user.quota.remaining(&usage),
+                usage,
+            }),
             user: Some(user),
             env: self.engine.env,
             configuration: self.engine.configuration,
         })
     }
 
+    #[instrument(skip_all)]
     pub fn get_unlogged(&self) -> Result<Playground> {
         let templates = new_runtime()?.block_on(self.clone().engine.list_templates())?;
         Ok(Playground {
             templates,
             user: None,
+            quota: None,
             env: self.clone().engine.env,
             configuration: self.clone().engine.configuration,
         })
@@ -175,182 +539,594 @@ impl Manager {
 
     // Users
 
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn get_user(&self, user: &LoggedUser, id: &str) -> Result<Option<User>> {
-        if user.id != id && !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
+        if user.id != id {
+            ensure_permission(user, Permission::UserRead)?;
         }
 
         new_runtime()?.block_on(self.engine.get_user(id))
     }
 
+    #[instrument(skip_all, fields(user = %user.id))]
     pub fn list_users(&self, user: &LoggedUser) -> Result<BTreeMap<String, User>> {
-        if !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
-        }
+        ensure_permission(user, Permission::UserRead)?;
 
         new_runtime()?.block_on(self.engine.list_users())
     }
 
+    /// Users matching `query`, see `listing`
+    #[instrument(skip_all, fields(user = %user.id))]
+    pub fn list_users_page(&self, user: &LoggedUser, query: &UserQuery) -> Result<Page<User>> {
+        query.page(self.list_users(user)?.into_iter())
+    }
+
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn create_user(self, user: &LoggedUser, id: String, conf: UserConfiguration) -> Result<()> {
-        if !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
-        }
+        let parameters = to_parameters(&conf);
+        self.audited(&user.id, AuditAction::CreateUser, &id, parameters, || {
+            ensure_permission(user, Permission::UserEdit)?;
 
-        new_runtime()?.block_on(self.engine.create_user(id, conf))
+            new_runtime()?.block_on(self.engine.create_user(id.clone(), conf))?;
+            self.logins.invalidate_user(&id);
+
+            Ok(())
+        })
     }
 
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn update_user(
         self,
         user: LoggedUser,
         id: String,
         conf: UserUpdateConfiguration,
     ) -> Result<()> {
-        if user.id != id && !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
-        }
+        let parameters = to_parameters(&conf);
+        self.audited(&user.id, AuditAction::UpdateUser, &id, parameters, || {
+            // Roles are part of the configuration, so users can't update themselves
+            ensure_permission(&user, Permission::UserEdit)?;
 
-        new_runtime()?.block_on(self.engine.update_user(id, conf))
+            new_runtime()?.block_on(self.engine.update_user(id.clone(), conf))?;
+            self.logins.invalidate_user(&id);
+
+            Ok(())
+        })
     }
 
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn delete_user(self, user: &LoggedUser, id: String) -> Result<()> {
-        if user.id != id && !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
+        let parameters = Value::Null;
+        self.audited(&user.id, AuditAction::DeleteUser, &id, parameters, || {
+            if user.id != id {
+                ensure_permission(user, Permission::UserEdit)?;
+            }
+
+            let runtime = new_runtime()?;
+            // Tokens would otherwise still grant access to a deleted user
+            let tokens = runtime.block_on(self.engine.list_api_tokens())?;
+            for (hash, token) in tokens.iter().filter(|(_, token)| token.user_id == id) {
+                let result = runtime.block_on(self.engine.delete_api_token(hash));
+                self.audit.record(AuditEntry::new(
+                    &user.id,
+                    AuditAction::DeleteApiToken,
+                    &format!("{}/{}", token.user_id, token.name),
+                    Value::Null,
+                    result.as_ref().err().map(ToString::to_string),
+                ));
+                result?;
+                info!(user = %token.user_id, token = %token.name, "Deleted API token");
+            }
+            runtime.block_on(self.engine.delete_user(id.clone()))?;
+            self.logins.invalidate_user(&id);
+
+            Ok(())
+        })
+    }
+
+    // API tokens
+
+    #[instrument(skip_all, fields(user = %user.id, user_id = %user_id))]
+    pub fn list_api_tokens(&self, user: &LoggedUser, user_id: &str) -> Result<Vec<ApiToken>> {
+        ensure_login(user)?;
+        if user.id != user_id {
+            ensure_permission(user, Permission::UserRead)?;
         }
 
-        new_runtime()?.block_on(self.engine.delete_user(id))
+        Ok(new_runtime()?
+            .block_on(self.engine.list_api_tokens())?
+            .into_values()
+            .filter(|token| token.user_id == user_id)
+            .collect())
+    }
+
+    #[instrument(skip_all, fields(user = %user.id, name = %name))]
+    pub fn create_api_token(
+        &self,
+        user: &LoggedUser,
+        name: &str,
+        conf: ApiTokenConfiguration,
+    ) -> Result<CreatedApiToken> {
+        let parameters = to_parameters(&conf);
+        self.audited(
+            &user.id,
+            AuditAction::CreateApiToken,
+            &format!("{}/{}", user.id, name),
+            parameters,
+            || {
+                ensure_login(user)?;
+                // A token can't grant more than what its owner is allowed
+                if let Some(scope) = conf.scopes.difference(&user.permissions).next() {
+                    return Err(Error::Unauthorized(*scope));
+                }
+                let duration = conf.duration.unwrap_or(Manager::DEFAULT_TOKEN_DURATION);
+                if duration > Manager::MAX_TOKEN_DURATION {
+                    return Err(Error::InvalidValue {
+                        field: "duration",
+                        reason: format!(
+                            "must be at most {} days",
+                            Manager::MAX_TOKEN_DURATION.as_secs() / (24 * 60 * 60)
+                        ),
+                    });
+                }
+
+                let runtime = new_runtime()?;
+                // Names are unique per user
+                if runtime
+                    .block_on(self.engine.list_api_tokens())?
+                    .values()
+                    .any(|token| token.user_id == user.id && token.name == name)
+                {
+                    return Err(Error::AlreadyExists("token"));
+                }
+
+                let value = token::generate();
+                let now = unix_time(SystemTime::now());
+                let api_token = ApiToken {
+                    name: name.to_string(),
+                    user_id: user.id.clone(),
+                    scopes: conf.scopes,
+                    organizations: user.organizations.clone(),
+                    teams: user.teams.clone(),
+                    created_at: now,
+                    expires_at: now + duration.as_secs(),
+                };
+                runtime.block_on(
+                    self.engine
+                        .create_api_token(&token::hash(&value), &api_token),
+                )?;
+
+                info!(user = %user.id, token = %name, "Created API token");
+
+                Ok(CreatedApiToken {
+                    token: value,
+                    expires_at: api_token.expires_at,
+                })
+            },
+        )
+    }
+
+    #[instrument(skip_all, fields(user = %user.id, name = %name, user_id = %user_id))]
+    pub fn delete_api_token(&self, user: &LoggedUser, user_id: &str, name: &str) -> Result<()> {
+        let parameters = Value::Null;
+        self.audited(
+            &user.id,
+            AuditAction::DeleteApiToken,
+            &format!("{}/{}", user_id, name),
+            parameters,
+            || {
+                ensure_login(user)?;
+                if user.id != user_id {
+                    ensure_permission(user, Permission::UserEdit)?;
+                }
+
+                let runtime = new_runtime()?;
+                let hash = runtime
+                    .block_on(self.engine.list_api_tokens())?
+                    .into_iter()
+                    .find(|(_, token)| token.user_id == user_id && token.name == name)
+                    .map(|(hash, _)| hash)
+                    .ok_or(Error::NotFound("token"))?;
+                runtime.block_on(self.engine.delete_api_token(&hash))?;
+
+                info!(user = %user_id, token = %name, "Deleted API token");
+
+                Ok(())
+            },
+        )
     }
 
     // Sessions
 
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn get_session(&self, user: &LoggedUser, id: &str) -> Result<Option<Session>> {
-        if session_id(&user.id) != id && !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
-        }
+        ensure_session_permission(
+            user,
+            id,
+            Permission::SessionRead,
+            Permission::SessionReadAny,
+        )?;
 
         new_runtime()?.block_on(self.engine.get_session(id))
     }
 
+    #[instrument(skip_all, fields(user = %user.id))]
     pub fn list_sessions(&self, user: &LoggedUser) -> Result<BTreeMap<String, Session>> {
-        if !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
-        }
+        ensure_permission(user, Permission::SessionReadAny)?;
 
         new_runtime()?.block_on(self.engine.list_sessions())
     }
 
+    /// Sessions matching `query`, see `listing`
+    #[instrument(skip_all, fields(user = %user.id))]
+    pub fn list_sessions_page(
+        &self,
+        user: &LoggedUser,
+        query: &SessionQuery,
+    ) -> Result<Page<Session>> {
+        query.page(self.list_sessions(user)?.into_iter())
+    }
+
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn create_session(
         &self,
         user: &LoggedUser,
         id: &str,
         conf: SessionConfiguration,
     ) -> Result<()> {
-        // Ids can only customized by users with proper rights
-        if session_id(&user.id) != id && !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
-        }
+        let parameters = to_parameters(&conf);
+        self.audited(&user.id, AuditAction::CreateSession, id, parameters, || {
+            // Ids can only customized by users with proper rights
+            ensure_session_permission(
+                user,
+                id,
+                Permission::SessionWrite,
+                Permission::SessionWriteAny,
+            )?;
 
-        if conf.duration.is_some() {
-            // Duration can only customized by users with proper rights
-            if !user.can_customize_duration() {
-                return Err(Error::Unauthorized());
+            if conf.duration.is_some() {
+                // Duration can only customized by users with proper rights
+                ensure_permission(user, Permission::CustomizeDuration)?;
             }
-        }
-        if conf.pool_affinity.is_some() {
-            // Duration can only customized by users with proper rights
-            if !user.can_customize_pool_affinity() {
-                return Err(Error::Unauthorized());
+            if conf.pool_affinity.is_some() {
+                // Pool affinity can only customized by users with proper rights
+                ensure_permission(user, Permission::CustomizePoolAffinity)?;
             }
-        }
 
-        let session_id = session_id(id);
-        // Ensure a workspace with the same id is not alread running
-        if new_runtime()?
-            .block_on(self.engine.get_session(&session_id))?
-            .is_some()
-        {
-            return Err(Error::Unauthorized());
-        }
+            let session_id = session_id(id);
+            // Ensure a workspace with the same id is not alread running
+            if new_runtime()?
+                .block_on(self.engine.get_session(&session_id))?
+                .is_some()
+            {
+                return Err(Error::AlreadyExists("session"));
+            }
 
-        let template = conf.clone().template;
-        let result = new_runtime()?.block_on(self.engine.create_session(user, &session_id, conf));
+            let usage = new_runtime()?.block_on(self.engine.get_usage(&user.id))?;
+            ensure_quota(
+                &user.quota,
+                &usage,
+                &conf.template,
+                conf.duration
+                    .unwrap_or(self.engine.configuration.session.get().duration),
+            )?;
 
-        info!("Created session {} with template {}", session_id, template);
+            let template = conf.clone().template;
+            let pool = conf.pool_affinity.clone();
+            let start = Instant::now();
+            let result =
+                new_runtime()?.block_on(self.engine.create_session(user, &session_id, conf));
 
-        match &result {
-            Ok(_session) => {
-                if let Ok(mut sessions) = self.sessions.lock() {
-                    sessions.insert(session_id);
-                } else {
-                    error!("Failed to acquire sessions lock");
+            match &result {
+                Ok(_session) => {
+                    info!(
+                        session_id = %session_id,
+                        template = %template,
+                        pool = ?pool,
+                        duration_ms = start.elapsed().as_millis() as u64,
+                        "Created session"
+                    );
+                    if let Ok(mut sessions) = self.sessions.lock() {
+                        sessions.insert(session_id);
+                    } else {
+                        error!("Failed to acquire sessions lock");
+                    }
+                    self.metrics.inc_deploy_counter(&template);
+                }
+                Err(e) => {
+                    self.metrics.inc_deploy_failures_counter(&template);
+                    error!(
+                        session_id = %session_id,
+                        template = %template,
+                        pool = ?pool,
+                        error = %e,
+                        "Error during deployment"
+                    );
                 }
-                self.metrics.inc_deploy_counter(&template);
-            }
-            Err(e) => {
-                self.metrics.inc_deploy_failures_counter(&template);
-                error!("Error during deployment {}", e);
             }
-        }
-        result
+            result
+        })
     }
 
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn update_session(
         &self,
         id: &str,
         user: &LoggedUser,
         conf: SessionUpdateConfiguration,
     ) -> Result<()> {
-        if conf.duration.is_some() {
-            // Duration can only customized by users with proper rights
-            if session_id(&user.id) != id && !user.can_customize_duration() {
-                return Err(Error::Unauthorized());
+        let parameters = to_parameters(&conf);
+        self.audited(&user.id, AuditAction::UpdateSession, id, parameters, || {
+            ensure_session_permission(
+                user,
+                id,
+                Permission::SessionWrite,
+                Permission::SessionWriteAny,
+            )?;
+
+            let runtime = new_runtime()?;
+            let session_id = session_id(id);
+            let session = runtime
+                .block_on(self.engine.get_session(&session_id))?
+                .ok_or(Error::NotFound("session"))?;
+            let duration = conf
+                .duration
+                .unwrap_or(self.engine.configuration.session.get().duration);
+            // Creators extending their sessions are bound by their quota. The current duration is already part of their usage.
+            if duration > session.duration && session.creator == user.id {
+                let usage = runtime.block_on(self.engine.get_usage(&user.id))?;
+                ensure_session_hours(&user.quota, &usage, duration - session.duration)?;
             }
-        }
 
-        new_runtime()?.block_on(self.engine.update_session(&session_id(id), conf))
+            runtime.block_on(self.engine.update_session(&session_id, conf))
+        })
     }
 
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
     pub fn delete_session(&self, user: &LoggedUser, id: &str) -> Result<()> {
-        if session_id(&user.id) != id && !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
-        }
-
-        let session_id = session_id(id);
-        let result = new_runtime()?.block_on(self.engine.delete_session(&session_id));
+        let parameters = Value::Null;
+        self.audited(&user.id, AuditAction::DeleteSession, id, parameters, || {
+            ensure_session_permission(
+                user,
+                id,
+                Permission::SessionWrite,
+                Permission::SessionWriteAny,
+            )?;
 
-        info!("Deleted session {}", session_id);
+            let session_id = session_id(id);
+            let result = new_runtime()?.block_on(self.engine.delete_session(&session_id));
 
-        match &result {
-            Ok(_) => {
-                self.metrics.inc_undeploy_counter();
-                if let Ok(mut sessions) = self.sessions.lock() {
-                    sessions.remove(session_id.as_str());
-                } else {
-                    error!("Failed to acquire sessions lock");
+            match &result {
+                Ok(session) => {
+                    info!(session_id = %session_id, "Deleted session");
+                    if let Some(session) = session {
+                        let reason = if session.user_id == user.id {
+                            SessionEndReason::User
+                        } else {
+                            SessionEndReason::Admin
+                        };
+                        self.record_session_end(session, reason);
+                    }
+                    self.metrics.inc_undeploy_counter();
+                    if let Ok(mut sessions) = self.sessions.lock() {
+                        sessions.remove(session_id.as_str());
+                    } else {
+                        error!("Failed to acquire sessions lock");
+                    }
+                }
+                Err(e) => {
+                    self.metrics.inc_undeploy_failures_counter();
+                    error!(session_id = %session_id, error = %e, "Error during undeployment");
                 }
             }
-            Err(e) => {
-                self.metrics.inc_undeploy_failures_counter();
-                error!("Error during undeployment {}", e);
-            }
-        }
-        result
+            result.map(|_| ())
+        })
+    }
+
+    /// Recreates session `id` on another node of its pool, keeping its remaining duration.
+    /// The time spent on its previous node is recorded in the history.
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
+    pub fn migrate_session(&self, user: &LoggedUser, id: &str) -> Result<()> {
+        let parameters = Value::Null;
+        self.audited(
+            &user.id,
+            AuditAction::MigrateSession,
+            id,
+            parameters,
+            || {
+                ensure_session_permission(
+                    user,
+                    id,
+                    Permission::SessionWrite,
+                    Permission::SessionWriteAny,
+                )?;
+
+                let runtime = new_runtime()?;
+                let session_id = session_id(id);
+                let session = runtime
+                    .block_on(self.engine.get_session(&session_id))?
+                    .ok_or(Error::NotFound("session"))?;
+                let result = runtime.block_on(self.engine.migrate_session(&session));
+                match &result {
+                    // The redeployed session is recorded separately once it ends
+                    Ok(()) => self.record_session_end(&session, SessionEndReason::Migrated),
+                    Err(err) => {
+                        // Deleted but not deployed again
+                        if let Ok(None) = runtime.block_on(self.engine.get_session(&session_id)) {
+                            error!(
+                                session_id = %session_id,
+                                error = %err,
+                                "Session lost while migrating"
+                            );
+                            self.record_session_end(&session, SessionEndReason::Failure);
+                        }
+                    }
+                }
+                result
+            },
+        )
     }
 
     // Pools
 
+    #[instrument(skip_all, fields(user = %user.id, pool_id = %pool_id))]
     pub fn get_pool(&self, user: &LoggedUser, pool_id: &str) -> Result<Option<Pool>> {
-        if !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
-        }
+        ensure_permission(user, Permission::PoolRead)?;
 
         new_runtime()?.block_on(self.engine.get_pool(pool_id))
     }
 
+    #[instrument(skip_all, fields(user = %user.id))]
     pub fn list_pools(&self, user: &LoggedUser) -> Result<BTreeMap<String, Pool>> {
-        if !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
-        }
+        ensure_permission(user, Permission::PoolRead)?;
 
         new_runtime()?.block_on(self.clone().engine.list_pools())
     }
+
+    /// Pools matching `query`, see `listing`
+    #[instrument(skip_all, fields(user = %user.id))]
+    pub fn list_pools_page(&self, user: &LoggedUser, query: &PoolQuery) -> Result<Page<Pool>> {
+        query.page(self.list_pools(user)?.into_iter())
+    }
+
+    /// Prevents (or allows again) new sessions on pool `id`
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
+    pub fn set_pool_cordoned(&self, user: &LoggedUser, id: &str, cordoned: bool) -> Result<()> {
+        let action = if cordoned {
+            AuditAction::CordonPool
+        } else {
+            AuditAction::UncordonPool
+        };
+        self.audited(&user.id, action, id, Value::Null, || {
+            ensure_permission(user, Permission::PoolEdit)?;
+
+            new_runtime()?.block_on(self.engine.set_pool_cordoned(id, cordoned))
+        })
+    }
+
+    /// Prevents (or allows again) new sessions on node `name`
+    #[instrument(skip_all, fields(user = %user.id, name = %name))]
+    pub fn set_node_cordoned(&self, user: &LoggedUser, name: &str, cordoned: bool) -> Result<()> {
+        let action = if cordoned {
+            AuditAction::CordonNode
+        } else {
+            AuditAction::UncordonNode
+        };
+        self.audited(&user.id, action, name, Value::Null, || {
+            ensure_permission(user, Permission::PoolEdit)?;
+
+            new_runtime()?.block_on(self.engine.set_node_unschedulable(name, cordoned))
+        })
+    }
+
+    /// Cordons pool `id` and gives its sessions a grace period to be migrated. Returns the owners of drained sessions.
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
+    pub fn drain_pool(
+        &self,
+        user: &LoggedUser,
+        id: &str,
+        conf: DrainConfiguration,
+    ) -> Result<Vec<String>> {
+        let parameters = to_parameters(&conf);
+        self.audited(&user.id, AuditAction::DrainPool, id, parameters, || {
+            ensure_permission(user, Permission::PoolEdit)?;
+
+            let grace_period = conf.grace_period.unwrap_or(Manager::DRAIN_GRACE_PERIOD);
+            let sessions = new_runtime()?.block_on(self.engine.drain_pool(id, grace_period))?;
+            Ok(drained_owners(&sessions, grace_period))
+        })
+    }
+
+    /// Cordons node `name` and gives its sessions a grace period to be migrated. Returns the owners of drained sessions.
+    #[instrument(skip_all, fields(user = %user.id, name = %name))]
+    pub fn drain_node(
+        &self,
+        user: &LoggedUser,
+        name: &str,
+        conf: DrainConfiguration,
+    ) -> Result<Vec<String>> {
+        let parameters = to_parameters(&conf);
+        self.audited(&user.id, AuditAction::DrainNode, name, parameters, || {
+            ensure_permission(user, Permission::PoolEdit)?;
+
+            let grace_period = conf.grace_period.unwrap_or(Manager::DRAIN_GRACE_PERIOD);
+            let sessions = new_runtime()?.block_on(self.engine.drain_node(name, grace_period))?;
+            Ok(drained_owners(&sessions, grace_period))
+        })
+    }
+
+    // Templates
+
+    #[instrument(skip_all, fields(user = %user.id, id = %id))]
+    pub fn import_template(&self, user: &LoggedUser, id: &str, template: Template) -> Result<()> {
+        let parameters = to_parameters(&template);
+        self.audited(
+            &user.id,
+            AuditAction::ImportTemplate,
+            id,
+            parameters,
+            || {
+                ensure_permission(user, Permission::TemplateEdit)?;
+
+                new_runtime()?.block_on(self.engine.import_template(id, &template))
+            },
+        )
+    }
+
+    #[instrument(skip_all, fields(user = %user.id))]
+    pub fn list_template_images(
+        &self,
+        user: &LoggedUser,
+    ) -> Result<BTreeMap<String, TemplateImage>> {
+        ensure_permission(user, Permission::PoolRead)?;
+
+        let images = new_runtime()?.block_on(self.engine.list_template_images())?;
+        for (id, image) in &images {
+            if image.missing {
+                warn!(
+                    image = %image.image,
+                    template = %id,
+                    "Image is not pulled on any node"
+                );
+            }
+        }
+        Ok(images)
+    }
+
+    // Audit
+
+    #[instrument(skip_all, fields(user = %user.id))]
+    pub fn list_audit_entries(
+        &self,
+        user: &LoggedUser,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEntry>> {
+        ensure_permission(user, Permission::AuditRead)?;
+
+        self.audit.query(filter)
+    }
+
+    // History
+
+    #[instrument(skip_all, fields(user = %user.id))]
+    pub fn list_session_history(
+        &self,
+        user: &LoggedUser,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<SessionRecord>> {
+        ensure_permission(user, Permission::ReportRead)?;
+
+        self.history.list(filter)
+    }
+
+    #[instrument(skip_all, fields(user = %user.id))]
+    pub fn get_usage_report(
+        &self,
+        user: &LoggedUser,
+        filter: &HistoryFilter,
+        group: UsageGroup,
+    ) -> Result<BTreeMap<String, UsageReport>> {
+        ensure_permission(user, Permission::ReportRead)?;
+
+        self.history.usage(filter, group)
+    }
 }
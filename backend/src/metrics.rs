@@ -1,6 +1,12 @@
+use crate::{
+    error,
+    types::{Pool, Session, SessionEndReason},
+};
 use prometheus::{
-    exponential_buckets, histogram_opts, opts, Error, HistogramVec, IntCounterVec, Registry,
+    exponential_buckets, histogram_opts, opts, Error, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Registry,
 };
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone)]
 pub struct Metrics {
@@ -8,11 +14,24 @@ pub struct Metrics {
     deploy_failures_counter: IntCounterVec,
     undeploy_counter: IntCounterVec,
     undeploy_failures_counter: IntCounterVec,
+    errors_counter: IntCounterVec,
     deploy_duration: HistogramVec,
+    session_lifetime: HistogramVec,
+    sessions: IntGaugeVec,
+    pool_free_sessions: IntGaugeVec,
+    pool_free_resources: IntGaugeVec,
+    queued_users: IntGauge,
 }
 
 impl Metrics {
     const TEMPLATE_LABEL: &'static str = "template";
+    const POOL_LABEL: &'static str = "pool";
+    const PHASE_LABEL: &'static str = "phase";
+    const REASON_LABEL: &'static str = "reason";
+    const RESOURCE_LABEL: &'static str = "resource";
+    const ERROR_LABEL: &'static str = "error";
+    // Pool label of sessions not yet assigned to a pool
+    const UNKNOWN_POOL: &'static str = "unknown";
 
     pub fn new() -> Result<Self, Error> {
         let opts = histogram_opts!(
@@ -20,6 +39,11 @@ impl Metrics {
             "Deployment duration in seconds",
             exponential_buckets(1.0, 2.0, 8).unwrap()
         );
+        let lifetime_opts = histogram_opts!(
+            "session_lifetime",
+            "Session lifetime in seconds",
+            exponential_buckets(60.0, 2.0, 10).unwrap()
+        );
         Ok(Metrics {
             deploy_counter: IntCounterVec::new(
                 opts!("deploy_counter", "Count of deployments"),
@@ -40,7 +64,34 @@ impl Metrics {
                 ),
                 &[],
             )?,
-            deploy_duration: HistogramVec::new(opts, &[])?,
+            errors_counter: IntCounterVec::new(
+                opts!("errors_counter", "Count of errors returned by the API"),
+                &[Self::ERROR_LABEL],
+            )?,
+            deploy_duration: HistogramVec::new(opts, &[Self::TEMPLATE_LABEL, Self::POOL_LABEL])?,
+            session_lifetime: HistogramVec::new(lifetime_opts, &[Self::REASON_LABEL])?,
+            sessions: IntGaugeVec::new(
+                opts!("sessions", "Current sessions"),
+                &[Self::TEMPLATE_LABEL, Self::POOL_LABEL, Self::PHASE_LABEL],
+            )?,
+            pool_free_sessions: IntGaugeVec::new(
+                opts!(
+                    "pool_free_sessions",
+                    "Count of sessions that can still be created on ready and uncordoned nodes"
+                ),
+                &[Self::POOL_LABEL],
+            )?,
+            pool_free_resources: IntGaugeVec::new(
+                opts!(
+                    "pool_free_resources",
+                    "Allocatable resources not requested by sessions (CPU in millicores, memory and storage in bytes)"
+                ),
+                &[Self::POOL_LABEL, Self::RESOURCE_LABEL],
+            )?,
+            queued_users: IntGauge::with_opts(opts!(
+                "queued_users",
+                "Count of users waiting for their session to be deployed"
+            ))?,
         })
     }
 
@@ -50,7 +101,13 @@ impl Metrics {
         registry.register(Box::new(self.deploy_failures_counter))?;
         registry.register(Box::new(self.undeploy_counter))?;
         registry.register(Box::new(self.undeploy_failures_counter))?;
+        registry.register(Box::new(self.errors_counter))?;
         registry.register(Box::new(self.deploy_duration))?;
+        registry.register(Box::new(self.session_lifetime))?;
+        registry.register(Box::new(self.sessions))?;
+        registry.register(Box::new(self.pool_free_sessions))?;
+        registry.register(Box::new(self.pool_free_resources))?;
+        registry.register(Box::new(self.queued_users))?;
         Ok(())
     }
 }
@@ -75,9 +132,75 @@ impl Metrics {
         self.undeploy_failures_counter.with_label_values(&[]).inc();
     }
 
-    pub fn observe_deploy_duration(&self, duration: f64) {
+    pub fn inc_errors_counter(&self, error: &error::Error) {
+        self.errors_counter.with_label_values(&[error.kind()]).inc();
+    }
+
+    pub fn observe_deploy_duration(&self, template: &str, pool: Option<&str>, duration: f64) {
         self.deploy_duration
-            .with_label_values(&[])
+            .with_label_values(&[template, pool.unwrap_or(Self::UNKNOWN_POOL)])
             .observe(duration);
     }
+
+    pub fn observe_session_lifetime(&self, reason: SessionEndReason, lifetime: f64) {
+        self.session_lifetime
+            .with_label_values(&[&format!("{:?}", reason)])
+            .observe(lifetime);
+    }
+
+    /// Replaces current sessions gauges with `sessions`
+    pub fn set_sessions<'a>(&self, sessions: impl Iterator<Item = &'a Session>) {
+        let mut counts: BTreeMap<(String, String, String), i64> = BTreeMap::new();
+        for session in sessions {
+            let pool = session
+                .pool
+                .clone()
+                .unwrap_or_else(|| Self::UNKNOWN_POOL.to_string());
+            *counts
+                .entry((
+                    session.template_id.clone(),
+                    pool,
+                    format!("{:?}", session.pod.phase),
+                ))
+                .or_default() += 1;
+        }
+        // Reset first so that labels of gone sessions disappear
+        self.sessions.reset();
+        for ((template, pool, phase), count) in counts {
+            self.sessions
+                .with_label_values(&[&template, &pool, &phase])
+                .set(count);
+        }
+    }
+
+    /// Replaces pools capacity gauges with `pools`, whose nodes can host `max_sessions_per_node` sessions each
+    pub fn set_pools(&self, pools: &BTreeMap<String, Pool>, max_sessions_per_node: usize) {
+        self.pool_free_sessions.reset();
+        self.pool_free_resources.reset();
+        for (id, pool) in pools {
+            let free_sessions: usize = pool
+                .nodes
+                .iter()
+                .filter(|node| node.ready && !node.cordoned && !pool.cordoned)
+                .map(|node| max_sessions_per_node.saturating_sub(node.sessions))
+                .sum();
+            self.pool_free_sessions
+                .with_label_values(&[id])
+                .set(free_sessions as i64);
+            let free = |allocatable: u64, used: u64| allocatable.saturating_sub(used) as i64;
+            for (resource, value) in [
+                ("cpu", free(pool.allocatable.cpu, pool.used.cpu)),
+                ("memory", free(pool.allocatable.memory, pool.used.memory)),
+                ("storage", free(pool.allocatable.storage, pool.used.storage)),
+            ] {
+                self.pool_free_resources
+                    .with_label_values(&[id, resource])
+                    .set(value);
+            }
+        }
+    }
+
+    pub fn set_queued_users(&self, count: usize) {
+        self.queued_users.set(count as i64);
+    }
 }
@@ -0,0 +1,637 @@
+//! OpenAPI 3 document describing the routes mounted in /api/v1
+//!
+//! Schemas are generated from the serde types of each route. Routes themselves are described in `operations`,
+//! `document` fails as soon as they diverge from the mounted ones. The generated document is committed as `openapi.json`.
+
+use crate::{
+    audit::{AuditEntry, AuditFilter},
+    error::{Error, Result},
+    history::HistoryFilter,
+    listing::{Page, PoolQuery, SessionQuery, UserQuery},
+    manager::Playground,
+    types::{
+        ApiToken, ApiTokenConfiguration, CreatedApiToken, DrainConfiguration, Pool, Session,
+        SessionConfiguration, SessionRecord, SessionUpdateConfiguration, Template, TemplateImage,
+        UsageReport, User, UserConfiguration, UserUpdateConfiguration,
+    },
+};
+use rocket::{http::Method, Route};
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{Schema, SchemaObject},
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+type QueryFn = fn(&mut SchemaGenerator) -> SchemaObject;
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    gen.subschema_for::<T>()
+}
+
+// Inlined, so that each field can be exposed as a query parameter
+fn query_schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> SchemaObject {
+    gen.root_schema_for::<T>().schema
+}
+
+enum Response {
+    /// Wrapped as `{"result": ...}`. `None` when `result` is always `null`.
+    Result(Option<SchemaFn>),
+    /// Returned as is, with the provided content type
+    Content(&'static str, SchemaFn),
+    Redirect,
+    Empty,
+}
+
+/// Describes a route. Path and query parameters are extracted from the route URI.
+struct Operation {
+    id: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    /// Fields of the `<name..>` query segment
+    query: Option<QueryFn>,
+    body: Option<SchemaFn>,
+    response: Response,
+}
+
+impl Operation {
+    fn new(id: &'static str, tag: &'static str, summary: &'static str) -> Self {
+        Operation {
+            id,
+            tag,
+            summary,
+            query: None,
+            body: None,
+            response: Response::Result(None),
+        }
+    }
+
+    fn query<T: JsonSchema>(self) -> Self {
+        Operation {
+            query: Some(query_schema::<T>),
+            ..self
+        }
+    }
+
+    fn body<T: JsonSchema>(self) -> Self {
+        Operation {
+            body: Some(schema::<T>),
+            ..self
+        }
+    }
+
+    fn result<T: JsonSchema>(self) -> Self {
+        Operation {
+            response: Response::Result(Some(schema::<T>)),
+            ..self
+        }
+    }
+
+    fn content<T: JsonSchema>(self, content_type: &'static str) -> Self {
+        Operation {
+            response: Response::Content(content_type, schema::<T>),
+            ..self
+        }
+    }
+
+    fn redirect(self) -> Self {
+        Operation {
+            response: Response::Redirect,
+            ..self
+        }
+    }
+
+    fn empty(self) -> Self {
+        Operation {
+            response: Response::Empty,
+            ..self
+        }
+    }
+}
+
+/// All routes mounted in /api/v1, by method and path
+fn operations() -> Vec<(Method, &'static str, Operation)> {
+    vec![
+        (
+            Method::Get,
+            "/",
+            Operation::new(
+                "get",
+                "playground",
+                "Playground details, including the current user if logged",
+            )
+            .result::<Playground>(),
+        ),
+        // Users
+        (
+            Method::Get,
+            "/users/<id>",
+            Operation::new("getUser", "users", "Get a user").result::<Option<User>>(),
+        ),
+        (
+            Method::Get,
+            "/users",
+            Operation::new("listUsers", "users", "List matching users")
+                .query::<UserQuery>()
+                .result::<Page<User>>(),
+        ),
+        (
+            Method::Put,
+            "/users/<id>",
+            Operation::new("createUser", "users", "Create a user").body::<UserConfiguration>(),
+        ),
+        (
+            Method::Patch,
+            "/users/<id>",
+            Operation::new("updateUser", "users", "Update a user")
+                .body::<UserUpdateConfiguration>(),
+        ),
+        (
+            Method::Delete,
+            "/users/<id>",
+            Operation::new("deleteUser", "users", "Delete a user"),
+        ),
+        // API tokens
+        (
+            Method::Get,
+            "/tokens",
+            Operation::new(
+                "listCurrentUserApiTokens",
+                "tokens",
+                "List the current user API tokens",
+            )
+            .result::<Vec<ApiToken>>(),
+        ),
+        (
+            Method::Put,
+            "/tokens/<name>",
+            Operation::new(
+                "createCurrentUserApiToken",
+                "tokens",
+                "Create an API token for the current user. The token is only returned once.",
+            )
+            .body::<ApiTokenConfiguration>()
+            .result::<CreatedApiToken>(),
+        ),
+        (
+            Method::Delete,
+            "/tokens/<name>",
+            Operation::new(
+                "deleteCurrentUserApiToken",
+                "tokens",
+                "Revoke an API token of the current user",
+            ),
+        ),
+        (
+            Method::Get,
+            "/users/<id>/tokens",
+            Operation::new("listApiTokens", "tokens", "List the API tokens of a user")
+                .result::<Vec<ApiToken>>(),
+        ),
+        (
+            Method::Delete,
+            "/users/<id>/tokens/<name>",
+            Operation::new("deleteApiToken", "tokens", "Revoke an API token of a user"),
+        ),
+        // Current Session
+        (
+            Method::Get,
+            "/session",
+            Operation::new(
+                "getCurrentSession",
+                "session",
+                "Get the current user session",
+            )
+            .result::<Option<Session>>(),
+        ),
+        (
+            Method::Put,
+            "/session",
+            Operation::new(
+                "createCurrentSession",
+                "session",
+                "Create a session for the current user",
+            )
+            .body::<SessionConfiguration>(),
+        ),
+        (
+            Method::Patch,
+            "/session",
+            Operation::new(
+                "updateCurrentSession",
+                "session",
+                "Update the current user session",
+            )
+            .body::<SessionUpdateConfiguration>(),
+        ),
+        (
+            Method::Delete,
+            "/session",
+            Operation::new(
+                "deleteCurrentSession",
+                "session",
+                "Delete the current user session",
+            ),
+        ),
+        (
+            Method::Put,
+            "/session/migrate",
+            Operation::new(
+                "migrateCurrentSession",
+                "session",
+                "Recreate the current user session on a node that is not drained",
+            ),
+        ),
+        // Sessions
+        (
+            Method::Get,
+            "/sessions/<id>",
+            Operation::new("getSession", "sessions", "Get a session").result::<Option<Session>>(),
+        ),
+        (
+            Method::Get,
+            "/sessions",
+            Operation::new("listSessions", "sessions", "List matching sessions")
+                .query::<SessionQuery>()
+                .result::<Page<Session>>(),
+        ),
+        (
+            Method::Put,
+            "/sessions/<id>",
+            Operation::new("createSession", "sessions", "Create a session")
+                .body::<SessionConfiguration>(),
+        ),
+        (
+            Method::Patch,
+            "/sessions/<id>",
+            Operation::new("updateSession", "sessions", "Update a session")
+                .body::<SessionUpdateConfiguration>(),
+        ),
+        (
+            Method::Delete,
+            "/sessions/<id>",
+            Operation::new("deleteSession", "sessions", "Delete a session"),
+        ),
+        (
+            Method::Put,
+            "/sessions/<id>/migrate",
+            Operation::new(
+                "migrateSession",
+                "sessions",
+                "Recreate a session on a node that is not drained",
+            ),
+        ),
+        // Pools
+        (
+            Method::Get,
+            "/pools/<id>",
+            Operation::new("getPool", "pools", "Get a pool").result::<Option<Pool>>(),
+        ),
+        (
+            Method::Get,
+            "/pools",
+            Operation::new("listPools", "pools", "List matching pools")
+                .query::<PoolQuery>()
+                .result::<Page<Pool>>(),
+        ),
+        (
+            Method::Put,
+            "/pools/<id>/cordon",
+            Operation::new("cordonPool", "pools", "Prevent new sessions on a pool"),
+        ),
+        (
+            Method::Delete,
+            "/pools/<id>/cordon",
+            Operation::new("uncordonPool", "pools", "Allow new sessions on a pool"),
+        ),
+        (
+            Method::Put,
+            "/pools/<id>/drain",
+            Operation::new(
+                "drainPool",
+                "pools",
+                "Cordon a pool and delete its sessions once the grace period is over. Returns the notified users.",
+            )
+            .body::<DrainConfiguration>()
+            .result::<Vec<String>>(),
+        ),
+        // Nodes
+        (
+            Method::Put,
+            "/nodes/<name>/cordon",
+            Operation::new("cordonNode", "nodes", "Prevent new sessions on a node"),
+        ),
+        (
+            Method::Delete,
+            "/nodes/<name>/cordon",
+            Operation::new("uncordonNode", "nodes", "Allow new sessions on a node"),
+        ),
+        (
+            Method::Put,
+            "/nodes/<name>/drain",
+            Operation::new(
+                "drainNode",
+                "nodes",
+                "Cordon a node and delete its sessions once the grace period is over. Returns the notified users.",
+            )
+            .body::<DrainConfiguration>()
+            .result::<Vec<String>>(),
+        ),
+        // Templates
+        (
+            Method::Get,
+            "/templates/images",
+            Operation::new(
+                "listTemplateImages",
+                "templates",
+                "Availability of template images on nodes",
+            )
+            .result::<BTreeMap<String, TemplateImage>>(),
+        ),
+        (
+            Method::Put,
+            "/templates/<id>",
+            Operation::new(
+                "importTemplate",
+                "templates",
+                "Create or replace a template",
+            )
+            .body::<Template>(),
+        ),
+        // Audit
+        (
+            Method::Get,
+            "/audit",
+            Operation::new("listAuditEntries", "audit", "List matching audit entries")
+                .query::<AuditFilter>()
+                .result::<Vec<AuditEntry>>(),
+        ),
+        (
+            Method::Get,
+            "/audit/export",
+            Operation::new(
+                "exportAuditEntries",
+                "audit",
+                "Export matching audit entries as JSON lines",
+            )
+            .query::<AuditFilter>()
+            .content::<AuditEntry>("application/x-ndjson"),
+        ),
+        (
+            Method::Get,
+            "/history",
+            Operation::new("listSessionHistory", "audit", "List matching finished sessions")
+                .query::<HistoryFilter>()
+                .result::<Vec<SessionRecord>>(),
+        ),
+        (
+            Method::Get,
+            "/reports/usage",
+            Operation::new(
+                "getUsageReport",
+                "audit",
+                "Usage of matching finished sessions grouped by `group` (one of `user`, `template` or `pool`)",
+            )
+            .query::<HistoryFilter>()
+            .result::<BTreeMap<String, UsageReport>>(),
+        ),
+        // Login
+        (
+            Method::Get,
+            "/login/<provider>",
+            Operation::new(
+                "oauthLogin",
+                "login",
+                "Redirect to the identity provider",
+            )
+            .redirect(),
+        ),
+        (
+            Method::Get,
+            "/auth/<provider>",
+            Operation::new(
+                "oauthCallback",
+                "login",
+                "Callback called by the identity provider",
+            )
+            .redirect(),
+        ),
+        (
+            Method::Get,
+            "/login",
+            Operation::new("login", "login", "Log in with an identity provider access token")
+                .empty(),
+        ),
+        (
+            Method::Get,
+            "/logout",
+            Operation::new("logout", "login", "Log out the current user").empty(),
+        ),
+        (
+            Method::Get,
+            "/openapi.json",
+            Operation::new("openapi", "playground", "This document")
+                .content::<Value>("application/json"),
+        ),
+    ]
+}
+
+// Parameters are `<name>` segments, `<name..>` being a set of fields
+fn parameters(segments: &str, separator: char) -> Vec<(&str, bool)> {
+    segments
+        .split(separator)
+        .filter_map(|segment| segment.strip_prefix('<')?.strip_suffix('>'))
+        .map(|name| match name.strip_suffix("..") {
+            Some(name) => (name, true),
+            None => (name, false),
+        })
+        .collect()
+}
+
+fn to_value<T: serde::Serialize>(value: T) -> Result<Value> {
+    serde_json::to_value(value).map_err(|err| Error::Failure(err.into()))
+}
+
+fn operation_object(
+    gen: &mut SchemaGenerator,
+    route: &Route,
+    operation: &Operation,
+) -> Result<Value> {
+    let mut params = Vec::new();
+    for (name, _) in parameters(route.uri.path(), '/') {
+        params.push(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": {"type": "string"},
+        }));
+    }
+    for (name, multiple) in parameters(route.uri.query().unwrap_or_default(), '&') {
+        if !multiple {
+            params.push(json!({
+                "name": name,
+                "in": "query",
+                "required": true,
+                "schema": {"type": "string"},
+            }));
+            continue;
+        }
+        let query = operation.query.ok_or_else(|| {
+            Error::Failure(format!("Undocumented query parameters {}", route.uri).into())
+        })?;
+        if let Some(object) = query(gen).object {
+            let object = *object;
+            for (field, schema) in object.properties {
+                params.push(json!({
+                    "name": field,
+                    "in": "query",
+                    "required": object.required.contains(&field),
+                    "schema": to_value(schema)?,
+                }));
+            }
+        }
+    }
+
+    let mut object = json!({
+        "operationId": operation.id,
+        "tags": [operation.tag],
+        "summary": operation.summary,
+        "parameters": params,
+    });
+    if let Some(body) = operation.body {
+        object["requestBody"] = json!({
+            "required": true,
+            "content": {"application/json": {"schema": to_value(body(gen))?}},
+        });
+    }
+    let success = match &operation.response {
+        Response::Result(result) => {
+            let result = match result {
+                Some(result) => to_value(result(gen))?,
+                None => json!({"nullable": true}),
+            };
+            json!({"200": {
+                "description": "Success",
+                "content": {"application/json": {"schema": {
+                    "type": "object",
+                    "properties": {"result": result},
+                }}},
+            }})
+        }
+        Response::Content(content_type, schema) => json!({"200": {
+            "description": "Success",
+            "content": {*content_type: {"schema": to_value(schema(gen))?}},
+        }}),
+        Response::Redirect => json!({"303": {"description": "Redirect"}}),
+        Response::Empty => json!({"200": {"description": "Success"}}),
+    };
+    object["responses"] = success;
+    object["responses"]["default"] = json!({"$ref": "#/components/responses/Error"});
+    Ok(object)
+}
+
+// Follows the body built by `api::error_body`
+fn error_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["error"],
+        "properties": {"error": {
+            "type": "object",
+            "required": ["code", "message"],
+            "properties": {
+                "code": {"type": "string", "enum": [
+                    "unauthenticated",
+                    "permission_denied",
+                    "quota_exceeded",
+                    "not_found",
+                    "already_exists",
+                    "invalid_value",
+                    "unavailable",
+                    "internal",
+                ]},
+                "message": {"type": "string"},
+                "details": {
+                    "type": "object",
+                    "nullable": true,
+                    "additionalProperties": {"type": "string"},
+                },
+                "requestId": {"type": "string", "nullable": true},
+            },
+        }},
+    })
+}
+
+/// Builds the OpenAPI document of `routes`, as mounted in /api/v1.
+/// Fails if a route isn't described in `operations` or if an operation doesn't match a route.
+pub fn document(routes: &[Route]) -> Result<Value> {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let operations = operations();
+    let mut documented = BTreeSet::new();
+    let mut paths = Map::new();
+    for route in routes {
+        let path = route.uri.path();
+        // Fallbacks of logged routes (e.g. `get_unlogged`) share their method and path
+        if !documented.insert((route.method.as_str(), path)) {
+            continue;
+        }
+        let (_, _, operation) = operations
+            .iter()
+            .find(|(method, p, _)| *method == route.method && *p == path)
+            .ok_or_else(|| {
+                Error::Failure(format!("Undocumented route {} {}", route.method, route.uri).into())
+            })?;
+        let openapi_path = path.replace('<', "{").replace('>', "}");
+        let item = paths.entry(openapi_path).or_insert_with(|| json!({}));
+        let object = operation_object(&mut gen, route, operation)?;
+        item[route.method.as_str().to_lowercase()] = object;
+    }
+    if let Some((method, path, _)) = operations
+        .iter()
+        .find(|(method, path, _)| !documented.contains(&(method.as_str(), *path)))
+    {
+        return Err(Error::Failure(
+            format!("Documented route {} {} is not mounted", method, path).into(),
+        ));
+    }
+
+    Ok(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Playground API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{"url": "/api/v1"}],
+        "paths": paths,
+        "components": {
+            "schemas": to_value(gen.definitions())?,
+            "responses": {"Error": {
+                "description": "Error, see `code` for its kind",
+                "content": {"application/json": {"schema": error_schema()}},
+            }},
+            "securitySchemes": {
+                "bearer": {"type": "http", "scheme": "bearer"},
+                "cookie": {"type": "apiKey", "in": "cookie", "name": "login"},
+            },
+        },
+        "security": [{"bearer": []}, {"cookie": []}],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1_routes;
+
+    #[test]
+    fn document_matches_committed_one() -> Result<()> {
+        let committed: Value = serde_json::from_str(include_str!("../openapi.json"))
+            .map_err(|err| Error::Failure(err.into()))?;
+        assert!(
+            document(&v1_routes())? == committed,
+            "openapi.json is outdated, regenerate it with `cargo run -- openapi > openapi.json`"
+        );
+        Ok(())
+    }
+}
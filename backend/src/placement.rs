@@ -0,0 +1,307 @@
+//! Placement of sessions on the nodes of a pool
+//!
+//! The node picked by the configured `PlacementStrategy` is pinned on the session pod with a required node affinity,
+//! so that the scheduler still checks its resources and taints. When no node can host a session (e.g. while a pool
+//! is being scaled up), placement is left to the kubernetes scheduler.
+
+use crate::{
+    error::{Error, Result},
+    types::{Node, Resources},
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{cmp::Ordering, str::FromStr};
+use tracing::{debug, info};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlacementStrategy {
+    /// Fill the most used nodes first, so that idle nodes can be removed
+    BinPack,
+    /// Favor the least used nodes
+    Spread,
+    /// Favor nodes that already pulled the template image, then the least used ones
+    PreferImage,
+}
+
+impl Default for PlacementStrategy {
+    fn default() -> Self {
+        PlacementStrategy::PreferImage
+    }
+}
+
+impl FromStr for PlacementStrategy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bin-pack" => Ok(PlacementStrategy::BinPack),
+            "spread" => Ok(PlacementStrategy::Spread),
+            "prefer-image" => Ok(PlacementStrategy::PreferImage),
+            _ => Err(Error::InvalidValue {
+                field: "placement",
+                reason: "must be one of bin-pack, spread or prefer-image".to_string(),
+            }),
+        }
+    }
+}
+
+/// A node that might host a session
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    /// Name of the kubernetes node
+    pub name: String,
+    pub node: Node,
+    /// If the template image has already been pulled
+    pub has_image: bool,
+}
+
+impl Candidate {
+    // If `requests` fit in the remaining resources, and a session can still be added. Unreported resources are ignored.
+    fn fits(&self, requests: &Resources, max_sessions: usize) -> bool {
+        let node = &self.node;
+        let fits = |used: u64, requested: u64, allocatable: u64| {
+            allocatable == 0 || used + requested <= allocatable
+        };
+        node.ready
+            && !node.cordoned
+            && node.sessions < max_sessions
+            && fits(node.used.cpu, requests.cpu, node.allocatable.cpu)
+            && fits(node.used.memory, requests.memory, node.allocatable.memory)
+            && fits(
+                node.used.storage,
+                requests.storage,
+                node.allocatable.storage,
+            )
+    }
+
+    // Average ratio of used resources, between 0 and 1
+    fn load(&self) -> f64 {
+        let node = &self.node;
+        let ratio = |used: u64, allocatable: u64| {
+            if allocatable == 0 {
+                0.0
+            } else {
+                used as f64 / allocatable as f64
+            }
+        };
+        (ratio(node.used.cpu, node.allocatable.cpu)
+            + ratio(node.used.memory, node.allocatable.memory)
+            + ratio(node.used.storage, node.allocatable.storage))
+            / 3.0
+    }
+}
+
+fn compare_load(a: &Candidate, b: &Candidate) -> Ordering {
+    a.load()
+        .partial_cmp(&b.load())
+        .unwrap_or(Ordering::Equal)
+        .then(a.node.sessions.cmp(&b.node.sessions))
+}
+
+/// Returns the name of the node that should host session `session_id`, requesting `requests`, if any `candidates` can
+pub fn place(
+    strategy: PlacementStrategy,
+    session_id: &str,
+    candidates: &[Candidate],
+    requests: &Resources,
+    max_sessions: usize,
+) -> Option<String> {
+    let fitting: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|candidate| candidate.fits(requests, max_sessions))
+        .collect();
+    for candidate in candidates {
+        debug!(
+            session_id = %session_id,
+            node = %candidate.name,
+            load = candidate.load(),
+            sessions = candidate.node.sessions,
+            has_image = candidate.has_image,
+            fits = fitting.iter().any(|fit| fit.name == candidate.name),
+            "Placement candidate"
+        );
+    }
+
+    let chosen = match strategy {
+        PlacementStrategy::BinPack => fitting.into_iter().max_by(|a, b| compare_load(a, b)),
+        PlacementStrategy::Spread => fitting.into_iter().min_by(|a, b| compare_load(a, b)),
+        PlacementStrategy::PreferImage => fitting
+            .into_iter()
+            .min_by(|a, b| b.has_image.cmp(&a.has_image).then(compare_load(a, b))),
+    };
+    match chosen {
+        Some(candidate) => {
+            info!(
+                session_id = %session_id,
+                node = %candidate.name,
+                strategy = ?strategy,
+                load = candidate.load(),
+                sessions = candidate.node.sessions,
+                has_image = candidate.has_image,
+                "Placing session"
+            );
+            Some(candidate.name.clone())
+        }
+        None => {
+            info!(
+                session_id = %session_id,
+                candidates = candidates.len(),
+                "No node can host session, leaving placement to the scheduler"
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_SESSIONS: usize = 3;
+
+    fn resources(cpu: u64, memory: u64) -> Resources {
+        Resources {
+            cpu,
+            memory,
+            storage: 0,
+        }
+    }
+
+    // A ready node with 1000 millicores and 1000 bytes, `used` of each
+    fn candidate(name: &str, used: u64, sessions: usize, has_image: bool) -> Candidate {
+        Candidate {
+            name: name.to_string(),
+            node: Node {
+                hostname: name.to_string(),
+                allocatable: resources(1000, 1000),
+                used: resources(used, used),
+                sessions,
+                cordoned: false,
+                ready: true,
+            },
+            has_image,
+        }
+    }
+
+    fn candidates() -> Vec<Candidate> {
+        vec![
+            candidate("busy", 600, 2, false),
+            candidate("idle", 0, 0, false),
+            candidate("cached", 300, 1, true),
+        ]
+    }
+
+    fn place_with(strategy: PlacementStrategy, candidates: &[Candidate]) -> Option<String> {
+        place(
+            strategy,
+            "alice",
+            candidates,
+            &resources(100, 100),
+            MAX_SESSIONS,
+        )
+    }
+
+    #[test]
+    fn strategies_are_parsed() {
+        assert_eq!(
+            "spread".parse::<PlacementStrategy>().ok(),
+            Some(PlacementStrategy::Spread)
+        );
+        assert!(matches!(
+            "random".parse::<PlacementStrategy>(),
+            Err(Error::InvalidValue {
+                field: "placement",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn fits_remaining_resources() {
+        let requests = resources(400, 100);
+        assert!(candidate("node", 600, 0, false).fits(&requests, MAX_SESSIONS));
+        assert!(!candidate("node", 601, 0, false).fits(&requests, MAX_SESSIONS));
+        // Memory is checked as well
+        assert!(!candidate("node", 0, 0, false).fits(&resources(0, 1001), MAX_SESSIONS));
+        // Unreported resources are ignored
+        let mut unreported = candidate("node", 0, 0, false);
+        unreported.node.allocatable = Resources::default();
+        assert!(unreported.fits(&requests, MAX_SESSIONS));
+    }
+
+    #[test]
+    fn fits_only_available_nodes() {
+        let requests = resources(0, 0);
+        assert!(!candidate("node", 0, MAX_SESSIONS, false).fits(&requests, MAX_SESSIONS));
+        let mut cordoned = candidate("node", 0, 0, false);
+        cordoned.node.cordoned = true;
+        assert!(!cordoned.fits(&requests, MAX_SESSIONS));
+        let mut not_ready = candidate("node", 0, 0, false);
+        not_ready.node.ready = false;
+        assert!(!not_ready.fits(&requests, MAX_SESSIONS));
+    }
+
+    #[test]
+    fn bin_pack_fills_most_used_nodes() {
+        assert_eq!(
+            place_with(PlacementStrategy::BinPack, &candidates()),
+            Some("busy".to_string())
+        );
+    }
+
+    #[test]
+    fn spread_favors_least_used_nodes() {
+        assert_eq!(
+            place_with(PlacementStrategy::Spread, &candidates()),
+            Some("idle".to_string())
+        );
+    }
+
+    #[test]
+    fn prefer_image_favors_cached_nodes() {
+        assert_eq!(
+            place_with(PlacementStrategy::PreferImage, &candidates()),
+            Some("cached".to_string())
+        );
+        // Then the least used ones
+        let mut candidates = candidates();
+        candidates.push(candidate("cached-idle", 0, 0, true));
+        assert_eq!(
+            place_with(PlacementStrategy::PreferImage, &candidates),
+            Some("cached-idle".to_string())
+        );
+    }
+
+    #[test]
+    fn nodes_that_do_not_fit_are_skipped() {
+        let candidates = vec![
+            candidate("full", 950, 1, true),
+            candidate("crowded", 0, MAX_SESSIONS, true),
+            candidate("busy", 600, 2, false),
+        ];
+        for strategy in &[
+            PlacementStrategy::BinPack,
+            PlacementStrategy::Spread,
+            PlacementStrategy::PreferImage,
+        ] {
+            assert_eq!(place_with(*strategy, &candidates), Some("busy".to_string()));
+        }
+        assert_eq!(
+            place_with(PlacementStrategy::Spread, &candidates[..2]),
+            None
+        );
+    }
+
+    #[test]
+    fn load_ties_are_broken_by_sessions() {
+        let candidates = vec![candidate("two", 0, 2, false), candidate("one", 0, 1, false)];
+        assert_eq!(
+            place_with(PlacementStrategy::Spread, &candidates),
+            Some("one".to_string())
+        );
+        assert_eq!(
+            place_with(PlacementStrategy::BinPack, &candidates),
+            Some("two".to_string())
+        );
+    }
+}
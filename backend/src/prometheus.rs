@@ -1,22 +1,85 @@
 //! Adapted from https://github.com/sd2k/rocket_prometheus/blob/master/src/lib.rs
 
-use prometheus::{Encoder, Registry, TextEncoder};
+use prometheus::{
+    histogram_opts, opts, Encoder, Error, HistogramVec, IntCounterVec, Registry, TextEncoder,
+};
 use rocket::{
+    fairing::{Fairing, Info, Kind},
     handler::Outcome,
     http::{ContentType, Method},
     response::Content,
-    Data, Handler, Request, Route,
+    Data, Handler, Request, Response, Route,
 };
+use std::time::Instant;
 
+/// Exposes the registry, and records requests served by routes as a `Fairing`
 #[derive(Clone)]
 pub struct PrometheusMetrics {
     pub registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_requests_duration_seconds: HistogramVec,
 }
 
+// Start time of a request, cached in its local state
+#[derive(Copy, Clone)]
+struct RequestTimer(Option<Instant>);
+
 impl PrometheusMetrics {
+    const ENDPOINT_LABEL: &'static str = "endpoint";
+    const METHOD_LABEL: &'static str = "method";
+    const STATUS_LABEL: &'static str = "status";
+
     /// Create a new `PrometheusMetrics` with a custom `Registry`.
-    pub fn with_registry(registry: Registry) -> Self {
-        PrometheusMetrics { registry }
+    pub fn with_registry(registry: Registry) -> Result<Self, Error> {
+        let labels = &[Self::ENDPOINT_LABEL, Self::METHOD_LABEL, Self::STATUS_LABEL];
+        let http_requests_total = IntCounterVec::new(
+            opts!("http_requests_total", "Total number of HTTP requests"),
+            labels,
+        )?;
+        let http_requests_duration_seconds = HistogramVec::new(
+            histogram_opts!(
+                "http_requests_duration_seconds",
+                "HTTP request duration in seconds"
+            ),
+            labels,
+        )?;
+        registry.register(Box::new(http_requests_total.clone()))?;
+        registry.register(Box::new(http_requests_duration_seconds.clone()))?;
+        Ok(PrometheusMetrics {
+            registry,
+            http_requests_total,
+            http_requests_duration_seconds,
+        })
+    }
+}
+
+impl Fairing for PrometheusMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Prometheus metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestTimer(Some(Instant::now())));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        // Requests not matching any route are ignored, to keep the number of endpoints bounded
+        if let Some(route) = request.route() {
+            // The route template (e.g. `/api/sessions/<id>`), not the actual path
+            let endpoint = route.uri.path();
+            let method = request.method().as_str();
+            let status = response.status().code.to_string();
+            let labels = &[endpoint, method, status.as_str()];
+            self.http_requests_total.with_label_values(labels).inc();
+            if let RequestTimer(Some(start)) = request.local_cache(|| RequestTimer(None)) {
+                self.http_requests_duration_seconds
+                    .with_label_values(labels)
+                    .observe(start.elapsed().as_secs_f64());
+            }
+        }
     }
 }
 
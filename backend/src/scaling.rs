@@ -0,0 +1,412 @@
+//! Dynamic scaling of node pools
+//!
+//! Pools configured with `scaling` bounds in the `playground-pools` ConfigMap are grown when their sessions
+//! don't fit on existing nodes, and shrunk when nodes are idle. Nodes are added and removed by a `NodeProvisioner`,
+//! selected via `NODE_PROVISIONER` (`gke` or `fake`). Pools are not scaled when unset.
+
+use crate::{
+    error::{Error, Result},
+    types::PoolScaling,
+};
+use async_trait::async_trait;
+use hyper::{
+    body::{aggregate, Buf},
+    client::HttpConnector,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Body, Client, Method, Request,
+};
+use hyper_tls::HttpsConnector;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{from_reader, json, Value};
+use std::{
+    collections::BTreeMap,
+    env,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+const CONTAINER_API_URL: &str = "https://container.googleapis.com/v1";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Adds and removes nodes of a pool
+#[async_trait]
+pub trait NodeProvisioner: Send + Sync {
+    /// Name used to select this provisioner via `NODE_PROVISIONER`
+    fn name(&self) -> &'static str;
+
+    /// Returns the number of nodes requested for `pool`, including those not yet registered
+    async fn size(&self, pool: &str) -> Result<usize>;
+
+    /// Requests `size` nodes for `pool`
+    async fn resize(&self, pool: &str, size: usize) -> Result<()>;
+
+    /// Removes the nodes named `nodes` from `pool`
+    async fn remove(&self, pool: &str, nodes: &[String]) -> Result<()>;
+
+    /// Whether `remove` actually deletes nodes. Nodes are cordoned before removal only when it does,
+    /// otherwise they would stay unschedulable forever.
+    fn deletes_nodes(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the `NodeProvisioner` selected via `NODE_PROVISIONER`, if any
+pub fn provisioner_from_env() -> Result<Option<Arc<dyn NodeProvisioner>>> {
+    match env::var("NODE_PROVISIONER").as_deref() {
+        Ok("gke") => Ok(Some(Arc::new(GkeProvisioner::from_env()?))),
+        Ok("fake") => Ok(Some(Arc::new(FakeProvisioner::default()))),
+        Ok(_) => Err(Error::InvalidValue {
+            field: "NODE_PROVISIONER",
+            reason: "must be one of gke or fake".to_string(),
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Returns the number of nodes needed to host `sessions`, within `scaling` bounds
+pub fn target_size(sessions: usize, max_sessions_per_node: usize, scaling: &PoolScaling) -> usize {
+    let per_node = max_sessions_per_node.max(1);
+    let needed = (sessions + per_node - 1) / per_node;
+    needed.max(scaling.min_nodes).min(scaling.max_nodes)
+}
+
+/// How a pool must be resized to reach its target size
+#[derive(Debug, PartialEq)]
+pub enum ScalingPlan {
+    /// Request this number of nodes
+    Grow(usize),
+    /// Remove those idle nodes
+    Shrink(Vec<String>),
+    Keep,
+}
+
+/// Returns how to reach `target` nodes, given the size `requested` to the provisioner, the number of `registered` nodes,
+/// the number of `schedulable` ones and those of them that are `idle`.
+/// Nodes are only removed once all requested nodes are registered.
+pub fn plan(
+    target: usize,
+    requested: usize,
+    registered: usize,
+    schedulable: usize,
+    idle: &[String],
+) -> ScalingPlan {
+    // Provisioners might not know about nodes they didn't add (e.g. the fake one before any resize)
+    let requested = requested.max(registered);
+    if target > requested {
+        return ScalingPlan::Grow(target);
+    }
+    if target >= schedulable || requested > registered {
+        return ScalingPlan::Keep;
+    }
+    let removed: Vec<String> = idle.iter().take(schedulable - target).cloned().collect();
+    if removed.is_empty() {
+        ScalingPlan::Keep
+    } else {
+        ScalingPlan::Shrink(removed)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodePool {
+    #[serde(default)]
+    instance_group_urls: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InstanceGroupManager {
+    target_size: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManagedInstances {
+    #[serde(default)]
+    managed_instances: Vec<ManagedInstance>,
+}
+
+#[derive(Deserialize)]
+struct ManagedInstance {
+    /// URL of the instance, ending with its name
+    instance: String,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+}
+
+/// Scales GKE node pools, via the instance groups backing them
+///
+/// Credentials are those of the node service account (or workload identity), as provided by the metadata server.
+/// For multi-zonal pools sizes are spread evenly across zones.
+pub struct GkeProvisioner {
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// `projects/{project}/locations/{location}/clusters/{cluster}`
+    cluster: String,
+}
+
+impl GkeProvisioner {
+    /// Creates a provisioner for the cluster defined by `GKE_PROJECT`, `GKE_LOCATION` and `GKE_CLUSTER`
+    pub fn from_env() -> Result<Self> {
+        let project = env::var("GKE_PROJECT").map_err(|_| Error::MissingData("GKE_PROJECT"))?;
+        let location = env::var("GKE_LOCATION").map_err(|_| Error::MissingData("GKE_LOCATION"))?;
+        let cluster = env::var("GKE_CLUSTER").map_err(|_| Error::MissingData("GKE_CLUSTER"))?;
+        Ok(GkeProvisioner {
+            client: Client::builder().build(HttpsConnector::new()),
+            cluster: format!(
+                "projects/{}/locations/{}/clusters/{}",
+                project, location, cluster
+            ),
+        })
+    }
+
+    async fn access_token(&self) -> Result<String> {
+        let req = Request::builder()
+            .uri(METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .body(Body::default())
+            .map_err(|err| Error::Failure(err.into()))?;
+        let res = self
+            .client
+            .request(req)
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        let body = aggregate(res)
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        let token: AccessToken =
+            from_reader(body.reader()).map_err(|err| Error::Failure(err.into()))?;
+        Ok(token.access_token)
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> Result<T> {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .header(
+                AUTHORIZATION,
+                format!("Bearer {}", self.access_token().await?),
+            )
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .map_err(|err| Error::Failure(err.into()))?;
+        let res = self
+            .client
+            .request(req)
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        let status = res.status();
+        let body = aggregate(res)
+            .await
+            .map_err(|err| Error::Failure(err.into()))?;
+        if !status.is_success() {
+            let error: Value = from_reader(body.reader()).unwrap_or_default();
+            return Err(Error::Failure(
+                format!("{} returned {}: {}", uri, status, error).into(),
+            ));
+        }
+        from_reader(body.reader()).map_err(|err| Error::Failure(err.into()))
+    }
+
+    fn node_pool_uri(&self, pool: &str) -> String {
+        format!("{}/{}/nodePools/{}", CONTAINER_API_URL, self.cluster, pool)
+    }
+
+    // URLs of the instance group managers backing `pool`, one per zone
+    async fn instance_groups(&self, pool: &str) -> Result<Vec<String>> {
+        let node_pool: NodePool = self
+            .call(Method::GET, &self.node_pool_uri(pool), None)
+            .await?;
+        Ok(node_pool.instance_group_urls)
+    }
+}
+
+#[async_trait]
+impl NodeProvisioner for GkeProvisioner {
+    fn name(&self) -> &'static str {
+        "gke"
+    }
+
+    async fn size(&self, pool: &str) -> Result<usize> {
+        let mut size = 0;
+        let groups = self.instance_groups(pool).await?;
+        for group in groups {
+            let manager: InstanceGroupManager = self.call(Method::GET, &group, None).await?;
+            size += manager.target_size;
+        }
+        Ok(size)
+    }
+
+    async fn resize(&self, pool: &str, size: usize) -> Result<()> {
+        let zones = self.instance_groups(pool).await?.len().max(1);
+        let node_count = (size + zones - 1) / zones;
+        let _: Value = self
+            .call(
+                Method::POST,
+                &format!("{}:setSize", self.node_pool_uri(pool)),
+                Some(json!({ "nodeCount": node_count })),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, pool: &str, nodes: &[String]) -> Result<()> {
+        // GKE nodes are named after their instance
+        let groups = self.instance_groups(pool).await?;
+        for group in groups {
+            let instances: ManagedInstances = self
+                .call(
+                    Method::POST,
+                    &format!("{}/listManagedInstances", group),
+                    None,
+                )
+                .await?;
+            let matching: Vec<String> = instances
+                .managed_instances
+                .into_iter()
+                .map(|instance| instance.instance)
+                .filter(|instance| {
+                    nodes
+                        .iter()
+                        .any(|node| instance.ends_with(&format!("/instances/{}", node)))
+                })
+                .collect();
+            if !matching.is_empty() {
+                let _: Value = self
+                    .call(
+                        Method::POST,
+                        &format!("{}/deleteInstances", group),
+                        Some(json!({ "instances": matching })),
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps track of requested sizes in memory, without adding or removing actual nodes. Used for local development.
+///
+/// Pools start with no requested node, their registered nodes are considered instead until they are resized.
+#[derive(Default)]
+pub struct FakeProvisioner {
+    sizes: Mutex<BTreeMap<String, usize>>,
+}
+
+impl FakeProvisioner {
+    fn with_sizes<T>(&self, f: impl FnOnce(&mut BTreeMap<String, usize>) -> T) -> Result<T> {
+        self.sizes
+            .lock()
+            .map(|mut sizes| f(&mut sizes))
+            .map_err(|_| Error::Failure("Failed to acquire sizes lock".into()))
+    }
+}
+
+#[async_trait]
+impl NodeProvisioner for FakeProvisioner {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn size(&self, pool: &str) -> Result<usize> {
+        self.with_sizes(|sizes| sizes.get(pool).cloned().unwrap_or_default())
+    }
+
+    async fn resize(&self, pool: &str, size: usize) -> Result<()> {
+        info!(pool = %pool, size, "Resizing pool");
+        self.with_sizes(|sizes| {
+            sizes.insert(pool.to_string(), size);
+        })
+    }
+
+    async fn remove(&self, pool: &str, nodes: &[String]) -> Result<()> {
+        info!(pool = %pool, nodes = ?nodes, "Removing nodes");
+        self.with_sizes(|sizes| {
+            let size = sizes.entry(pool.to_string()).or_default();
+            *size = size.saturating_sub(nodes.len());
+        })
+    }
+
+    fn deletes_nodes(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCALING: PoolScaling = PoolScaling {
+        min_nodes: 1,
+        max_nodes: 4,
+    };
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn target_size_fits_sessions() {
+        assert_eq!(target_size(0, 2, &SCALING), 1);
+        assert_eq!(target_size(2, 2, &SCALING), 1);
+        assert_eq!(target_size(3, 2, &SCALING), 2);
+        assert_eq!(target_size(100, 2, &SCALING), 4);
+        // At least one session per node
+        assert_eq!(target_size(3, 0, &SCALING), 3);
+    }
+
+    #[test]
+    fn plan_grows_up_to_target() {
+        assert_eq!(plan(3, 2, 2, 2, &[]), ScalingPlan::Grow(3));
+        // Unknown requested size defaults to registered nodes
+        assert_eq!(plan(3, 0, 3, 3, &[]), ScalingPlan::Keep);
+        assert_eq!(plan(4, 0, 3, 3, &[]), ScalingPlan::Grow(4));
+    }
+
+    #[test]
+    fn plan_shrinks_idle_nodes() {
+        let idle = names(&["a", "b", "c"]);
+        assert_eq!(
+            plan(1, 3, 3, 3, &idle),
+            ScalingPlan::Shrink(names(&["a", "b"]))
+        );
+        assert_eq!(
+            plan(1, 0, 3, 3, &idle),
+            ScalingPlan::Shrink(names(&["a", "b"]))
+        );
+        // Busy nodes are kept
+        assert_eq!(
+            plan(1, 3, 3, 3, &idle[..1]),
+            ScalingPlan::Shrink(names(&["a"]))
+        );
+        assert_eq!(plan(1, 3, 3, 3, &[]), ScalingPlan::Keep);
+    }
+
+    #[test]
+    fn plan_waits_for_requested_nodes() {
+        assert_eq!(plan(1, 4, 3, 3, &names(&["a"])), ScalingPlan::Keep);
+    }
+
+    #[tokio::test]
+    async fn fake_provisioner_scales_up_and_down() -> Result<()> {
+        let provisioner = FakeProvisioner::default();
+        assert_eq!(provisioner.size("pool").await?, 0);
+        provisioner.resize("pool", 3).await?;
+        assert_eq!(provisioner.size("pool").await?, 3);
+        assert_eq!(provisioner.size("other").await?, 0);
+        provisioner.remove("pool", &names(&["a", "b"])).await?;
+        assert_eq!(provisioner.size("pool").await?, 1);
+        provisioner.remove("pool", &names(&["a", "b"])).await?;
+        assert_eq!(provisioner.size("pool").await?, 0);
+        assert!(!provisioner.deletes_nodes());
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+//! Settings, read from a YAML file and overridden by env variables
+//!
+//! The file at `CONFIG_PATH` (`/etc/playground/config.yaml` by default) is optional. Each setting can be overridden
+//! by its env variable (e.g. `SESSION_DEFAULT_DURATION`). Secrets (identity provider client ID and secret) are only
+//! read from env variables.
+//!
+//! Non-secret settings are reloaded when the file changes, typically when the ConfigMap it is mounted from is updated.
+
+use crate::{
+    error::{Error, Result},
+    placement::PlacementStrategy,
+    types::{Quota, SessionDefaults},
+};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+use thiserror::Error;
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/playground/config.yaml";
+
+/// All problems found while validating settings
+#[derive(Error, Debug)]
+#[error("Invalid configuration: {}", .0.join("; "))]
+pub struct ValidationErrors(pub Vec<String>);
+
+/// Looks up env variables by name, tests provide their own rather than mutating the process env
+type Vars = fn(&str) -> Option<String>;
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+/// Content of the configuration file
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    session: SessionFile,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct SessionFile {
+    /// Minutes
+    default_duration: Option<u64>,
+    /// Minutes
+    max_duration: Option<u64>,
+    default_pool_affinity: Option<String>,
+    max_sessions_per_node: Option<usize>,
+    placement: Option<String>,
+    /// Applies to users without user or organization quota
+    #[serde(default)]
+    quota: Quota,
+}
+
+/// Settings read once at startup
+pub struct Settings {
+    pub client_id: String,
+    pub client_secret: String,
+    pub session: SessionDefaults,
+}
+
+fn config_path() -> PathBuf {
+    env::var("CONFIG_PATH")
+        .unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())
+        .into()
+}
+
+fn read_file(errors: &mut Vec<String>, path: &Path) -> File {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_yaml::from_str(&content).unwrap_or_else(|err| {
+            errors.push(format!("{}: {}", path.display(), err));
+            File::default()
+        }),
+        // Settings can all be provided via env variables
+        Err(_) => File::default(),
+    }
+}
+
+// The env variable `name` if set, `value` otherwise
+fn setting<T>(errors: &mut Vec<String>, vars: Vars, name: &str, value: Option<T>) -> Option<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match vars(name) {
+        Some(var) => match var.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(err) => {
+                errors.push(format!("{}: {}", name, err));
+                None
+            }
+        },
+        None => value,
+    }
+}
+
+fn required<T>(errors: &mut Vec<String>, key: &str, name: &str, value: Option<T>) -> Option<T> {
+    if value.is_none() {
+        errors.push(format!("missing {} (or {})", key, name));
+    }
+    value
+}
+
+fn session_defaults(errors: &mut Vec<String>, path: &Path, vars: Vars) -> Option<SessionDefaults> {
+    let file = read_file(errors, path).session;
+    let duration = setting(
+        errors,
+        vars,
+        "SESSION_DEFAULT_DURATION",
+        file.default_duration,
+    );
+    let duration = required(
+        errors,
+        "session.defaultDuration",
+        "SESSION_DEFAULT_DURATION",
+        duration,
+    );
+    let max_duration = setting(errors, vars, "SESSION_MAX_DURATION", file.max_duration);
+    let max_duration = required(
+        errors,
+        "session.maxDuration",
+        "SESSION_MAX_DURATION",
+        max_duration,
+    );
+    let pool_affinity = setting(
+        errors,
+        vars,
+        "SESSION_DEFAULT_POOL_AFFINITY",
+        file.default_pool_affinity,
+    );
+    let pool_affinity = required(
+        errors,
+        "session.defaultPoolAffinity",
+        "SESSION_DEFAULT_POOL_AFFINITY",
+        pool_affinity,
+    );
+    let max_sessions_per_node = setting(
+        errors,
+        vars,
+        "SESSION_DEFAULT_MAX_PER_NODE",
+        file.max_sessions_per_node,
+    );
+    let max_sessions_per_node = required(
+        errors,
+        "session.maxSessionsPerNode",
+        "SESSION_DEFAULT_MAX_PER_NODE",
+        max_sessions_per_node,
+    );
+    let placement = setting(errors, vars, "SESSION_PLACEMENT_STRATEGY", file.placement);
+    let placement = match placement.map(|placement| placement.parse::<PlacementStrategy>()) {
+        Some(Ok(placement)) => placement,
+        Some(Err(err)) => {
+            errors.push(format!("session.placement: {}", err));
+            PlacementStrategy::default()
+        }
+        None => PlacementStrategy::default(),
+    };
+    // Default quota is unlimited
+    let quota = Quota {
+        max_concurrent_sessions: setting(
+            errors,
+            vars,
+            "SESSION_DEFAULT_MAX_CONCURRENT_SESSIONS",
+            file.quota.max_concurrent_sessions,
+        ),
+        max_session_hours_per_day: setting(
+            errors,
+            vars,
+            "SESSION_DEFAULT_MAX_HOURS_PER_DAY",
+            file.quota.max_session_hours_per_day,
+        ),
+        max_session_hours_per_week: setting(
+            errors,
+            vars,
+            "SESSION_DEFAULT_MAX_HOURS_PER_WEEK",
+            file.quota.max_session_hours_per_week,
+        ),
+        allowed_templates: vars("SESSION_DEFAULT_ALLOWED_TEMPLATES")
+            .map(|templates| templates.split(',').map(|s| s.trim().to_string()).collect())
+            .or(file.quota.allowed_templates),
+    };
+
+    if duration == Some(0) {
+        errors.push("session.defaultDuration must be greater than 0".to_string());
+    }
+    if let (Some(duration), Some(max_duration)) = (duration, max_duration) {
+        if duration > max_duration {
+            errors.push("session.defaultDuration must be at most session.maxDuration".to_string());
+        }
+    }
+    if pool_affinity.as_deref() == Some("") {
+        errors.push("session.defaultPoolAffinity must not be empty".to_string());
+    }
+    if max_sessions_per_node == Some(0) {
+        errors.push("session.maxSessionsPerNode must be greater than 0".to_string());
+    }
+
+    Some(SessionDefaults {
+        duration: Duration::from_secs(duration? * 60),
+        max_duration: Duration::from_secs(max_duration? * 60),
+        pool_affinity: pool_affinity?,
+        max_sessions_per_pod: max_sessions_per_node?,
+        placement,
+        quota,
+    })
+}
+
+fn secret(errors: &mut Vec<String>, vars: Vars, name: &str) -> Option<String> {
+    let value = vars(name);
+    if value.is_none() {
+        errors.push(format!("missing {}", name));
+    }
+    value
+}
+
+fn validated<T>(errors: Vec<String>, value: Option<T>) -> Result<T> {
+    match value {
+        Some(value) if errors.is_empty() => Ok(value),
+        _ => Err(Error::Failure(Box::new(ValidationErrors(errors)))),
+    }
+}
+
+/// Loads all settings. `env_prefix` prefixes the identity provider client ID and secret env variables (e.g. `GITHUB`).
+/// Fails with all invalid or missing settings.
+pub fn load(env_prefix: &str) -> Result<Settings> {
+    load_from(env_prefix, &config_path(), env_var)
+}
+
+fn load_from(env_prefix: &str, path: &Path, vars: Vars) -> Result<Settings> {
+    let mut errors = Vec::new();
+    let client_id = secret(&mut errors, vars, &format!("{}_CLIENT_ID", env_prefix));
+    let client_secret = secret(&mut errors, vars, &format!("{}_CLIENT_SECRET", env_prefix));
+    let session = session_defaults(&mut errors, path, vars);
+    let settings = match (client_id, client_secret, session) {
+        (Some(client_id), Some(client_secret), Some(session)) => Some(Settings {
+            client_id,
+            client_secret,
+            session,
+        }),
+        _ => None,
+    };
+    validated(errors, settings)
+}
+
+/// Settings that can be reloaded at runtime, shared by all clones
+#[derive(Clone, Debug)]
+pub struct Reloadable<T>(Arc<RwLock<T>>);
+
+impl<T: Clone> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Reloadable(Arc::new(RwLock::new(value)))
+    }
+
+    /// The current value
+    pub fn get(&self) -> T {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set(&self, value: T) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = value;
+    }
+}
+
+impl<T: Clone + Serialize> Serialize for Reloadable<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    // Follows symlinks, updated when a mounted ConfigMap changes
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Reloads settings when the configuration file changes
+#[derive(Clone)]
+pub struct Watcher {
+    path: PathBuf,
+    vars: Vars,
+    modified: Arc<Mutex<Option<SystemTime>>>,
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Watcher::new(config_path(), env_var)
+    }
+}
+
+impl Watcher {
+    fn new(path: PathBuf, vars: Vars) -> Self {
+        Watcher {
+            modified: Arc::new(Mutex::new(modified(&path))),
+            path,
+            vars,
+        }
+    }
+
+    /// Updates `session` if the file changed since the last call. Returns `None` if it didn't change.
+    /// Invalid changes are ignored and `session` keeps its current value.
+    pub fn reload(&self, session: &Reloadable<SessionDefaults>) -> Option<Result<()>> {
+        let current = modified(&self.path);
+        let mut last = self
+            .modified
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if *last == current {
+            return None;
+        }
+        *last = current;
+
+        let mut errors = Vec::new();
+        let defaults = session_defaults(&mut errors, &self.path, self.vars);
+        Some(validated(errors, defaults).map(|defaults| session.set(defaults)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    const CONFIG: &str = "
+session:
+  defaultDuration: 60
+  maxDuration: 120
+  defaultPoolAffinity: default
+  maxSessionsPerNode: 6
+";
+
+    // Writes `content` to a temporary file, `name` must be unique among tests
+    fn config_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("playground-{}-{}.yaml", name, process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn secrets(name: &str) -> Option<String> {
+        match name {
+            "GITHUB_CLIENT_ID" => Some("id".to_string()),
+            "GITHUB_CLIENT_SECRET" => Some("secret".to_string()),
+            _ => None,
+        }
+    }
+
+    fn validation_errors(result: Result<Settings>) -> Vec<String> {
+        match result {
+            Err(Error::Failure(err)) => err.downcast_ref::<ValidationErrors>().unwrap().0.clone(),
+            _ => panic!("Settings should be invalid"),
+        }
+    }
+
+    // Writes in a row might share the same modification time
+    fn forget_modification(watcher: &Watcher) {
+        *watcher.modified.lock().unwrap() = None;
+    }
+
+    #[test]
+    fn all_problems_are_reported_at_once() {
+        let path = config_file(
+            "invalid",
+            "
+session:
+  defaultDuration: 0
+  defaultPoolAffinity: default
+  placement: random
+",
+        );
+        let vars = |name: &str| match name {
+            "SESSION_MAX_DURATION" => Some("soon".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            validation_errors(load_from("GITHUB", &path, vars)),
+            vec![
+                "missing GITHUB_CLIENT_ID",
+                "missing GITHUB_CLIENT_SECRET",
+                "SESSION_MAX_DURATION: invalid digit found in string",
+                "missing session.maxDuration (or SESSION_MAX_DURATION)",
+                "missing session.maxSessionsPerNode (or SESSION_DEFAULT_MAX_PER_NODE)",
+                "session.placement: Invalid placement: must be one of bin-pack, spread or prefer-image",
+                "session.defaultDuration must be greater than 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn env_variables_override_file() {
+        let path = config_file("overridden", CONFIG);
+        let vars = |name: &str| match name {
+            "SESSION_DEFAULT_DURATION" => Some("30".to_string()),
+            "SESSION_DEFAULT_ALLOWED_TEMPLATES" => Some("node, kitties".to_string()),
+            _ => secrets(name),
+        };
+        let settings = load_from("GITHUB", &path, vars).unwrap();
+        assert_eq!(settings.client_id, "id");
+        assert_eq!(settings.client_secret, "secret");
+        let session = settings.session;
+        assert_eq!(session.duration, Duration::from_secs(30 * 60));
+        assert_eq!(session.max_duration, Duration::from_secs(120 * 60));
+        assert_eq!(session.pool_affinity, "default");
+        assert_eq!(session.max_sessions_per_pod, 6);
+        assert_eq!(
+            session.quota.allowed_templates,
+            Some(vec!["node".to_string(), "kitties".to_string()])
+        );
+        assert_eq!(session.quota.max_concurrent_sessions, None);
+    }
+
+    #[test]
+    fn invalid_changes_are_not_reloaded() {
+        let path = config_file("reloaded", CONFIG);
+        let session = Reloadable::new(load_from("GITHUB", &path, secrets).unwrap().session);
+        let watcher = Watcher::new(path.clone(), secrets);
+        assert!(watcher.reload(&session).is_none());
+
+        fs::write(
+            &path,
+            CONFIG.replace("defaultDuration: 60", "defaultDuration: 180"),
+        )
+        .unwrap();
+        forget_modification(&watcher);
+        assert!(matches!(watcher.reload(&session), Some(Err(_))));
+        assert_eq!(session.get().duration, Duration::from_secs(60 * 60));
+
+        fs::write(
+            &path,
+            CONFIG.replace("defaultDuration: 60", "defaultDuration: 90"),
+        )
+        .unwrap();
+        forget_modification(&watcher);
+        assert!(matches!(watcher.reload(&session), Some(Ok(()))));
+        assert_eq!(session.get().duration, Duration::from_secs(90 * 60));
+        assert!(watcher.reload(&session).is_none());
+    }
+}
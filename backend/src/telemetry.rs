@@ -0,0 +1,106 @@
+//! Distributed tracing
+//!
+//! Spans of `Manager`, `Engine` and identity providers calls are exported over OTLP (gRPC) to the collector at
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` (e.g. `http://localhost:4317`). Tracing is disabled when unset.
+//! The trace context is propagated via W3C `traceparent` headers: taken from incoming requests and sent along with
+//! outgoing ones (e.g. to GitHub), so that the backend is part of traces spanning other services.
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    runtime,
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{self, Tracer},
+        Resource,
+    },
+    trace::TraceContextExt,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use std::{env, error::Error};
+use tracing::{field, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+const SERVICE_NAME: &str = "playground";
+
+/// If spans are exported
+pub fn enabled() -> bool {
+    env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok()
+}
+
+/// Returns a layer exporting spans over OTLP, if `OTEL_EXPORTER_OTLP_ENDPOINT` is set
+pub fn layer<S>() -> Result<Option<OpenTelemetryLayer<S, Tracer>>, Box<dyn Error>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let endpoint = match env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => endpoint,
+        Err(_) => return Ok(None),
+    };
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                SERVICE_NAME,
+            )])),
+        )
+        .install_batch(runtime::Tokio)?;
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Flushes pending spans
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Records the ID of the trace `span` belongs to as its `trace_id` field, so that it is included in logs.
+/// `span` must declare this field.
+pub fn record_trace_id(span: &Span) {
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        span.record(
+            "trace_id",
+            &field::display(span_context.trace_id().to_hex()),
+        );
+    }
+}
+
+/// Makes the trace context propagated by `extractor` (e.g. headers of an incoming request) the parent of `span`.
+/// Must be called before `span` is entered.
+pub fn set_parent(span: &Span, extractor: &dyn Extractor) {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(extractor));
+    span.set_parent(parent);
+}
+
+// Writes propagated fields as HTTP headers
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Adds the trace context of the current span to `headers` of an outgoing request
+pub fn inject(headers: &mut HeaderMap) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
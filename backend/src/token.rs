@@ -0,0 +1,20 @@
+//! Personal API tokens utilities
+//!
+//! Tokens are only shown once to their owner. Only their SHA-256 hash is stored.
+
+use rand::{thread_rng, RngCore};
+use sha2::{Digest, Sha256};
+
+const TOKEN_PREFIX: &str = "pgt_";
+
+/// Generates a new random token
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// Returns the hex encoded hash of `token`, used to store and lookup tokens
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
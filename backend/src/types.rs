@@ -1,22 +1,132 @@
+use crate::placement::PlacementStrategy;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
+    ops::AddAssign,
     str::FromStr,
-    time::{Duration, SystemTime},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tracing::warn;
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct Session {
     pub user_id: String,
+    /// ID of the user who created this session
+    pub creator: String,
+    /// ID of `template`, as used to create this session
+    pub template_id: String,
     pub template: Template,
+    /// Organizations of its owner when created
+    pub organizations: Vec<String>,
     pub url: String,
     pub pod: Pod,
     #[serde(with = "duration")]
+    #[schemars(with = "u64")]
     pub duration: Duration,
     pub node: String,
+    /// Pool this session has been deployed on
+    pub pool: Option<String>,
+    /// Set when its node is drained. Seconds since UNIX epoch after which this session is deleted unless migrated.
+    pub drain_deadline: Option<u64>,
+}
+
+/// Outcome of a dependency check
+#[derive(Serialize, Clone, Debug)]
+pub struct HealthCheck {
+    pub healthy: bool,
+    pub error: Option<String>,
+    /// Only failing critical checks make the backend unready
+    pub critical: bool,
+}
+
+impl<E: ToString> From<Result<(), E>> for HealthCheck {
+    fn from(result: Result<(), E>) -> Self {
+        HealthCheck {
+            healthy: result.is_ok(),
+            error: result.err().map(|err| err.to_string()),
+            critical: true,
+        }
+    }
+}
+
+/// If requests can be served, i.e. all critical dependencies are healthy
+#[derive(Serialize, Clone, Debug)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: BTreeMap<String, HealthCheck>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Version {
+    pub version: String,
+    /// SHA of the commit this backend has been built from, if known
+    pub sha: Option<String>,
+    pub features: Features,
+}
+
+/// Features enabled in this deployment
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Features {
+    pub identity_provider: String,
+    /// Pools are not scaled when `None`
+    pub node_provisioner: Option<String>,
+    pub placement: PlacementStrategy,
+    pub tracing: bool,
+    pub json_logs: bool,
+}
+
+/// Why a session ended
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum SessionEndReason {
+    Expired,
+    /// Deleted by its owner
+    User,
+    /// Deleted by another user
+    Admin,
+    /// Deleted while failed
+    Failure,
+    /// Not migrated before its node has been drained
+    Drained,
+    /// Recreated on another node, the session continues as a new record
+    Migrated,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// A finished session
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRecord {
+    pub id: String,
+    pub user_id: String,
+    pub creator: String,
+    /// Template ID
+    pub template: String,
+    pub pool: Option<String>,
+    pub node: String,
+    /// Seconds since UNIX epoch
+    pub start: u64,
+    /// Seconds since UNIX epoch
+    pub end: u64,
+    pub end_reason: SessionEndReason,
+    /// Seconds
+    pub deploy_duration: Option<u64>,
+}
+
+/// Aggregated usage of finished sessions
+#[derive(Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub sessions: usize,
+    pub session_hours: f64,
+    pub failures: usize,
+    /// Seconds
+    pub average_deploy_duration: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum Phase {
     Pending,
     Running,
@@ -39,18 +149,21 @@ impl FromStr for Phase {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Pod {
     pub phase: Phase,
     pub reason: String,
     pub message: String,
     #[serde(with = "system_time")]
+    #[schemars(with = "Option<u64>")]
     pub start_time: Option<SystemTime>,
     pub container: Option<ContainerStatus>,
+    /// Seconds between the pod creation and its container start
+    pub deploy_duration: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum ContainerPhase {
     Running,
     Terminated,
@@ -58,119 +171,567 @@ pub enum ContainerPhase {
     Unknown,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, JsonSchema)]
 pub struct ContainerStatus {
     pub phase: ContainerPhase,
     pub reason: Option<String>,
     pub message: Option<String>,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Pool {
     pub name: String,
     pub instance_type: Option<String>,
     pub nodes: Vec<Node>,
+    pub scaling: Option<PoolScaling>,
+    /// New sessions can't be created on cordoned pools
+    pub cordoned: bool,
+    /// Sum of all nodes allocatable resources
+    pub allocatable: Resources,
+    /// Sum of all nodes used resources
+    pub used: Resources,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Node {
     pub hostname: String,
+    pub allocatable: Resources,
+    /// Requested by running and pending sessions
+    pub used: Resources,
+    /// Number of running and pending sessions
+    pub sessions: usize,
+    /// New sessions can't be scheduled on cordoned nodes
+    pub cordoned: bool,
+    pub ready: bool,
+}
+
+/// CPU in millicores, memory and storage in bytes
+#[derive(Serialize, Clone, Copy, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Resources {
+    pub cpu: u64,
+    pub memory: u64,
+    pub storage: u64,
+}
+
+impl AddAssign for Resources {
+    fn add_assign(&mut self, other: Self) {
+        self.cpu += other.cpu;
+        self.memory += other.memory;
+        self.storage += other.storage;
+    }
+}
+
+/// Configuration of a pool, as stored in the `playground-pools` ConfigMap
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolConfiguration {
+    /// Labels nodes of this pool must all have. Defaults to the pool label (`NODE_POOL_LABEL`) set to the pool ID.
+    pub node_selector: Option<BTreeMap<String, String>>,
+    /// Added to sessions pods, e.g. to allow them on tainted nodes
+    #[serde(default)]
+    pub tolerations: Vec<Toleration>,
+    /// Pools without scaling bounds keep their current nodes
+    pub scaling: Option<PoolScaling>,
+    /// Cordoned pools don't accept new sessions and are not scaled
+    #[serde(default)]
+    pub cordoned: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Toleration {
+    pub key: Option<String>,
+    /// `Equal` (default) or `Exists`
+    pub operator: Option<String>,
+    pub value: Option<String>,
+    /// e.g. `NoSchedule`. All effects are tolerated when not set
+    pub effect: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolScaling {
+    pub min_nodes: usize,
+    pub max_nodes: usize,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SessionConfiguration {
     pub template: String,
     #[serde(default)]
     #[serde(with = "option_duration")]
+    #[schemars(with = "Option<u64>")]
     pub duration: Option<Duration>,
     pub pool_affinity: Option<String>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DrainConfiguration {
+    /// The number of minutes left to sessions owners to migrate
+    #[serde(default)]
+    #[serde(with = "option_duration")]
+    #[schemars(with = "Option<u64>")]
+    pub grace_period: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct SessionUpdateConfiguration {
     #[serde(default)]
     #[serde(with = "option_duration")]
+    #[schemars(with = "Option<u64>")]
     pub duration: Option<Duration>,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SessionDefaults {
     #[serde(with = "duration")]
+    #[schemars(with = "u64")]
     pub duration: Duration,
     #[serde(with = "duration")]
+    #[schemars(with = "u64")]
     pub max_duration: Duration,
     pub pool_affinity: String,
     pub max_sessions_per_pod: usize,
+    /// How nodes are picked within a pool
+    pub placement: PlacementStrategy,
+    /// Applies to users without user or organization quota
+    pub quota: Quota,
+}
+
+/// Role granted to all logged users
+pub const DEFAULT_ROLE: &str = "user";
+/// Role granted all permissions
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Actions a `LoggedUser` can be allowed to perform
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, JsonSchema,
+)]
+pub enum Permission {
+    /// Access its own session
+    SessionRead,
+    /// Create, update and delete its own session
+    SessionWrite,
+    /// Access sessions of all users
+    SessionReadAny,
+    /// Create, update and delete sessions of all users
+    SessionWriteAny,
+    UserRead,
+    UserEdit,
+    TemplateEdit,
+    PoolRead,
+    /// Cordon and drain pools and nodes
+    PoolEdit,
+    CustomizeDuration,
+    CustomizePoolAffinity,
+    /// Access the audit log
+    AuditRead,
+    /// Access sessions history and usage reports
+    ReportRead,
 }
 
+impl Permission {
+    pub const ALL: [Permission; 13] = [
+        Permission::SessionRead,
+        Permission::SessionWrite,
+        Permission::SessionReadAny,
+        Permission::SessionWriteAny,
+        Permission::UserRead,
+        Permission::UserEdit,
+        Permission::TemplateEdit,
+        Permission::PoolRead,
+        Permission::PoolEdit,
+        Permission::CustomizeDuration,
+        Permission::CustomizePoolAffinity,
+        Permission::AuditRead,
+        Permission::ReportRead,
+    ];
+}
+
+/// A named set of `Permission`s, assignable to users and organizations
 #[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Role {
+    pub permissions: BTreeSet<Permission>,
+}
+
+impl Role {
+    /// Roles always available, unless overridden by configuration
+    pub fn defaults() -> BTreeMap<String, Role> {
+        BTreeMap::from([
+            (
+                ADMIN_ROLE.to_string(),
+                Role {
+                    permissions: Permission::ALL.iter().cloned().collect(),
+                },
+            ),
+            (
+                DEFAULT_ROLE.to_string(),
+                Role {
+                    permissions: BTreeSet::from([
+                        Permission::SessionRead,
+                        Permission::SessionWrite,
+                    ]),
+                },
+            ),
+        ])
+    }
+}
+
+/// Configuration shared by all members of a GitHub organization
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Organization {
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Applies to each member individually
+    pub quota: Option<Quota>,
+    /// Configuration of GitHub teams, keyed by team slug
+    #[serde(default)]
+    pub teams: BTreeMap<String, Team>,
+}
+
+/// Configuration shared by all members of a GitHub team, on top of its organization one
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Team {
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub pool_affinity: Option<String>,
+    /// Applies to each member individually
+    pub quota: Option<Quota>,
+}
+
+/// Limits on sessions created by a user. `None` means unlimited.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    pub max_concurrent_sessions: Option<usize>,
+    pub max_session_hours_per_day: Option<f64>,
+    pub max_session_hours_per_week: Option<f64>,
+    pub allowed_templates: Option<Vec<String>>,
+}
+
+// `None` (unlimited) wins over any limit
+fn most_permissive<T: PartialOrd>(values: Vec<Option<T>>) -> Option<T> {
+    let mut result = None;
+    for value in values {
+        let value = value?;
+        if result.as_ref().map_or(true, |current| &value > current) {
+            result = Some(value);
+        }
+    }
+    result
+}
+
+impl Quota {
+    /// Combines `quotas` by keeping the most permissive limits
+    pub fn merge(quotas: Vec<&Quota>) -> Quota {
+        Quota {
+            max_concurrent_sessions: most_permissive(
+                quotas.iter().map(|q| q.max_concurrent_sessions).collect(),
+            ),
+            max_session_hours_per_day: most_permissive(
+                quotas.iter().map(|q| q.max_session_hours_per_day).collect(),
+            ),
+            max_session_hours_per_week: most_permissive(
+                quotas
+                    .iter()
+                    .map(|q| q.max_session_hours_per_week)
+                    .collect(),
+            ),
+            allowed_templates: quotas
+                .iter()
+                .map(|q| q.allowed_templates.clone())
+                .collect::<Option<Vec<Vec<String>>>>()
+                .map(|templates| {
+                    templates
+                        .into_iter()
+                        .flatten()
+                        .collect::<BTreeSet<String>>()
+                        .into_iter()
+                        .collect()
+                }),
+        }
+    }
+
+    /// Returns this quota, with limits it doesn't set taken from `base`
+    pub fn over(&self, base: &Quota) -> Quota {
+        Quota {
+            max_concurrent_sessions: self
+                .max_concurrent_sessions
+                .or(base.max_concurrent_sessions),
+            max_session_hours_per_day: self
+                .max_session_hours_per_day
+                .or(base.max_session_hours_per_day),
+            max_session_hours_per_week: self
+                .max_session_hours_per_week
+                .or(base.max_session_hours_per_week),
+            allowed_templates: self
+                .allowed_templates
+                .clone()
+                .or_else(|| base.allowed_templates.clone()),
+        }
+    }
+
+    pub fn allows_template(&self, template: &str) -> bool {
+        self.allowed_templates
+            .as_ref()
+            .map_or(true, |templates| templates.iter().any(|t| t == template))
+    }
+
+    pub fn remaining(&self, usage: &Usage) -> Remaining {
+        Remaining {
+            concurrent_sessions: self
+                .max_concurrent_sessions
+                .map(|max| max.saturating_sub(usage.concurrent_sessions)),
+            session_hours_per_day: self
+                .max_session_hours_per_day
+                .map(|max| (max - usage.session_hours_last_day).max(0.0)),
+            session_hours_per_week: self
+                .max_session_hours_per_week
+                .map(|max| (max - usage.session_hours_last_week).max(0.0)),
+        }
+    }
+}
+
+/// Sessions created by a user, over rolling windows
+#[derive(Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub concurrent_sessions: usize,
+    pub session_hours_last_day: f64,
+    pub session_hours_last_week: f64,
+}
+
+/// What is left of a `Quota`. `None` means unlimited.
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Remaining {
+    pub concurrent_sessions: Option<usize>,
+    pub session_hours_per_day: Option<f64>,
+    pub session_hours_per_week: Option<f64>,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct QuotaStatus {
+    pub quota: Quota,
+    pub usage: Usage,
+    pub remaining: Remaining,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
-    pub admin: bool,
-    #[serde(default = "default_as_false")]
-    pub can_customize_duration: bool,
-    #[serde(default = "default_as_false")]
-    pub can_customize_pool_affinity: bool,
+    #[serde(default)]
+    pub roles: Vec<String>,
     pub pool_affinity: Option<String>,
+    /// Overrides quotas of organizations
+    pub quota: Option<Quota>,
+    /// Organizations this user belonged to when last logged in
+    #[serde(default)]
+    pub organizations: Vec<String>,
+    /// Teams, as `org/team`, this user belonged to when last logged in
+    #[serde(default)]
+    pub teams: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.roles.iter().any(|role| role == ADMIN_ROLE)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct UserConfiguration {
-    pub admin: bool,
-    #[serde(default = "default_as_false")]
-    pub can_customize_duration: bool,
-    #[serde(default = "default_as_false")]
-    pub can_customize_pool_affinity: bool,
+    #[serde(default)]
+    pub roles: Vec<String>,
     pub pool_affinity: Option<String>,
+    pub quota: Option<Quota>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct UserUpdateConfiguration {
-    pub admin: bool,
-    #[serde(default = "default_as_false")]
-    pub can_customize_duration: bool,
-    #[serde(default = "default_as_false")]
-    pub can_customize_pool_affinity: bool,
+    #[serde(default)]
+    pub roles: Vec<String>,
     pub pool_affinity: Option<String>,
+    pub quota: Option<Quota>,
 }
-#[derive(Serialize, Deserialize, Clone, Debug)]
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct LoggedUser {
     pub id: String,
-    pub admin: bool,
     pub organizations: Vec<String>,
+    /// Teams, as `org/team`
+    pub teams: Vec<String>,
     pub pool_affinity: Option<String>,
-    pub can_customize_duration: bool,
-    pub can_customize_pool_affinity: bool,
+    /// Roles granted directly or via organizations and teams
+    pub roles: BTreeSet<String>,
+    pub permissions: BTreeSet<Permission>,
+    pub quota: Quota,
+    /// Authenticated with an API token rather than a login
+    #[serde(skip)]
+    pub api_token: bool,
 }
 
 impl LoggedUser {
-    pub fn is_paritytech_member(&self) -> bool {
-        self.organizations.contains(&"paritytech".to_string())
-    }
-    pub fn can_customize_duration(&self) -> bool {
-        self.admin || self.can_customize_duration || self.is_paritytech_member()
-    }
+    /// Creates a `LoggedUser` by resolving roles granted to `user`, its `organizations` and its `teams`
+    pub fn new(
+        id: String,
+        organizations: Vec<String>,
+        teams: Vec<String>,
+        user: Option<&User>,
+        organizations_configuration: &BTreeMap<String, Organization>,
+        roles: &BTreeMap<String, Role>,
+        default_quota: &Quota,
+    ) -> Self {
+        let mut granted_roles = BTreeSet::from([DEFAULT_ROLE.to_string()]);
+        if let Some(user) = user {
+            granted_roles.extend(user.roles.iter().cloned());
+        }
+        for organization in &organizations {
+            if let Some(configuration) = organizations_configuration.get(organization) {
+                granted_roles.extend(configuration.roles.iter().cloned());
+            }
+        }
+        // Teams with the name of their organization
+        let teams_configuration: Vec<(&str, &Team)> = teams
+            .iter()
+            .filter_map(|team| {
+                let (organization, slug) = team.split_once('/')?;
+                let configuration = organizations_configuration
+                    .get(organization)?
+                    .teams
+                    .get(slug)?;
+                Some((organization, configuration))
+            })
+            .collect();
+        for (_, configuration) in &teams_configuration {
+            granted_roles.extend(configuration.roles.iter().cloned());
+        }
+        let permissions = granted_roles
+            .iter()
+            .filter_map(|name| {
+                let role = roles.get(name);
+                if role.is_none() {
+                    warn!(user = %id, role = %name, "Unknown role");
+                }
+                role
+            })
+            .flat_map(|role| role.permissions.iter().cloned())
+            .collect();
+
+        // User quota wins, then the most permissive of all organizations and teams quotas.
+        // A team quota applies on top of its organization one, inheriting the limits it doesn't set.
+        let mut organizations_quotas: Vec<Quota> = Vec::new();
+        let names: BTreeSet<&str> = organizations
+            .iter()
+            .map(String::as_str)
+            .chain(teams_configuration.iter().map(|(name, _)| *name))
+            .collect();
+        for name in names {
+            let organization_quota = organizations_configuration
+                .get(name)
+                .and_then(|configuration| configuration.quota.as_ref());
+            let base = organization_quota.cloned().unwrap_or_default();
+            let teams_quotas: Vec<Quota> = teams_configuration
+                .iter()
+                .filter(|(organization, _)| *organization == name)
+                .filter_map(|(_, team)| team.quota.as_ref())
+                .map(|quota| quota.over(&base))
+                .collect();
+            if !teams_quotas.is_empty() {
+                organizations_quotas.extend(teams_quotas);
+            } else if let Some(quota) = organization_quota {
+                organizations_quotas.push(quota.clone());
+            }
+        }
+        let quota = match user.and_then(|user| user.quota.as_ref()) {
+            Some(quota) => quota.clone(),
+            None if !organizations_quotas.is_empty() => {
+                Quota::merge(organizations_quotas.iter().collect())
+            }
+            None => default_quota.clone(),
+        };
 
-    pub fn can_customize_pool_affinity(&self) -> bool {
-        self.admin || self.can_customize_pool_affinity || self.is_paritytech_member()
+        // User pool affinity wins, then the first one defined by a team
+        let pool_affinity = user
+            .and_then(|user| user.pool_affinity.clone())
+            .or_else(|| {
+                teams_configuration
+                    .iter()
+                    .find_map(|(_, configuration)| configuration.pool_affinity.clone())
+            });
+
+        LoggedUser {
+            pool_affinity,
+            id,
+            organizations,
+            teams,
+            roles: granted_roles,
+            permissions,
+            quota,
+            api_token: false,
+        }
     }
 
-    pub fn has_admin_read_rights(&self) -> bool {
-        self.admin || self.is_paritytech_member()
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
     }
+}
 
-    pub fn has_admin_edit_rights(&self) -> bool {
-        self.admin
+/// A personal API token, as stored. The token itself is only known by its owner.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub name: String,
+    pub user_id: String,
+    /// Permissions this token is restricted to
+    pub scopes: BTreeSet<Permission>,
+    /// Organizations of the user when this token was created
+    pub organizations: Vec<String>,
+    /// Teams of the user when this token was created
+    #[serde(default)]
+    pub teams: Vec<String>,
+    /// Seconds since UNIX epoch
+    pub created_at: u64,
+    /// Seconds since UNIX epoch
+    pub expires_at: u64,
+}
+
+impl ApiToken {
+    pub fn is_expired(&self) -> bool {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(true, |now| now.as_secs() >= self.expires_at)
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct ApiTokenConfiguration {
+    pub scopes: BTreeSet<Permission>,
+    #[serde(default)]
+    #[serde(with = "option_duration")]
+    #[schemars(with = "Option<u64>")]
+    pub duration: Option<Duration>,
+}
+
+/// Returned once when a token is created
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiToken {
+    pub token: String,
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Template {
     pub name: String,
     pub image: String,
@@ -179,19 +740,30 @@ pub struct Template {
     pub runtime: Option<RuntimeConfiguration>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Availability of a `Template` image on the cluster nodes
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateImage {
+    pub image: String,
+    /// Hostnames of the nodes where the image is already pulled, per pool
+    pub pools: BTreeMap<String, Vec<String>>,
+    /// True if the image is pulled on no node at all
+    pub missing: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct RuntimeConfiguration {
     pub env: Option<Vec<NameValuePair>>,
     pub ports: Option<Vec<Port>>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct NameValuePair {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Port {
     pub name: String,
     pub protocol: Option<String>,
@@ -226,9 +798,19 @@ mod system_time {
 }
 
 mod option_duration {
-    use serde::{self, Deserialize, Deserializer};
+    use serde::{self, Deserialize, Deserializer, Serializer};
     use std::time::Duration;
 
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(duration) => serializer.serialize_some(&(duration.as_secs() / 60)),
+            None => serializer.serialize_none(),
+        }
+    }
+
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
     where
         D: Deserializer<'de>,
@@ -251,6 +833,98 @@ mod duration {
     }
 }
 
-fn default_as_false() -> bool {
-    false
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(concurrent: Option<usize>, per_day: Option<f64>) -> Quota {
+        Quota {
+            max_concurrent_sessions: concurrent,
+            max_session_hours_per_day: per_day,
+            ..Quota::default()
+        }
+    }
+
+    fn logged_user(
+        teams: Vec<&str>,
+        user: Option<&User>,
+        organizations: &BTreeMap<String, Organization>,
+    ) -> LoggedUser {
+        LoggedUser::new(
+            "alice".to_string(),
+            vec!["org".to_string()],
+            teams.into_iter().map(String::from).collect(),
+            user,
+            organizations,
+            &Role::defaults(),
+            &quota(Some(1), Some(1.0)),
+        )
+    }
+
+    fn organizations(team_quota: Option<Quota>) -> BTreeMap<String, Organization> {
+        BTreeMap::from([(
+            "org".to_string(),
+            Organization {
+                quota: Some(quota(Some(2), Some(8.0))),
+                teams: BTreeMap::from([(
+                    "team".to_string(),
+                    Team {
+                        quota: team_quota,
+                        ..Team::default()
+                    },
+                )]),
+                ..Organization::default()
+            },
+        )])
+    }
+
+    #[test]
+    fn quota_over_inherits_unset_limits() {
+        let quota = quota(Some(5), None).over(&quota(Some(2), Some(8.0)));
+        assert_eq!(quota.max_concurrent_sessions, Some(5));
+        assert_eq!(quota.max_session_hours_per_day, Some(8.0));
+        assert_eq!(quota.max_session_hours_per_week, None);
+    }
+
+    #[test]
+    fn merge_keeps_most_permissive_limits() {
+        let merged = Quota::merge(vec![&quota(Some(2), None), &quota(Some(5), Some(8.0))]);
+        assert_eq!(merged.max_concurrent_sessions, Some(5));
+        assert_eq!(merged.max_session_hours_per_day, None);
+    }
+
+    #[test]
+    fn default_quota_applies_without_organization_quota() {
+        let user = logged_user(vec![], None, &BTreeMap::new());
+        assert_eq!(user.quota, quota(Some(1), Some(1.0)));
+    }
+
+    #[test]
+    fn organization_quota_applies_to_members() {
+        let user = logged_user(vec![], None, &organizations(None));
+        assert_eq!(user.quota, quota(Some(2), Some(8.0)));
+        // Teams without quota keep their organization one
+        let user = logged_user(vec!["org/team"], None, &organizations(None));
+        assert_eq!(user.quota, quota(Some(2), Some(8.0)));
+    }
+
+    #[test]
+    fn team_quota_overrides_organization_quota_by_limit() {
+        let organizations = organizations(Some(quota(Some(1), None)));
+        let user = logged_user(vec!["org/team"], None, &organizations);
+        assert_eq!(user.quota, quota(Some(1), Some(8.0)));
+    }
+
+    #[test]
+    fn user_quota_wins() {
+        let user = User {
+            roles: Vec::new(),
+            pool_affinity: None,
+            quota: Some(quota(Some(10), None)),
+            organizations: Vec::new(),
+            teams: Vec::new(),
+        };
+        let user = logged_user(vec!["org/team"], Some(&user), &organizations(None));
+        assert_eq!(user.quota, quota(Some(10), None));
+    }
 }
@@ -0,0 +1,73 @@
+// Checks that interfaces and string unions of src/types.ts match the schemas of the backend OpenAPI document.
+// Usage: node scripts/check-types.js <openapi.json>
+const fs = require('fs');
+const path = require('path');
+
+const document = JSON.parse(fs.readFileSync(process.argv[2] || path.join(__dirname, '../../backend/openapi.json'), 'utf8'));
+const source = fs.readFileSync(path.join(__dirname, '../src/types.ts'), 'utf8');
+
+// Generic schemas are named after their parameter, e.g. `Page_for_Session` for `Page<Session>`
+function typeName(schema) {
+    return schema.split('_for_')[0];
+}
+
+// Inlined in `Page<T>`
+const inlined = ['Item'];
+
+function parseTypes(source) {
+    const interfaces = {};
+    const unions = {};
+    for (const [, name, body] of source.matchAll(/export interface (\w+)(?:<\w+>)?(?: extends \w+)? \{\n([\s\S]*?)\n\}/g)) {
+        interfaces[name] = {};
+        for (const [, field, optional] of body.matchAll(/^\s+(\w+)(\??):/gm)) {
+            interfaces[name][field] = optional === '?';
+        }
+    }
+    for (const [, name, values] of source.matchAll(/export type (\w+) = ((?:'[^']*'(?: \| )?)+);/g)) {
+        unions[name] = values.split(' | ').map(value => value.slice(1, -1));
+    }
+    return {interfaces, unions};
+}
+
+const {interfaces, unions} = parseTypes(source);
+const errors = [];
+for (const [name, schema] of Object.entries(document.components.schemas)) {
+    const type = typeName(name);
+    if (inlined.includes(type)) {
+        continue;
+    }
+    if (schema.enum) {
+        const values = unions[type];
+        if (!values) {
+            errors.push(`Missing type ${type}`);
+        } else if (values.join() != schema.enum.join()) {
+            errors.push(`${type} is ${values.join(' | ')} instead of ${schema.enum.join(' | ')}`);
+        }
+    } else if (schema.properties) {
+        const fields = interfaces[type];
+        if (!fields) {
+            errors.push(`Missing interface ${type}`);
+            continue;
+        }
+        const required = schema.required || [];
+        for (const [field, property] of Object.entries(schema.properties)) {
+            const optional = !required.includes(field);
+            if (!(field in fields)) {
+                errors.push(`Missing field ${type}.${field}`);
+            } else if (fields[field] != optional && !('default' in property)) {
+                // Fields with a default value can be typed either way, they are always returned
+                errors.push(`${type}.${field} must be ${optional ? 'optional' : 'required'}`);
+            }
+        }
+        for (const field of Object.keys(fields)) {
+            if (!(field in schema.properties)) {
+                errors.push(`Unknown field ${type}.${field}`);
+            }
+        }
+    }
+}
+
+if (errors.length > 0) {
+    console.error(`src/types.ts doesn't match the OpenAPI document:\n${errors.join('\n')}`);
+    process.exit(1);
+}
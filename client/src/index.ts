@@ -1,13 +1,20 @@
 import { fetchWithTimeout, rpc } from './rpc';
-import { Playground, Pool, Session, SessionConfiguration, SessionUpdateConfiguration, User, UserConfiguration, UserUpdateConfiguration, } from './types';
+import { ApiToken, ApiTokenConfiguration, AuditEntry, AuditFilter, CreatedApiToken, DrainConfiguration, HistoryFilter, Page, Playground, Pool, PoolQuery, Session, SessionConfiguration, SessionQuery, SessionRecord, SessionUpdateConfiguration, Template, TemplateImage, UsageGroup, UsageReport, User, UserConfiguration, UserQuery, UserUpdateConfiguration, } from './types';
 
 export class Client {
 
+    static version = 'v1';
     static userResource = 'user';
     static usersResource = 'users';
     static sessionResource = 'session';
     static sessionsResource = 'sessions';
+    static tokensResource = 'tokens';
     static poolsResource = 'pools';
+    static nodesResource = 'nodes';
+    static templatesResource = 'templates';
+    static auditResource = 'audit';
+    static historyResource = 'history';
+    static reportsResource = 'reports';
 
     private readonly base: string;
     private readonly timeout: number;
@@ -20,11 +27,11 @@ export class Client {
     }
 
     path(...resources: string[]): string {
-        return [this.base, ...resources].join("/");
+        return [this.base, Client.version, ...resources].join("/");
     }
 
-    loginPath(queryParams: string = window.location.search): string {
-        return this.path(`login/github${queryParams}`);
+    loginPath(queryParams: string = window.location.search, provider: string = 'github'): string {
+        return this.path(`login/${provider}${queryParams}`);
     }
 
     async get(init: RequestInit = this.defaultInit): Promise<Playground> {
@@ -45,8 +52,9 @@ export class Client {
         return rpc(this.path(Client.usersResource, id), init, this.timeout);
     }
 
-    async listUsers(init: RequestInit = this.defaultInit): Promise<Record<string, User>> {
-        return rpc(this.path(Client.usersResource), init, this.timeout);
+    async listUsers(query: UserQuery = {}, init: RequestInit = this.defaultInit): Promise<Page<User>> {
+        const params = new URLSearchParams(Object.entries(query).map(([key, value]) => [key, String(value)]));
+        return rpc(`${this.path(Client.usersResource)}?${params}`, init, this.timeout);
     }
 
     async createUser(id: string, conf: UserConfiguration, init: RequestInit = this.defaultInit): Promise<void> {
@@ -72,6 +80,38 @@ export class Client {
         }, this.timeout);
     }
 
+    // API Tokens
+
+    async listCurrentUserApiTokens(init: RequestInit = this.defaultInit): Promise<ApiToken[]> {
+        return rpc(this.path(Client.tokensResource), init, this.timeout);
+    }
+
+    async createCurrentUserApiToken(name: string, conf: ApiTokenConfiguration, init: RequestInit = this.defaultInit): Promise<CreatedApiToken> {
+        return rpc(this.path(Client.tokensResource, name), {
+            method: 'PUT',
+            body: JSON.stringify(conf),
+            ...init
+        }, this.timeout);
+    }
+
+    async deleteCurrentUserApiToken(name: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.tokensResource, name), {
+            method: 'DELETE',
+            ...init
+        }, this.timeout);
+    }
+
+    async listApiTokens(userId: string, init: RequestInit = this.defaultInit): Promise<ApiToken[]> {
+        return rpc(this.path(Client.usersResource, userId, Client.tokensResource), init, this.timeout);
+    }
+
+    async deleteApiToken(userId: string, name: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.usersResource, userId, Client.tokensResource, name), {
+            method: 'DELETE',
+            ...init
+        }, this.timeout);
+    }
+
     // Current Session
 
     async getCurrentSession(init: RequestInit = this.defaultInit): Promise<Session | null> {
@@ -101,10 +141,18 @@ export class Client {
         }, this.timeout);
     }
 
+    async migrateCurrentSession(init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.sessionResource, 'migrate'), {
+            method: 'PUT',
+            ...init
+        }, this.timeout);
+    }
+
     // Sessions
 
-    async listSessions(init: RequestInit = this.defaultInit): Promise<Record<string, Session>> {
-        return rpc(this.path(Client.sessionsResource), init, this.timeout);
+    async listSessions(query: SessionQuery = {}, init: RequestInit = this.defaultInit): Promise<Page<Session>> {
+        const params = new URLSearchParams(Object.entries(query).map(([key, value]) => [key, String(value)]));
+        return rpc(`${this.path(Client.sessionsResource)}?${params}`, init, this.timeout);
     }
 
     async createSession(id: string, conf: SessionConfiguration, init: RequestInit = this.defaultInit): Promise<void> {
@@ -130,14 +178,104 @@ export class Client {
         }, this.timeout);
     }
 
+    async migrateSession(id: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.sessionsResource, id, 'migrate'), {
+            method: 'PUT',
+            ...init
+        }, this.timeout);
+    }
+
     // Pools
 
     async getPool(id: string, init: RequestInit = this.defaultInit): Promise<Pool | null> {
         return rpc(this.path(Client.poolsResource, id), init, this.timeout);
     }
 
-    async listPools(init: RequestInit = this.defaultInit): Promise<Record<string, Pool>> {
-        return rpc(this.path(Client.poolsResource), init, this.timeout);
+    async listPools(query: PoolQuery = {}, init: RequestInit = this.defaultInit): Promise<Page<Pool>> {
+        const params = new URLSearchParams(Object.entries(query).map(([key, value]) => [key, String(value)]));
+        return rpc(`${this.path(Client.poolsResource)}?${params}`, init, this.timeout);
+    }
+
+    async cordonPool(id: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.poolsResource, id, 'cordon'), {
+            method: 'PUT',
+            ...init
+        }, this.timeout);
+    }
+
+    async uncordonPool(id: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.poolsResource, id, 'cordon'), {
+            method: 'DELETE',
+            ...init
+        }, this.timeout);
+    }
+
+    /* Returns the IDs of users whose session must be migrated */
+    async drainPool(id: string, conf: DrainConfiguration = {}, init: RequestInit = this.defaultInit): Promise<string[]> {
+        return rpc(this.path(Client.poolsResource, id, 'drain'), {
+            method: 'PUT',
+            body: JSON.stringify(conf),
+            ...init
+        }, this.timeout);
+    }
+
+    // Nodes
+
+    async cordonNode(name: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.nodesResource, name, 'cordon'), {
+            method: 'PUT',
+            ...init
+        }, this.timeout);
+    }
+
+    async uncordonNode(name: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.nodesResource, name, 'cordon'), {
+            method: 'DELETE',
+            ...init
+        }, this.timeout);
+    }
+
+    /* Returns the IDs of users whose session must be migrated */
+    async drainNode(name: string, conf: DrainConfiguration = {}, init: RequestInit = this.defaultInit): Promise<string[]> {
+        return rpc(this.path(Client.nodesResource, name, 'drain'), {
+            method: 'PUT',
+            body: JSON.stringify(conf),
+            ...init
+        }, this.timeout);
+    }
+
+    // Templates
+
+    async listTemplateImages(init: RequestInit = this.defaultInit): Promise<Record<string, TemplateImage>> {
+        return rpc(this.path(Client.templatesResource, 'images'), init, this.timeout);
+    }
+
+    async importTemplate(id: string, template: Template, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.templatesResource, id), {
+            method: 'PUT',
+            body: JSON.stringify(template),
+            ...init
+        }, this.timeout);
+    }
+
+    // Audit
+
+    async listAuditEntries(filter: AuditFilter = {}, init: RequestInit = this.defaultInit): Promise<AuditEntry[]> {
+        const params = new URLSearchParams(Object.entries(filter).map(([key, value]) => [key, String(value)]));
+        return rpc(`${this.path(Client.auditResource)}?${params}`, init, this.timeout);
+    }
+
+    // History
+
+    async listSessionHistory(filter: HistoryFilter = {}, init: RequestInit = this.defaultInit): Promise<SessionRecord[]> {
+        const params = new URLSearchParams(Object.entries(filter).map(([key, value]) => [key, String(value)]));
+        return rpc(`${this.path(Client.historyResource)}?${params}`, init, this.timeout);
+    }
+
+    async getUsageReport(group: UsageGroup, filter: HistoryFilter = {}, init: RequestInit = this.defaultInit): Promise<Record<string, UsageReport>> {
+        const params = new URLSearchParams(Object.entries(filter).map(([key, value]) => [key, String(value)]));
+        params.set('group', group);
+        return rpc(`${this.path(Client.reportsResource, 'usage')}?${params}`, init, this.timeout);
     }
 
     // Login
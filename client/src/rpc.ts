@@ -28,37 +28,56 @@ export async function fetchWithTimeout(input: RequestInfo, init: RequestInit, ti
     return response;
 }
 
+export type ApiErrorCode = 'unauthenticated' | 'permission_denied' | 'quota_exceeded' | 'not_found' | 'already_exists' | 'invalid_value' | 'unavailable' | 'internal';
+
+export class ApiError extends Error {
+    readonly status: number;
+    readonly code: ApiErrorCode;
+    readonly details?: Record<string, string>;
+    readonly requestId?: string;
+
+    constructor(status: number, {code, message, details, requestId}: {code: ApiErrorCode, message: string, details?: Record<string, string>, requestId?: string}) {
+        // Include the request ID so that errors can be matched with backend logs
+        super(requestId ? `${message} (request ${requestId})` : message);
+        this.status = status;
+        this.code = code;
+        this.details = details;
+        this.requestId = requestId;
+    }
+}
+
 async function call<T>(input: RequestInfo, init: RequestInit, timeout: number): Promise<T> {
+    let response;
     try {
         const controller = new AbortController();
         const id = setTimeout(() => controller.abort(), timeout);
-        const response = await fetch(input, {
+        response = await fetch(input, {
           ...init,
           signal: controller.signal
         });
         clearTimeout(id);
-        if (response.ok) {
-            // TODO check content-type
-            try {
-                const { result, error } = await response.json();
-                if (error) {
-                    return Promise.reject(error);
-                } else {
-                    return Promise.resolve(result);
-                }
-            } catch (e) {
-                // Failed to parse as JSON
-                return Promise.reject(new RpcError(RpcErrorCode.PARSE_ERROR, response.statusText));
-            }
-        } else {
-            if (response.status == 401) {
-                return Promise.reject(new RpcError(RpcErrorCode.INVALID_REQUEST, 'User unauthorized'));
-            }
-            return Promise.reject(new RpcError(RpcErrorCode.SERVER_ERROR, response.statusText));
-        }
     } catch (e) {
         return Promise.reject(new RpcError(RpcErrorCode.TIMEOUT_ERROR, 'Failed to fetch'));
     }
+    // TODO check content-type
+    let body;
+    try {
+        body = await response.json();
+    } catch (e) {
+        // Failed to parse as JSON
+        if (response.status == 401) {
+            return Promise.reject(new RpcError(RpcErrorCode.INVALID_REQUEST, 'User unauthorized'));
+        }
+        return Promise.reject(new RpcError(response.ok ? RpcErrorCode.PARSE_ERROR : RpcErrorCode.SERVER_ERROR, response.statusText));
+    }
+    const { result, error } = body;
+    if (error) {
+        return Promise.reject(new ApiError(response.status, error));
+    } else if (!response.ok) {
+        return Promise.reject(new RpcError(RpcErrorCode.SERVER_ERROR, response.statusText));
+    } else {
+        return Promise.resolve(result);
+    }
 }
 
 export async function rpc<T>(input: string, init: RequestInit, timeout: number): Promise<T> {
@@ -3,6 +3,7 @@ export interface Playground {
     configuration: Configuration,
     templates: Record<string, Template>,
     user?: LoggedUser,
+    quota?: QuotaStatus,
 }
 
 export interface Environment {
@@ -12,67 +13,273 @@ export interface Environment {
 }
 
 export interface Configuration {
-    githubClientId: string,
+    identityProvider: IdentityProvider,
+    clientId: string,
+    /* Only set when GitHub is the identity provider */
+    githubClientId?: string,
     session: SessionDefaults,
 }
 
+export type IdentityProvider = {type: 'github'} | {type: 'gitlab', url: string} | {type: 'oidc', issuerUrl: string};
+
 export interface SessionDefaults {
     /* The default number of minutes sessions can last */
     duration: number,
     maxDuration: number,
     poolAffinity: string,
-    maxSessionsPerPod: string,
+    maxSessionsPerPod: number,
+    /* How nodes are picked within a pool */
+    placement: PlacementStrategy,
+    /* Applies to users without user or organization quota */
+    quota: Quota,
+}
+
+export type PlacementStrategy = 'bin-pack' | 'spread' | 'prefer-image';
+
+/* Limits on sessions created by a user. Missing values mean unlimited */
+export interface Quota {
+    maxConcurrentSessions?: number,
+    maxSessionHoursPerDay?: number,
+    maxSessionHoursPerWeek?: number,
+    allowedTemplates?: string[],
+}
+
+export interface Usage {
+    concurrentSessions: number,
+    sessionHoursLastDay: number,
+    sessionHoursLastWeek: number,
+}
+
+export interface Remaining {
+    concurrentSessions?: number,
+    sessionHoursPerDay?: number,
+    sessionHoursPerWeek?: number,
 }
 
+export interface QuotaStatus {
+    quota: Quota,
+    usage: Usage,
+    remaining: Remaining,
+}
+
+export type Permission = 'SessionRead' | 'SessionWrite' | 'SessionReadAny' | 'SessionWriteAny' | 'UserRead' | 'UserEdit' | 'TemplateEdit' | 'PoolRead' | 'PoolEdit' | 'CustomizeDuration' | 'CustomizePoolAffinity' | 'AuditRead' | 'ReportRead';
+
 export interface LoggedUser {
     id: string,
-    admin: boolean,
     organizations: string[],
-    poolAffinity: string,
-    canCustomizeDuration: boolean,
-    canCustomizePoolAffinity: boolean,
+    /* Teams, as `org/team` */
+    teams: string[],
+    poolAffinity?: string,
+    /* Roles granted directly or via organizations and teams */
+    roles: string[],
+    permissions: Permission[],
+    quota: Quota,
 }
 
 export interface User {
-    admin: boolean,
-    poolAffinity: string,
-    canCustomizeDuration: boolean,
-    canCustomizePoolAffinity: boolean,
+    roles: string[],
+    poolAffinity?: string,
+    quota?: Quota,
+    /* Organizations this user belonged to when last logged in */
+    organizations: string[],
+    /* Teams, as `org/team`, this user belonged to when last logged in */
+    teams: string[],
 }
 
 export interface UserConfiguration {
-    admin: boolean,
+    roles: string[],
     poolAffinity?: string,
-    canCustomizeDuration: boolean,
-    canCustomizePoolAffinity: boolean,
+    quota?: Quota,
 }
 
 export interface UserUpdateConfiguration {
-    admin: boolean,
+    roles: string[],
     poolAffinity?: string,
-    canCustomizeDuration: boolean,
-    canCustomizePoolAffinity: boolean,
+    quota?: Quota,
+}
+
+export interface ApiToken {
+    name: string,
+    userId: string,
+    scopes: Permission[],
+    organizations: string[],
+    teams: string[],
+    /* Seconds since UNIX epoch */
+    createdAt: number,
+    expiresAt: number,
+}
+
+export interface ApiTokenConfiguration {
+    scopes: Permission[],
+    /* The number of minutes this token is valid */
+    duration?: number,
+}
+
+export interface CreatedApiToken {
+    /* Only returned once, must be provided as `Authorization: Bearer` header */
+    token: string,
+    expiresAt: number,
+}
+
+export type AuditAction = 'Login' | 'Logout' | 'CreateUser' | 'UpdateUser' | 'DeleteUser' | 'CreateApiToken' | 'DeleteApiToken' | 'CreateSession' | 'UpdateSession' | 'DeleteSession' | 'MigrateSession' | 'CordonPool' | 'UncordonPool' | 'DrainPool' | 'CordonNode' | 'UncordonNode' | 'DrainNode' | 'ImportTemplate';
+
+export interface AuditEntry {
+    /* Seconds since UNIX epoch */
+    timestamp: number,
+    actor: string,
+    action: AuditAction,
+    target: string,
+    parameters: any,
+    success: boolean,
+    error?: string,
+}
+
+export interface AuditFilter {
+    actor?: string,
+    action?: AuditAction,
+    target?: string,
+    success?: boolean,
+    since?: number,
+    until?: number,
+    limit?: number,
 }
 
 export interface Session {
     userId: string,
+    /* ID of the user who created this session */
+    creator: string,
+    /* ID of `template`, as used to create this session */
+    templateId: string,
     url: string,
     template: Template,
+    /* Organizations of its owner when created */
+    organizations: string[],
     pod: Pod,
     /* The number of minutes this session can last */
     duration: number,
-    maxDuration: number,
     node: string,
+    /* Pool this session has been deployed on */
+    pool?: string,
+    /* Set when its node is drained. Seconds since UNIX epoch after which this session is deleted unless migrated */
+    drainDeadline?: number,
+}
+
+export type SessionEndReason = 'Expired' | 'User' | 'Admin' | 'Failure' | 'Drained' | 'Migrated';
+
+export interface SessionRecord {
+    id: string,
+    userId: string,
+    creator: string,
+    /* Template ID */
+    template: string,
+    pool?: string,
+    node: string,
+    /* Seconds since UNIX epoch */
+    start: number,
+    /* Seconds since UNIX epoch */
+    end: number,
+    endReason: SessionEndReason,
+    /* Seconds */
+    deployDuration?: number,
+}
+
+export interface HistoryFilter {
+    user?: string,
+    template?: string,
+    pool?: string,
+    since?: number,
+    until?: number,
+}
+
+export type UsageGroup = 'user' | 'template' | 'pool';
+
+/* A page of list results */
+export interface Page<T> {
+    items: Array<{id: string} & T>,
+    /* Number of items matching the filters, across all pages */
+    total: number,
+    /* To be provided as `cursor` to get the next page. Not set on the last page */
+    nextCursor?: string,
+}
+
+export interface PageQuery {
+    /* `id` or a resource field, prefixed with `-` for descending order */
+    sort?: string,
+    cursor?: string,
+    limit?: number,
+}
+
+export interface SessionQuery extends PageQuery {
+    template?: string,
+    phase?: Phase,
+    pool?: string,
+    owner?: string,
+    /* Organization the owner belonged to when the session was created */
+    org?: string,
+}
+
+export interface UserQuery extends PageQuery {
+    role?: string,
+    pool?: string,
+    /* Organization the user belonged to when last logged in */
+    org?: string,
+}
+
+export interface PoolQuery extends PageQuery {
+    cordoned?: boolean,
+}
+
+export interface UsageReport {
+    sessions: number,
+    sessionHours: number,
+    failures: number,
+    /* Seconds */
+    averageDeployDuration?: number,
 }
 
 export interface Pool {
     name: string,
     instanceType?: string,
     nodes: Node[],
+    /* Only set for pools that are automatically scaled */
+    scaling?: PoolScaling,
+    /* New sessions can't be created on cordoned pools */
+    cordoned: boolean,
+    /* Sum of all nodes allocatable resources */
+    allocatable: Resources,
+    /* Sum of all nodes used resources */
+    used: Resources,
+}
+
+export interface DrainConfiguration {
+    /* The number of minutes left to sessions owners to migrate */
+    gracePeriod?: number,
+}
+
+export interface PoolScaling {
+    minNodes: number,
+    maxNodes: number,
 }
 
 export interface Node {
     hostname: string,
+    allocatable: Resources,
+    /* Requested by running and pending sessions */
+    used: Resources,
+    /* Number of running and pending sessions */
+    sessions: number,
+    /* New sessions can't be scheduled on cordoned nodes */
+    cordoned: boolean,
+    ready: boolean,
+}
+
+export interface Resources {
+    /* Millicores */
+    cpu: number,
+    /* Bytes */
+    memory: number,
+    /* Bytes */
+    storage: number,
 }
 
 export interface SessionConfiguration {
@@ -113,6 +320,14 @@ export interface Template {
     runtime?: RuntimeConfiguration,
 }
 
+export interface TemplateImage {
+    image: string,
+    /* Hostnames of the nodes where the image is already pulled, per pool */
+    pools: Record<string, string[]>,
+    /* True if the image is pulled on no node at all */
+    missing: boolean,
+}
+
 export type Phase = 'Pending' | 'Running' | 'Succeeded' | 'Failed' | 'Unknown';
 export interface Pod {
     phase: Phase,
@@ -121,6 +336,8 @@ export interface Pod {
     /* The number of seconds since this session started */
     startTime?: number,
     container?: ContainerStatus,
+    /* The number of seconds between the pod creation and its container start */
+    deployDuration?: number,
 }
 
 export type ContainerPhase = 'Running' | 'Terminated' | 'Waiting' | 'Unknown';
@@ -182,7 +182,7 @@ function Nav({ conf, onPlayground, onStatsClick, onAdminClick, onLogout, user, c
                          color="inherit"
                          size="small"
                      >
-                         <Avatar alt={user.id} src={`https://github.com/${user.id}.png`} />
+                         <Avatar alt={user.id} src={conf.githubClientId && `https://github.com/${user.id}.png`} />
                      </IconButton>
                      <Menu
                          id="menu-appbar"
@@ -200,7 +200,8 @@ function Nav({ conf, onPlayground, onStatsClick, onAdminClick, onLogout, user, c
                          onClose={handleClose}
                      >
                          <MenuItem onClick={() => window.open("https://github.com/paritytech/substrate-playground/discussions")}>FEEDBACK</MenuItem>
-                         <MenuItem onClick={() => window.open(`https://github.com/settings/connections/applications/${conf.githubClientId}`)}>GITHUB APPLICATION</MenuItem>
+                         {conf.githubClientId &&
+                         <MenuItem onClick={() => window.open(`https://github.com/settings/connections/applications/${conf.githubClientId}`)}>GITHUB APPLICATION</MenuItem>}
 
                          <MenuItem onClick={async () => {handleClose(); onLogout()}}>LOGOUT</MenuItem>
                      </Menu>
@@ -7,7 +7,7 @@ import simpleAnalyticsPlugin from "analytics-plugin-simple-analytics";
 import Button from "@material-ui/core/Button";
 import Typography from "@material-ui/core/Typography";
 import { useMachine } from '@xstate/react';
-import { CenteredContainer, ErrorMessage, LoadingPanel, Wrapper } from './components';
+import { CenteredContainer, ErrorMessage, ErrorSnackbar, LoadingPanel, Wrapper } from './components';
 import { useInterval } from "./hooks";
 import { newMachine, Events, PanelId, States } from './lifecycle';
 import { AdminPanel } from './panels/admin';
@@ -41,10 +41,18 @@ function MainPanel({ client, conf, user, id, templates, restartAction, onConnect
     }
 }
 
-function ExtraTheiaNav({ session, restartAction }: { session: Session | null | undefined, restartAction: () => void }): JSX.Element {
+function ExtraTheiaNav({ session, restartAction, migrateAction }: { session: Session | null | undefined, restartAction: () => void, migrateAction: () => void }): JSX.Element {
     if (session) {
-        const { pod, duration } = session;
+        const { pod, duration, drainDeadline } = session;
         if (pod.phase == 'Running') {
+            if (drainDeadline) {
+                const minutes = Math.max(0, Math.ceil((drainDeadline - Date.now() / 1000) / 60));
+                return (
+                    <Typography variant="h6">
+                        Your session's node is under maintenance and will end in {minutes} minutes. Export your changes, then <Button onClick={migrateAction}>move it</Button>
+                    </Typography>
+                );
+            }
             const remaining = duration * 60 - (pod.startTime || 0);
             if (remaining < 300) { // 5 minutes
                 return (
@@ -66,6 +74,7 @@ function ExtraTheiaNav({ session, restartAction }: { session: Session | null | u
 
 function WrappedTheiaPanel({ params, conf, client, user, templates, selectPanel, restartAction, send }: { params: Params, client: Client, conf: Configuration, user: LoggedUser, templates: Record<string, Template>, selectPanel: (id: PanelId) => void, restartAction: () => void, send: (event: Events) => void }): JSX.Element {
     const [session, setSession] = useState<Session | null | undefined>(undefined);
+    const [errorMessage, setErrorMessage] = useState<string | null>(null);
 
     useInterval(async () => {
         const session = await client.getCurrentSession();
@@ -86,9 +95,21 @@ function WrappedTheiaPanel({ params, conf, client, user, templates, selectPanel,
         }
     }, 5000);
 
+    const migrateAction = async () => {
+        try {
+            await client.migrateCurrentSession();
+            restartAction();
+        } catch (e) {
+            console.error(e);
+            setErrorMessage(`Failed to move the session: ${e}`);
+        }
+    };
+
     return (
-        <Wrapper conf={conf} extraNav={<ExtraTheiaNav session={session} restartAction={restartAction} />} params={params} thin={true} onPlayground={() => selectPanel(PanelId.Session)} onAdminClick={() => selectPanel(PanelId.Admin)} onStatsClick={() => selectPanel(PanelId.Stats)} onLogout={() => send(Events.LOGOUT)} user={user}>
+        <Wrapper conf={conf} extraNav={<ExtraTheiaNav session={session} restartAction={restartAction} migrateAction={migrateAction} />} params={params} thin={true} onPlayground={() => selectPanel(PanelId.Session)} onAdminClick={() => selectPanel(PanelId.Admin)} onStatsClick={() => selectPanel(PanelId.Stats)} onLogout={() => send(Events.LOGOUT)} user={user}>
             <TheiaPanel client={client} autoDeploy={params.deploy} templates={templates} onMissingSession={restartAction} onSessionFailing={restartAction} onSessionTimeout={restartAction} />
+            {errorMessage &&
+            <ErrorSnackbar open={true} message={errorMessage} onClose={() => setErrorMessage(null)} />}
         </Wrapper>
     );
 }
@@ -130,7 +151,7 @@ function App({ params }: { params: Params }): JSX.Element {
                         ? <CenteredContainer>
                             <ErrorMessage reason={error} action={restartAction} />
                         </CenteredContainer>
-                        : <LoginPanel client={client} />
+                        : <LoginPanel client={client} conf={conf} />
                         : <LoadingPanel />}
                 </Wrapper>}
             </div>
@@ -8,7 +8,6 @@ import Container from '@material-ui/core/Container';
 import Dialog from '@material-ui/core/Dialog';
 import DialogContent from '@material-ui/core/DialogContent';
 import DialogTitle from '@material-ui/core/DialogTitle';
-import FormControlLabel from '@material-ui/core/FormControlLabel';
 import IconButton from '@material-ui/core/IconButton';
 import AddIcon from '@material-ui/icons/Add';
 import DeleteIcon from '@material-ui/icons/Delete';
@@ -37,7 +36,7 @@ import LastPageIcon from '@material-ui/icons/LastPage';
 import TableFooter from '@material-ui/core/TableFooter';
 import TablePagination from '@material-ui/core/TablePagination';
 import { Autocomplete } from '@material-ui/lab';
-import { Client, Configuration, LoggedUser, Pool, Session, SessionConfiguration, SessionUpdateConfiguration, Template, User, UserConfiguration, UserUpdateConfiguration } from '@substrate/playground-client';
+import { Client, Configuration, LoggedUser, Node, Page, PageQuery, Pool, Resources as NodeResources, Session, SessionConfiguration, SessionUpdateConfiguration, Template, User, UserConfiguration, UserUpdateConfiguration } from '@substrate/playground-client';
 import { CenteredContainer, ErrorSnackbar, LoadingPanel } from '../components';
 import { useInterval } from '../hooks';
 import { canCustomizeDuration, canCustomizePoolAffinity, hasAdminEditRights } from '../utils';
@@ -65,6 +64,18 @@ function NoResourcesContainer({ user, label, action }: { user: LoggedUser, label
     );
 }
 
+// Follows cursors until all pages are fetched
+async function listAll<T>(list: (query: PageQuery) => Promise<Page<T>>): Promise<Record<string, T>> {
+    const resources: Record<string, T> = {};
+    let cursor: string | undefined = undefined;
+    do {
+        const page: Page<T> = await list({cursor: cursor, limit: 500});
+        page.items.forEach(({ id, ...resource }) => resources[id] = resource as unknown as T);
+        cursor = page.nextCursor;
+    } while (cursor);
+    return resources;
+}
+
 function Resources<T>( { children, callback }: { children: (resources: Record<string, T>, setter: Dispatch<SetStateAction<Record<string, T> | null>>) => NonNullable<React.ReactNode>, callback: () => Promise<Record<string, T>> }): JSX.Element {
     const [resources, setResources] = useState<Record<string, T> | null>(null);
 
@@ -101,9 +112,9 @@ export function SessionCreationDialog({ client, conf, sessions, user, template,
     const [users, setUsers] = useState<Record<string, User> | null>(null);
 
     useInterval(async () => {
-        setPools(await client.listPools());
+        setPools(await listAll(query => client.listPools(query)));
         if (allowUserSelection) {
-            setUsers(await client.listUsers());
+            setUsers(await listAll(query => client.listUsers(query)));
         }
     }, 5000);
 
@@ -331,9 +342,11 @@ function Sessions({ client, conf, user }: { client: Client, conf: Configuration,
     function sessionMock(conf: SessionConfiguration): Session {
         return {
             duration: conf.duration || 0,
-            maxDuration: 0,
+            templateId: conf.template,
             template: {name: "", image: "", description: ""},
+            organizations: [],
             userId: "",
+            creator: "",
             url: "",
             pod: {phase: 'Pending', reason: "", message: ""},
             node: ""
@@ -411,7 +424,7 @@ function Sessions({ client, conf, user }: { client: Client, conf: Configuration,
     const stopPropagation = (event: React.SyntheticEvent) => event.stopPropagation();
 
     return (
-        <Resources<Session> callback={async () => await client.listSessions()}>
+        <Resources<Session> callback={async () => await listAll(query => client.listSessions(query))}>
             {(resources: Record<string, Session>, setSessions: Dispatch<SetStateAction<Record<string, Session> | null>>) => {
                 const allResources = Object.entries(resources);
                 const filteredResources = rowsPerPage > 0 ? allResources.slice(page * rowsPerPage, page * rowsPerPage + rowsPerPage) : allResources;
@@ -640,23 +653,23 @@ function EnhancedTableToolbar({ user, label, selected = null, onCreate, onUpdate
     );
 }
 
+function parseRoles(roles: string): string[] {
+    return roles.split(",").map(role => role.trim()).filter(role => role.length > 0);
+}
+
 function UserCreationDialog({ client, conf, users, show, onCreate, onHide }: { client: Client, conf: Configuration, users: Record<string, User>, show: boolean, onCreate: (id: string, conf: UserConfiguration) => void, onHide: () => void }): JSX.Element {
     const [id, setID] = React.useState('');
-    const [adminChecked, setAdminChecked] = React.useState(false);
+    const [roles, setRoles] = React.useState('');
     const [poolAffinity, setPoolAffinity] = React.useState<string>(conf.session.poolAffinity);
-    const [customizeDurationChecked, setCustomizeDurationChecked] = React.useState(false);
-    const [customizePoolAffinityChecked, setCustomizePoolAffinityChecked] = React.useState(false);
     const [pools, setPools] = useState<Record<string, Pool> | null>(null);
 
     useInterval(async () => {
-        setPools(await client.listPools());
+        setPools(await listAll(query => client.listPools(query)));
     }, 5000);
 
     const handleIDChange = (event: React.ChangeEvent<HTMLInputElement>) => setID(event.target.value);
-    const handleAdminChange = (event: React.ChangeEvent<HTMLInputElement>) => setAdminChecked(event.target.checked);
+    const handleRolesChange = (event: React.ChangeEvent<HTMLInputElement>) => setRoles(event.target.value);
     const handlePoolAffinityChange = (event: React.ChangeEvent<HTMLInputElement>) => setPoolAffinity(event.target.value);
-    const handleCustomizeDurationChange = (event: React.ChangeEvent<HTMLInputElement>) => setCustomizeDurationChecked(event.target.checked);
-    const handleCustomizePoolAffinityChange = (event: React.ChangeEvent<HTMLInputElement>) => setCustomizePoolAffinityChecked(event.target.checked);
     return (
         <Dialog open={show} onClose={onHide} maxWidth="md">
             <DialogTitle>User details</DialogTitle>
@@ -670,15 +683,12 @@ function UserCreationDialog({ client, conf, users, show, onCreate, onHide }: { c
                         label="GitHub ID"
                         autoFocus
                         />
-                    <FormControlLabel
-                        control={
-                            <Checkbox
-                                checked={adminChecked}
-                                onChange={handleAdminChange}
-                                />
-                        }
-                        label="Is admin"
-                    />
+                    <TextField
+                        style={{marginBottom: 20}}
+                        value={roles}
+                        onChange={handleRolesChange}
+                        label="Roles (comma separated)"
+                        />
                    <TextField
                         style={{marginBottom: 20}}
                         select
@@ -695,26 +705,8 @@ function UserCreationDialog({ client, conf, users, show, onCreate, onHide }: { c
                         </MenuItem>))
                     }
                     </TextField>
-                    <FormControlLabel
-                        control={
-                            <Checkbox
-                                checked={customizeDurationChecked}
-                                onChange={handleCustomizeDurationChange}
-                                />
-                        }
-                        label="Can Customize duration"
-                    />
-                    <FormControlLabel
-                        control={
-                            <Checkbox
-                                checked={customizePoolAffinityChecked}
-                                onChange={handleCustomizePoolAffinityChange}
-                                />
-                        }
-                        label="Can Customize pool affinity"
-                    />
                     <ButtonGroup style={{alignSelf: "flex-end", marginTop: 20}} size="small">
-                        <Button disabled={!id || users[id] != null || !poolAffinity} onClick={() => {onCreate(id.toLowerCase(), {admin: adminChecked, poolAffinity: poolAffinity, canCustomizeDuration: customizeDurationChecked, canCustomizePoolAffinity: customizePoolAffinityChecked}); onHide();}}>CREATE</Button>
+                        <Button disabled={!id || users[id] != null || !poolAffinity} onClick={() => {onCreate(id.toLowerCase(), {roles: parseRoles(roles), poolAffinity: poolAffinity}); onHide();}}>CREATE</Button>
                         <Button onClick={onHide}>CLOSE</Button>
                     </ButtonGroup>
                 </Container>
@@ -724,34 +716,27 @@ function UserCreationDialog({ client, conf, users, show, onCreate, onHide }: { c
 }
 
 function UserUpdateDialog({ client, id, user, show, onUpdate, onHide }: { client: Client, id: string, user: User, show: boolean, onUpdate: (id: string, conf: UserUpdateConfiguration) => void, onHide: () => void }): JSX.Element {
-    const [adminChecked, setAdminChecked] = React.useState(user.admin);
+    const [roles, setRoles] = React.useState(user.roles.join(", "));
     const [poolAffinity, setPoolAffinity] = React.useState(user.poolAffinity);
-    const [customizeDurationChecked, setCustomizeDurationChecked] = React.useState(user.canCustomizeDuration);
-    const [customizePoolAffinityChecked, setCustomizePoolAffinityChecked] = React.useState(user.canCustomizePoolAffinity);
     const [pools, setPools] = useState<Record<string, Pool> | null>(null);
 
     useInterval(async () => {
-        setPools(await client.listPools());
+        setPools(await listAll(query => client.listPools(query)));
     }, 5000);
 
-    const handleAdminChange = (event: React.ChangeEvent<HTMLInputElement>) => setAdminChecked(event.target.checked);
+    const handleRolesChange = (event: React.ChangeEvent<HTMLInputElement>) => setRoles(event.target.value);
     const handlePoolAffinityChange = (event: React.ChangeEvent<HTMLInputElement>) => setPoolAffinity(event.target.value);
-    const handleCustomizeDurationChange = (event: React.ChangeEvent<HTMLInputElement>) => setCustomizeDurationChecked(event.target.checked);
-    const handleCustomizePoolAffinityChange = (event: React.ChangeEvent<HTMLInputElement>) => setCustomizePoolAffinityChecked(event.target.checked);
     return (
         <Dialog open={show} onClose={onHide} maxWidth="md">
             <DialogTitle>User details</DialogTitle>
             <DialogContent>
                 <Container style={{display: "flex", flexDirection: "column"}}>
-                    <FormControlLabel
-                        control={
-                            <Checkbox
-                                checked={adminChecked}
-                                onChange={handleAdminChange}
-                                />
-                        }
-                        label="Is admin"
-                    />
+                    <TextField
+                        style={{marginBottom: 20}}
+                        value={roles}
+                        onChange={handleRolesChange}
+                        label="Roles (comma separated)"
+                        />
                   <TextField
                         style={{marginBottom: 20}}
                         select
@@ -768,26 +753,8 @@ function UserUpdateDialog({ client, id, user, show, onUpdate, onHide }: { client
                         </MenuItem>))
                     }
                     </TextField>
-                    <FormControlLabel
-                        control={
-                            <Checkbox
-                                checked={customizeDurationChecked}
-                                onChange={handleCustomizeDurationChange}
-                                />
-                        }
-                        label="Can Customize duration"
-                    />
-                    <FormControlLabel
-                        control={
-                            <Checkbox
-                                checked={customizePoolAffinityChecked}
-                                onChange={handleCustomizePoolAffinityChange}
-                                />
-                        }
-                        label="Can Customize pool affinity"
-                    />
                     <ButtonGroup style={{alignSelf: "flex-end", marginTop: 20}} size="small">
-                        <Button disabled={ adminChecked == user.admin && poolAffinity == user.poolAffinity && customizeDurationChecked == user.canCustomizeDuration && customizePoolAffinityChecked == user.canCustomizePoolAffinity } onClick={() => {onUpdate(id.toLowerCase(), {admin: adminChecked, poolAffinity: poolAffinity, canCustomizeDuration: customizeDurationChecked, canCustomizePoolAffinity: customizePoolAffinityChecked}); onHide();}}>UPDATE</Button>
+                        <Button disabled={ parseRoles(roles).join() == user.roles.join() && poolAffinity == user.poolAffinity } onClick={() => {onUpdate(id.toLowerCase(), {roles: parseRoles(roles), poolAffinity: poolAffinity}); onHide();}}>UPDATE</Button>
                         <Button onClick={onHide}>CLOSE</Button>
                     </ButtonGroup>
                 </Container>
@@ -827,7 +794,7 @@ function Users({ client, user, conf }: { client: Client, user: LoggedUser, conf:
     }
 
     function updatedUserMock(conf: UserUpdateConfiguration, user?: User): User {
-        return {admin: conf.admin, poolAffinity: user?.poolAffinity || "", canCustomizeDuration: conf.canCustomizeDuration, canCustomizePoolAffinity: user?.canCustomizePoolAffinity || false};
+        return {roles: conf.roles, poolAffinity: conf.poolAffinity || user?.poolAffinity, organizations: user?.organizations || [], teams: user?.teams || []};
     }
 
     async function onUpdate(id: string, conf: UserUpdateConfiguration, setUsers: Dispatch<SetStateAction<Record<string, User> | null>>): Promise<void> {
@@ -867,7 +834,7 @@ function Users({ client, user, conf }: { client: Client, user: LoggedUser, conf:
     }
 
     return (
-        <Resources<User> callback={async () => await client.listUsers()}>
+        <Resources<User> callback={async () => await listAll(query => client.listUsers(query))}>
         {(resources: Record<string, User>, setUsers: Dispatch<SetStateAction<Record<string, User> | null>>) => (
             <>
                 <EnhancedTableToolbar user={user} label="Users" selected={selected} onCreate={() => setShowCreationDialog(true)} onUpdate={() => setShowUpdateDialog(true)} onDelete={() => onDelete(setUsers)} />
@@ -878,9 +845,7 @@ function Users({ client, user, conf }: { client: Client, user: LoggedUser, conf:
                                 <TableCell></TableCell>
                                 <TableCell>ID</TableCell>
                                 <TableCell>Pool Affinity</TableCell>
-                                <TableCell>Admin</TableCell>
-                                <TableCell>Can Customize Duration</TableCell>
-                                <TableCell>Can Customize Pool Affinity</TableCell>
+                                <TableCell>Roles</TableCell>
                             </TableRow>
                         </TableHead>
                         <TableBody>
@@ -906,9 +871,7 @@ function Users({ client, user, conf }: { client: Client, user: LoggedUser, conf:
                                         {id}
                                     </TableCell>
                                     <TableCell>{user.poolAffinity}</TableCell>
-                                    <TableCell>{user.admin.toString()}</TableCell>
-                                    <TableCell>{user.canCustomizeDuration.toString()}</TableCell>
-                                    <TableCell>{user.canCustomizePoolAffinity.toString()}</TableCell>
+                                    <TableCell>{user.roles.join(", ")}</TableCell>
                                 </TableRow>
                                 )})}
                         </TableBody>
@@ -962,11 +925,44 @@ function DetailsPanel({ conf }: { conf: Configuration }): JSX.Element {
     );
 }
 
-function Pools({ client, user }: { client: Client, user: LoggedUser }): JSX.Element {
+// Nodes using more than this ratio of any resource are considered saturated
+const SATURATION_THRESHOLD = 0.9;
+
+function formatCpu(millicores: number): string {
+    return `${(millicores / 1000).toFixed(1)}`;
+}
+
+function formatBytes(bytes: number): string {
+    return `${(bytes / 1024 ** 3).toFixed(1)}Gi`;
+}
+
+function formatUsage(used: number, allocatable: number, format: (value: number) => string): string {
+    const percent = allocatable > 0 ? Math.round(used / allocatable * 100) : 0;
+    return `${format(used)} / ${format(allocatable)} (${percent}%)`;
+}
+
+function isSaturated(used: NodeResources, allocatable: NodeResources): boolean {
+    return (['cpu', 'memory', 'storage'] as const).some(resource => allocatable[resource] > 0 && used[resource] / allocatable[resource] >= SATURATION_THRESHOLD);
+}
+
+function nodeStatus(node: Node, maxSessionsPerNode: number): string {
+    if (!node.ready) {
+        return "Not ready";
+    } else if (node.cordoned) {
+        return "Cordoned";
+    } else if (isSaturated(node.used, node.allocatable) || node.sessions >= maxSessionsPerNode) {
+        return "Saturated";
+    } else {
+        return "Ready";
+    }
+}
+
+function Pools({ client, user, conf }: { client: Client, user: LoggedUser, conf: Configuration }): JSX.Element {
     const classes = useStyles();
+    const maxSessionsPerNode = conf.session.maxSessionsPerPod;
 
     return (
-        <Resources<Pool> callback={async () => await client.listPools()}>
+        <Resources<Pool> callback={async () => await listAll(query => client.listPools(query))}>
         {(resources: Record<string, Pool>) => (
             <>
                 <EnhancedTableToolbar user={user} label="Pools" />
@@ -977,6 +973,9 @@ function Pools({ client, user }: { client: Client, user: LoggedUser }): JSX.Elem
                                 <TableCell>Name</TableCell>
                                 <TableCell>Instance type</TableCell>
                                 <TableCell># of nodes</TableCell>
+                                <TableCell>CPU</TableCell>
+                                <TableCell>Memory</TableCell>
+                                <TableCell>Storage</TableCell>
                             </TableRow>
                         </TableHead>
                         <TableBody>
@@ -985,11 +984,48 @@ function Pools({ client, user }: { client: Client, user: LoggedUser }): JSX.Elem
                             <TableCell>{pool.name}</TableCell>
                             <TableCell>{pool.instanceType}</TableCell>
                             <TableCell>{pool.nodes.length}</TableCell>
+                            <TableCell>{formatUsage(pool.used.cpu, pool.allocatable.cpu, formatCpu)}</TableCell>
+                            <TableCell>{formatUsage(pool.used.memory, pool.allocatable.memory, formatBytes)}</TableCell>
+                            <TableCell>{formatUsage(pool.used.storage, pool.allocatable.storage, formatBytes)}</TableCell>
                         </TableRow>
                         ))}
                         </TableBody>
                     </Table>
                 </TableContainer>
+                <EnhancedTableToolbar user={user} label="Nodes" />
+                <TableContainer component={Paper}>
+                    <Table className={classes.table} aria-label="simple table">
+                        <TableHead>
+                            <TableRow>
+                                <TableCell>Pool</TableCell>
+                                <TableCell>Hostname</TableCell>
+                                <TableCell>CPU</TableCell>
+                                <TableCell>Memory</TableCell>
+                                <TableCell>Storage</TableCell>
+                                <TableCell># of sessions</TableCell>
+                                <TableCell>Status</TableCell>
+                            </TableRow>
+                        </TableHead>
+                        <TableBody>
+                        {Object.entries(resources).flatMap(([id, pool]) => pool.nodes.map(node => {
+                            const status = nodeStatus(node, maxSessionsPerNode);
+                            return (
+                            <TableRow key={`${id}-${node.hostname}`}>
+                                <TableCell>{pool.name}</TableCell>
+                                <TableCell>{node.hostname}</TableCell>
+                                <TableCell>{formatUsage(node.used.cpu, node.allocatable.cpu, formatCpu)}</TableCell>
+                                <TableCell>{formatUsage(node.used.memory, node.allocatable.memory, formatBytes)}</TableCell>
+                                <TableCell>{formatUsage(node.used.storage, node.allocatable.storage, formatBytes)}</TableCell>
+                                <TableCell>{node.sessions} / {maxSessionsPerNode}</TableCell>
+                                <TableCell>
+                                    <Typography color={status == "Ready" ? "textPrimary" : "error"}>{status}</Typography>
+                                </TableCell>
+                            </TableRow>
+                            );
+                        }))}
+                        </TableBody>
+                    </Table>
+                </TableContainer>
             </>
             )}
         </Resources>
@@ -1022,7 +1058,7 @@ export function AdminPanel({ client, user, conf }: { client: Client, user: Logge
                 ? <Users client={client} user={user} conf={conf} />
                 : value == 3
                 ? <Sessions client={client} conf={conf} user={user} />
-                : <Pools client={client} user={user} />}
+                : <Pools client={client} user={user} conf={conf} />}
             </Paper>
         </CenteredContainer>
     );
@@ -1,4 +1,4 @@
-import { LoggedUser } from "@substrate/playground-client";
+import { LoggedUser, Permission } from "@substrate/playground-client";
 
 function timeout<T>(promise: Promise<T>, ms: number): Promise<T> {
     return new Promise(function(resolve, reject) {
@@ -28,22 +28,22 @@ export function formatDuration(s: number): string {
 
 // User helpers
 
-export function isParitytechMember(user: LoggedUser): boolean {
-    return user.organizations.indexOf('paritytech') != -1;
+export function hasPermission(user: LoggedUser, permission: Permission): boolean {
+    return user.permissions.indexOf(permission) != -1;
 }
 
 export function canCustomizeDuration(user: LoggedUser): boolean {
-    return user.admin || user.canCustomizeDuration || isParitytechMember(user);
+    return hasPermission(user, 'CustomizeDuration');
 }
 
 export function canCustomizePoolAffinity(user: LoggedUser): boolean {
-    return user.admin || user.canCustomizePoolAffinity || isParitytechMember(user);
+    return hasPermission(user, 'CustomizePoolAffinity');
 }
 
 export function hasAdminReadRights(user: LoggedUser): boolean {
-    return user.admin || isParitytechMember(user);
+    return hasPermission(user, 'SessionReadAny') || hasPermission(user, 'UserRead') || hasPermission(user, 'PoolRead');
 }
 
 export function hasAdminEditRights(user: LoggedUser): boolean {
-    return user.admin;
+    return hasPermission(user, 'SessionWriteAny') || hasPermission(user, 'UserEdit');
 }